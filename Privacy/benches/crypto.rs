@@ -0,0 +1,71 @@
+// Operations per second for the core crypto paths, without the GUI
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use digital_signature_system::{KeyConfig, KeyScheme, SignatureSystem, SUPPORTED_RSA_BITS};
+use std::time::Duration;
+
+const MESSAGE: &str = "gg, rematch at the usual server?";
+
+fn system_for(scheme: KeyScheme) -> SignatureSystem {
+    let mut system = SignatureSystem::default();
+    system.key_scheme = scheme;
+    system.create_user("alice".to_string()).expect("create user");
+    system.create_user("bob".to_string()).expect("create user");
+    system
+}
+
+fn create_user(c: &mut Criterion) {
+    let mut group = c.benchmark_group("create_user");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("x25519", |b| {
+        let mut system = SignatureSystem::default();
+        b.iter(|| system.create_user("alice".to_string()).expect("create user"));
+    });
+
+    // RSA needs a prime search where X25519 is one scalar multiplication, so expect x25519 to
+    // come out well over an order of magnitude ahead. A 4096-bit key takes seconds, so take
+    // the fewest samples Criterion allows
+    group.sample_size(10).measurement_time(Duration::from_secs(30));
+    for rsa_bits in SUPPORTED_RSA_BITS {
+        group.bench_function(format!("rsa-{}", rsa_bits), |b| {
+            let mut system = SignatureSystem::default();
+            system.key_scheme = KeyScheme::Rsa;
+            system.key_config = KeyConfig { rsa_bits };
+            b.iter(|| system.create_user("alice".to_string()).expect("create user"));
+        });
+    }
+    group.finish();
+}
+
+fn encrypt_message(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encrypt_message");
+    group.throughput(Throughput::Elements(1));
+    for (name, scheme) in [("x25519", KeyScheme::X25519), ("rsa", KeyScheme::Rsa)] {
+        let system = system_for(scheme);
+        let (alice, bob) = (&system.users["alice"], system.users["bob"].contact());
+        group.bench_function(name, |b| {
+            b.iter(|| system.encrypt_message(alice, &bob, MESSAGE).expect("encrypt"));
+        });
+    }
+    group.finish();
+}
+
+fn decrypt_message(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decrypt_message");
+    group.throughput(Throughput::Elements(1));
+    for (name, scheme) in [("x25519", KeyScheme::X25519), ("rsa", KeyScheme::Rsa)] {
+        let system = system_for(scheme);
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        // Replay protection refuses a message read twice, so every iteration gets a fresh one
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || system.encrypt_message(alice, &bob.contact(), MESSAGE).expect("encrypt"),
+                |message| system.decrypt_message(bob, &message).expect("decrypt"),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, create_user, encrypt_message, decrypt_message);
+criterion_main!(benches);
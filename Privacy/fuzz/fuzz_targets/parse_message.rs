@@ -0,0 +1,28 @@
+#![no_main]
+
+// Untrusted bytes through every message parser: the binary wire format, then the same bytes as
+// armored or base64 text. Any of them may refuse the input, but none may panic.
+// Run with `cargo fuzz run parse_message` from this directory; corpus/ holds valid messages.
+use digital_signature_system::{armor, dearmor, EncryptedMessage};
+use libfuzzer_sys::fuzz_target;
+
+// Small enough that declared field lengths routinely overrun it
+const SMALL_LIMIT: usize = 256;
+
+fuzz_target!(|data: &[u8]| {
+    // Whatever parses must serialize to something that parses again
+    if let Ok(message) = EncryptedMessage::from_wire(data) {
+        assert!(EncryptedMessage::from_wire(&message.to_wire()).is_ok());
+    }
+    let _ = EncryptedMessage::from_wire_limited(data, SMALL_LIMIT);
+
+    // Armoring any bytes and taking the armor off gives them back
+    assert_eq!(dearmor(&armor(data)).ok().as_deref(), Some(data));
+
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = dearmor(text);
+        let _ = EncryptedMessage::from_armored(text);
+        let _ = EncryptedMessage::from_armored_limited(text, SMALL_LIMIT);
+        let _ = EncryptedMessage::from_base64(text);
+    }
+});
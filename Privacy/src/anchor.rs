@@ -0,0 +1,338 @@
+use crate::error::AnchorError;
+use crate::keystore::write_atomic;
+use crate::message::EncryptedMessage;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use web_time::Instant;
+
+// Domain separation for on-chain message commitments
+const ANCHOR_CONTEXT: &[u8] = b"pgfi-anchor-v1";
+
+// Where a commitment landed on the L2 chain
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnchorReceipt {
+    pub commitment: [u8; 32],            // SHA-256 over ciphertext, signature and timestamp
+    pub tx_hash: String,
+    pub block_height: u64,
+}
+
+// Chain access used by ChainAnchor; implement against a node RPC or a test double
+pub trait AnchorClient {
+    // Publish a commitment, returning (tx hash, block height) on inclusion
+    fn submit_commitment(&self, commitment: [u8; 32]) -> Result<(String, u64), AnchorError>;
+}
+
+// Commits encrypted messages to the chain so later tampering is evident
+pub struct ChainAnchor<C: AnchorClient> {
+    client: C,
+}
+
+// Commitment over the parts of a message an auditor can see without decrypting it
+pub fn message_commitment(message: &EncryptedMessage) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(ANCHOR_CONTEXT);
+    hasher.update((message.encrypted_data.len() as u64).to_be_bytes());
+    hasher.update(&message.encrypted_data);
+    hasher.update(message.signature.to_bytes());
+    hasher.update(message.timestamp.to_be_bytes());
+    hasher.finalize().into()
+}
+
+impl<C: AnchorClient> ChainAnchor<C> {
+    pub fn new(client: C) -> Self {
+        Self { client }
+    }
+
+    // Submit the message commitment and return where it was included
+    pub fn anchor_message(&self, message: &EncryptedMessage) -> Result<AnchorReceipt, AnchorError> {
+        self.anchor_commitment(message_commitment(message))
+    }
+
+    fn anchor_commitment(&self, commitment: [u8; 32]) -> Result<AnchorReceipt, AnchorError> {
+        let (tx_hash, block_height) = self.client.submit_commitment(commitment)?;
+        Ok(AnchorReceipt {
+            commitment,
+            tx_hash,
+            block_height,
+        })
+    }
+
+    // True if the message is byte-for-byte what was anchored
+    pub fn verify_anchor(&self, receipt: &AnchorReceipt, message: &EncryptedMessage) -> bool {
+        receipt.commitment == message_commitment(message)
+    }
+}
+
+// How AnchorQueue waits out an unreachable chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub initial_backoff: Duration,       // Wait after the first failure, doubled after each one that follows
+    pub max_backoff: Duration,
+    pub capacity: usize,                 // Pending anchors held before anchor_message refuses more
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+            capacity: 1024,
+        }
+    }
+}
+
+// Anchors messages without waiting on the chain. Commitments queue up, optionally on disk so they
+// survive a restart, and `poll` submits them in order, backing off while submissions fail.
+pub struct AnchorQueue<C: AnchorClient> {
+    anchor: ChainAnchor<C>,
+    policy: RetryPolicy,
+    pending: VecDeque<[u8; 32]>,
+    path: Option<PathBuf>,               // One hex commitment per line
+    failures: u32,                       // Consecutive failed submissions
+    next_attempt: Option<Instant>,       // None while the chain is reachable
+}
+
+fn parse_commitment(line: &str) -> Option<[u8; 32]> {
+    let mut commitment = [0u8; 32];
+    if line.len() != 64 || !line.is_ascii() {
+        return None;
+    }
+    for (byte, pair) in commitment.iter_mut().zip(line.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(commitment)
+}
+
+impl<C: AnchorClient> AnchorQueue<C> {
+    // Queue held only in memory
+    pub fn new(client: C, policy: RetryPolicy) -> Self {
+        Self {
+            anchor: ChainAnchor::new(client),
+            policy,
+            pending: VecDeque::new(),
+            path: None,
+            failures: 0,
+            next_attempt: None,
+        }
+    }
+
+    // Queue saved to `path` after every change, picking up anchors left pending by an earlier run
+    pub fn persistent(client: C, policy: RetryPolicy, path: impl Into<PathBuf>) -> Result<Self, AnchorError> {
+        let path = path.into();
+        let mut queue = Self::new(client, policy);
+        match fs::read_to_string(&path) {
+            Ok(saved) => {
+                for line in saved.lines() {
+                    let commitment = parse_commitment(line).ok_or_else(|| AnchorError::Io(format!("corrupt entry in {}", path.display())))?;
+                    queue.pending.push_back(commitment);
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(AnchorError::Io(err.to_string())),
+        }
+        queue.path = Some(path);
+        Ok(queue)
+    }
+
+    // Queue the message's commitment and return it; the receipt arrives from a later `poll`
+    pub fn anchor_message(&mut self, message: &EncryptedMessage) -> Result<[u8; 32], AnchorError> {
+        if self.pending.len() >= self.policy.capacity {
+            return Err(AnchorError::QueueFull);
+        }
+        let commitment = message_commitment(message);
+        self.pending.push_back(commitment);
+        // Not accepted unless it would survive a restart
+        if let Err(err) = self.persist() {
+            self.pending.pop_back();
+            return Err(err);
+        }
+        Ok(commitment)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Submit what is due, oldest first, and return the receipts of anchors that landed
+    pub fn poll(&mut self) -> Vec<AnchorReceipt> {
+        self.poll_at(Instant::now())
+    }
+
+    fn poll_at(&mut self, now: Instant) -> Vec<AnchorReceipt> {
+        if self.next_attempt.is_some_and(|next| now < next) {
+            return Vec::new();
+        }
+        let mut landed = Vec::new();
+        while let Some(&commitment) = self.pending.front() {
+            match self.anchor.anchor_commitment(commitment) {
+                Ok(receipt) => {
+                    self.pending.pop_front();
+                    self.failures = 0;
+                    self.next_attempt = None;
+                    landed.push(receipt);
+                }
+                Err(_) => {
+                    let backoff = self.policy.initial_backoff.saturating_mul(1 << self.failures.min(30));
+                    self.failures += 1;
+                    self.next_attempt = Some(now + backoff.min(self.policy.max_backoff));
+                    break;
+                }
+            }
+        }
+        // Best effort: if this fails, landed anchors are submitted again after a restart, which only anchors them twice
+        if !landed.is_empty() {
+            let _ = self.persist();
+        }
+        landed
+    }
+
+    pub fn verify_anchor(&self, receipt: &AnchorReceipt, message: &EncryptedMessage) -> bool {
+        self.anchor.verify_anchor(receipt, message)
+    }
+
+    fn persist(&self) -> Result<(), AnchorError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let saved: String = self
+            .pending
+            .iter()
+            .map(|commitment| commitment.iter().map(|byte| format!("{:02x}", byte)).collect::<String>() + "\n")
+            .collect();
+        write_atomic(path, |file| file.write_all(saved.as_bytes())).map_err(|err| AnchorError::Io(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SignatureSystem;
+    use std::cell::{Cell, RefCell};
+
+    // Records commitments in memory, one per block
+    #[derive(Default)]
+    struct MockClient {
+        blocks: RefCell<Vec<[u8; 32]>>,
+    }
+
+    impl AnchorClient for MockClient {
+        fn submit_commitment(&self, commitment: [u8; 32]) -> Result<(String, u64), AnchorError> {
+            let mut blocks = self.blocks.borrow_mut();
+            blocks.push(commitment);
+            let tx_hash = commitment.iter().map(|byte| format!("{:02x}", byte)).collect();
+            Ok((tx_hash, blocks.len() as u64))
+        }
+    }
+
+    // Refuses the first `outages` submissions, as a node that is still coming back would
+    struct FlakyClient {
+        outages: Cell<u32>,
+        attempts: Cell<u32>,
+        inner: MockClient,
+    }
+
+    impl FlakyClient {
+        fn new(outages: u32) -> Self {
+            Self {
+                outages: Cell::new(outages),
+                attempts: Cell::new(0),
+                inner: MockClient::default(),
+            }
+        }
+    }
+
+    impl AnchorClient for FlakyClient {
+        fn submit_commitment(&self, commitment: [u8; 32]) -> Result<(String, u64), AnchorError> {
+            self.attempts.set(self.attempts.get() + 1);
+            if self.outages.get() > 0 {
+                self.outages.set(self.outages.get() - 1);
+                return Err(AnchorError::Submission("connection refused".to_string()));
+            }
+            self.inner.submit_commitment(commitment)
+        }
+    }
+
+    fn sample_message() -> EncryptedMessage {
+        let mut system = SignatureSystem::default();
+        system.create_user("alice".to_string()).expect("create user");
+        system.create_user("bob".to_string()).expect("create user");
+        system
+            .encrypt_message(&system.users["alice"], &system.users["bob"].contact(), "loot split 50/50")
+            .expect("encrypt")
+    }
+
+    #[test]
+    fn anchored_message_verifies() {
+        let anchor = ChainAnchor::new(MockClient::default());
+        let message = sample_message();
+
+        let receipt = anchor.anchor_message(&message).expect("anchor");
+        assert_eq!(receipt.block_height, 1);
+        assert_eq!(anchor.client.blocks.borrow()[0], receipt.commitment);
+        assert!(anchor.verify_anchor(&receipt, &message));
+    }
+
+    #[test]
+    fn modified_ciphertext_fails_verification() {
+        let anchor = ChainAnchor::new(MockClient::default());
+        let mut message = sample_message();
+        let receipt = anchor.anchor_message(&message).expect("anchor");
+
+        message.encrypted_data[0] ^= 0x01;
+        assert!(!anchor.verify_anchor(&receipt, &message));
+    }
+
+    #[test]
+    fn queued_anchor_lands_after_retries() {
+        let mut queue = AnchorQueue::new(FlakyClient::new(2), RetryPolicy::default());
+        let message = sample_message();
+        let commitment = queue.anchor_message(&message).expect("enqueue");
+        assert_eq!(queue.anchor.client.attempts.get(), 0);
+
+        let start = Instant::now();
+        assert!(queue.poll_at(start).is_empty());
+        // Backing off: nothing is tried again until a second has passed, then two more
+        assert!(queue.poll_at(start + Duration::from_millis(500)).is_empty());
+        assert_eq!(queue.anchor.client.attempts.get(), 1);
+        assert!(queue.poll_at(start + Duration::from_secs(1)).is_empty());
+        assert!(queue.poll_at(start + Duration::from_secs(2)).is_empty());
+        assert_eq!(queue.anchor.client.attempts.get(), 2);
+
+        let landed = queue.poll_at(start + Duration::from_secs(3));
+        assert_eq!(landed.len(), 1);
+        assert_eq!(landed[0].commitment, commitment);
+        assert!(queue.verify_anchor(&landed[0], &message));
+        assert_eq!(queue.pending(), 0);
+    }
+
+    #[test]
+    fn pending_anchors_survive_restart() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("anchors.pending");
+        let message = sample_message();
+
+        let mut offline = AnchorQueue::persistent(FlakyClient::new(u32::MAX), RetryPolicy::default(), &path).expect("open");
+        offline.anchor_message(&message).expect("enqueue");
+        assert!(offline.poll().is_empty());
+        drop(offline);
+
+        let mut online = AnchorQueue::persistent(MockClient::default(), RetryPolicy::default(), &path).expect("open");
+        assert_eq!(online.pending(), 1);
+        let landed = online.poll();
+        assert!(online.verify_anchor(&landed[0], &message));
+        assert_eq!(AnchorQueue::persistent(MockClient::default(), RetryPolicy::default(), &path).expect("open").pending(), 0);
+    }
+
+    #[test]
+    fn full_queue_refuses_without_blocking() {
+        let policy = RetryPolicy { capacity: 1, ..RetryPolicy::default() };
+        let mut queue = AnchorQueue::new(FlakyClient::new(u32::MAX), policy);
+        let message = sample_message();
+        queue.anchor_message(&message).expect("enqueue");
+        assert_eq!(queue.anchor_message(&message), Err(AnchorError::QueueFull));
+    }
+}
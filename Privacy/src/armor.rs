@@ -0,0 +1,125 @@
+use crate::error::{ArmorError, WireError};
+use crate::message::{EncryptedMessage, DEFAULT_MAX_MESSAGE_BYTES, WIRE_OVERHEAD};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+
+const BEGIN: &str = "-----BEGIN PGFI MESSAGE-----";
+const END: &str = "-----END PGFI MESSAGE-----";
+const LINE_LEN: usize = 64;              // Base64 characters per body line
+
+// OpenPGP's CRC-24 (RFC 4880 section 6.1)
+const CRC24_INIT: u32 = 0xb7_04ce;
+const CRC24_POLY: u32 = 0x186_4cfb;
+
+fn crc24(bytes: &[u8]) -> [u8; 3] {
+    let mut crc = CRC24_INIT;
+    for &byte in bytes {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+    let [_, high, mid, low] = crc.to_be_bytes();
+    [high, mid, low]
+}
+
+// Wire bytes as base64 lines between BEGIN and END markers, closed by "=" and the base64 CRC-24.
+// The checksum is what catches a paste that lost or changed characters, before the bytes are parsed.
+pub fn armor(wire: &[u8]) -> String {
+    let body = BASE64.encode(wire);
+    let mut text = format!("{}\n", BEGIN);
+    for line in body.as_bytes().chunks(LINE_LEN) {
+        text.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        text.push('\n');
+    }
+    text.push_str(&format!("={}\n{}\n", BASE64.encode(crc24(wire)), END));
+    text
+}
+
+// Undo armor. Text around the markers and whitespace anywhere inside them, such as line breaks a
+// chat client rewrapped, are ignored.
+pub fn dearmor(text: &str) -> Result<Vec<u8>, ArmorError> {
+    let start = text.find(BEGIN).ok_or(ArmorError::MissingHeader)? + BEGIN.len();
+    let len = text[start..].find(END).ok_or(ArmorError::MissingFooter)?;
+    let inner: String = text[start..start + len].split_whitespace().collect();
+
+    // The checksum is always "=" and four characters, as three bytes need no padding
+    let split = inner
+        .len()
+        .checked_sub(5)
+        .filter(|&split| inner.as_bytes()[split] == b'=')
+        .ok_or(ArmorError::MissingChecksum)?;
+    let wire = BASE64.decode(&inner[..split]).map_err(|_| ArmorError::InvalidBase64)?;
+    let checksum = BASE64.decode(&inner[split + 1..]).map_err(|_| ArmorError::InvalidBase64)?;
+    if checksum != crc24(&wire) {
+        return Err(ArmorError::BadChecksum);
+    }
+    Ok(wire)
+}
+
+impl EncryptedMessage {
+    // to_base64, armored for pasting into chat
+    pub fn to_armored(&self) -> String {
+        armor(&self.to_wire())
+    }
+
+    pub fn from_armored(text: &str) -> Result<Self, ArmorError> {
+        Self::from_armored_limited(text, DEFAULT_MAX_MESSAGE_BYTES)
+    }
+
+    // from_armored with from_wire_limited's bound, checked before decoding as from_base64_limited does
+    pub fn from_armored_limited(text: &str, max_message_bytes: usize) -> Result<Self, ArmorError> {
+        if text.len() / 4 * 3 > max_message_bytes.saturating_add(WIRE_OVERHEAD).saturating_add(LINE_LEN) {
+            return Err(WireError::TooLarge(max_message_bytes).into());
+        }
+        Ok(Self::from_wire_limited(&dearmor(text)?, max_message_bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SignatureSystem;
+
+    #[test]
+    fn crc24_matches_openpgp() {
+        // Check value of the CRC-24/OPENPGP catalogue entry
+        assert_eq!(crc24(b"123456789"), [0x21, 0xcf, 0x02]);
+    }
+
+    #[test]
+    fn armored_message_survives_rewrapping() {
+        let mut system = SignatureSystem::default();
+        for name in ["alice", "bob"] {
+            system.create_user(name.to_string()).expect("create user");
+        }
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let message = system.encrypt_message(alice, bob, "meet at the portal").expect("encrypt");
+        let armored = message.to_armored();
+        assert!(armored.starts_with(BEGIN) && armored.trim_end().ends_with(END));
+        assert!(armored.lines().all(|line| line.len() <= LINE_LEN));
+
+        // A chat client that indents, rewraps and adds chatter around it changes nothing
+        let pasted = format!("here you go:\n  {}\nttyl", armored.replace('\n', "\n   ").replacen("\n", " ", 3));
+        let received = EncryptedMessage::from_armored(&pasted).expect("dearmor");
+        assert_eq!(system.decrypt_message(bob, &received).expect("decrypt"), "meet at the portal");
+    }
+
+    #[test]
+    fn corrupted_body_fails_checksum() {
+        let armored = armor(b"gg, rematch?");
+        let body_start = armored.find('\n').expect("header line") + 1;
+
+        // Swap one body character for another valid base64 character
+        let mut corrupted = armored.clone().into_bytes();
+        corrupted[body_start] = if corrupted[body_start] == b'A' { b'B' } else { b'A' };
+        assert_eq!(dearmor(std::str::from_utf8(&corrupted).expect("ascii")), Err(ArmorError::BadChecksum));
+
+        // Cut-off pastes and armor without a checksum are refused before any decoding
+        assert_eq!(dearmor(&armored[..armored.len() - END.len() - 2]), Err(ArmorError::MissingFooter));
+        assert_eq!(dearmor("gg"), Err(ArmorError::MissingHeader));
+        assert_eq!(dearmor(&armored.replace("\n=", "\n")), Err(ArmorError::MissingChecksum));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::ct::ct_eq;
+use crate::error::{CryptoError, DecryptError};
+use crate::keys::KeyId;
+use crate::message::{push_field, EncryptedMessage};
+use crate::selftest::hex;
+use crate::signing::{MessageSignature, SignatureAlgorithm, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Domain separation for audit entry hashes and the signatures over them
+const AUDIT_CONTEXT: &[u8] = b"pgfi-audit-v1";
+const AUDIT_SIGNATURE_CONTEXT: &[u8] = b"pgfi-audit-signature-v1";
+
+// Outcome recorded for a successful decrypt
+const OUTCOME_OK: &str = "ok";
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// One decrypt attempt. `hash` covers every other field including `prev_hash`, and `signature`
+// covers `hash`, so entries can't be edited, reordered or dropped without breaking the chain,
+// and a chain recomputed after doing so isn't signed by the system's audit key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub sequence: u64,                   // Position in the log, starting at 0
+    pub timestamp: u64,                  // Unix millis of the attempt
+    pub username: String,                // Who tried to read the message
+    pub key_id: String,                  // Hex key id of their current encryption key
+    pub message_id: String,              // Hex EncryptedMessage::message_id
+    pub outcome: String,                 // "ok" or the DecryptError message
+    pub prev_hash: String,               // Hex hash of the previous entry, zeros for the first
+    pub hash: String,
+    pub signature: String,               // Hex signature over `hash` by the audit key
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let mut bytes = AUDIT_CONTEXT.to_vec();
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        for field in [&self.username, &self.key_id, &self.message_id, &self.outcome, &self.prev_hash] {
+            push_field(&mut bytes, field.as_bytes());
+        }
+        to_hex(&Sha256::digest(bytes))
+    }
+}
+
+fn signed_bytes(hash: &str) -> Vec<u8> {
+    [AUDIT_SIGNATURE_CONTEXT, hash.as_bytes()].concat()
+}
+
+// The key a SignatureSystem signs its audit entries with, fresh for each system. Verifiers need
+// its public half, SignatureSystem::audit_key, to tell the log from one rebuilt without an entry.
+pub(crate) struct AuditKey(SigningKey);
+
+impl AuditKey {
+    pub(crate) fn public(&self) -> VerifyingKey {
+        self.0.public()
+    }
+}
+
+impl Default for AuditKey {
+    fn default() -> Self {
+        Self(SigningKey::generate(SignatureAlgorithm::Ed25519, &mut OsRng).expect("any 32 bytes are an Ed25519 secret"))
+    }
+}
+
+// Append-only, hash-chained and signed record of decrypt attempts. Publishing or anchoring
+// `head` now and then also makes it evident if entries are cut off the end.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub(crate) fn record<T>(&mut self, key: &AuditKey, timestamp: u64, username: &str, key_id: &KeyId, message: &EncryptedMessage, result: &Result<T, DecryptError>) {
+        let mut entry = AuditEntry {
+            sequence: self.entries.len() as u64,
+            timestamp,
+            username: username.to_string(),
+            key_id: to_hex(key_id),
+            message_id: to_hex(&message.message_id()),
+            outcome: match result {
+                Ok(_) => OUTCOME_OK.to_string(),
+                Err(err) => err.to_string(),
+            },
+            prev_hash: self.head(),
+            hash: String::new(),
+            signature: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        entry.signature = to_hex(&key.0.sign(&signed_bytes(&entry.hash)).to_bytes());
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    // Hash of the newest entry, zeros while the log is empty
+    pub fn head(&self) -> String {
+        self.entries
+            .last()
+            .map(|entry| entry.hash.clone())
+            .unwrap_or_else(|| to_hex(&[0u8; 32]))
+    }
+
+    // True if every entry is intact, in sequence, linked to the one before and signed by `key`,
+    // the SignatureSystem::audit_key of the system that wrote the log
+    pub fn verify(&self, key: &VerifyingKey) -> bool {
+        let mut prev_hash = to_hex(&[0u8; 32]);
+        for (sequence, entry) in self.entries.iter().enumerate() {
+            if entry.sequence != sequence as u64 || !ct_eq(entry.prev_hash.as_bytes(), prev_hash.as_bytes()) || !ct_eq(entry.hash.as_bytes(), entry.compute_hash().as_bytes()) {
+                return false;
+            }
+            let signed = MessageSignature::from_bytes(key.algorithm(), &hex(&entry.signature))
+                .is_some_and(|signature| key.verify(&signed_bytes(&entry.hash), &signature).is_ok());
+            if !signed {
+                return false;
+            }
+            prev_hash = entry.hash.clone();
+        }
+        true
+    }
+
+    // One JSON object per line, oldest first
+    pub fn to_jsonl(&self) -> Result<String, CryptoError> {
+        let mut jsonl = String::new();
+        for entry in &self.entries {
+            jsonl.push_str(&serde_json::to_string(entry).map_err(|err| CryptoError::Serialization(err.to_string()))?);
+            jsonl.push('\n');
+        }
+        Ok(jsonl)
+    }
+
+    // Parse to_jsonl output; call verify before trusting it
+    pub fn from_jsonl(jsonl: &str) -> Result<Self, CryptoError> {
+        let entries = jsonl
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|err| CryptoError::Serialization(err.to_string())))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::{system_with_users, SignatureSystem};
+
+    #[test]
+    fn decrypt_attempts_form_tamper_evident_chain() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let (alice, bob, carol) = (&system.users["alice"], &system.users["bob"], &system.users["carol"]);
+        let encrypted = system.encrypt_message(alice, bob, "loot is in the chest").expect("encrypt");
+
+        system.decrypt_message(bob, &encrypted).expect("decrypt");
+        assert_eq!(system.decrypt_message(carol, &encrypted), Err(DecryptError::WrongRecipient));
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::NonceReused));
+
+        let log = system.audit_log().clone();
+        let entries = log.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries.iter().map(|entry| entry.username.as_str()).collect::<Vec<_>>(),
+            ["bob", "carol", "bob"]
+        );
+        assert_eq!(entries[0].outcome, "ok");
+        assert_eq!(entries[1].outcome, DecryptError::WrongRecipient.to_string());
+        assert_eq!(entries[1].key_id, to_hex(&carol.encryption_key.key_id()));
+        assert!(entries.iter().all(|entry| entry.message_id == to_hex(&encrypted.message_id())));
+        assert_eq!(entries[1].prev_hash, entries[0].hash);
+        assert_eq!(entries[2].prev_hash, entries[1].hash);
+        let key = system.audit_key();
+        assert!(log.verify(&key));
+        assert!(!log.verify(&SignatureSystem::default().audit_key()));
+
+        // Survives export, but not edits or a dropped line
+        let jsonl = log.to_jsonl().expect("to_jsonl");
+        assert_eq!(AuditLog::from_jsonl(&jsonl).expect("from_jsonl"), log);
+        let edited = jsonl.replacen("\"carol\"", "\"dave\"", 1);
+        assert!(!AuditLog::from_jsonl(&edited).expect("from_jsonl").verify(&key));
+        let dropped: String = jsonl.lines().enumerate().filter(|(i, _)| *i != 1).map(|(_, line)| format!("{}\n", line)).collect();
+        assert!(!AuditLog::from_jsonl(&dropped).expect("from_jsonl").verify(&key));
+    }
+
+    // Renumber and relink entries the way someone covering their tracks would
+    fn rechain(mut entries: Vec<AuditEntry>) -> AuditLog {
+        let mut prev_hash = to_hex(&[0u8; 32]);
+        for (sequence, entry) in entries.iter_mut().enumerate() {
+            entry.sequence = sequence as u64;
+            entry.prev_hash = prev_hash;
+            entry.hash = entry.compute_hash();
+            prev_hash = entry.hash.clone();
+        }
+        AuditLog { entries }
+    }
+
+    #[test]
+    fn rechained_log_fails_signature_check() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let (alice, bob, carol) = (&system.users["alice"], &system.users["bob"], &system.users["carol"]);
+        let encrypted = system.encrypt_message(alice, bob, "loot is in the chest").expect("encrypt");
+        assert_eq!(system.decrypt_message(carol, &encrypted), Err(DecryptError::WrongRecipient));
+        system.decrypt_message(bob, &encrypted).expect("decrypt");
+        assert_eq!(system.decrypt_message(carol, &encrypted), Err(DecryptError::WrongRecipient));
+
+        let key = system.audit_key();
+        let entries = system.audit_log().entries().to_vec();
+        assert!(rechain(entries.clone()).verify(&key));
+
+        // Dropping or reordering an entry changes the hashes after it, and the signatures don't follow
+        let dropped = rechain(entries.iter().filter(|entry| entry.sequence != 0).cloned().collect());
+        assert!(!dropped.verify(&key));
+        let reordered = rechain(vec![entries[1].clone(), entries[0].clone(), entries[2].clone()]);
+        assert!(!reordered.verify(&key));
+
+        // Nor does signing the recomputed chain with some other key help
+        let forger = AuditKey::default();
+        let mut forged = dropped.entries;
+        for entry in &mut forged {
+            entry.signature = to_hex(&forger.0.sign(&signed_bytes(&entry.hash)).to_bytes());
+        }
+        assert!(!AuditLog { entries: forged }.verify(&key));
+    }
+}
@@ -0,0 +1,176 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use digital_signature_system::{keystore, run_self_test, EncryptedMessage, KeyConfig, KeyScheme, KeystoreFile, SignatureSystem, User};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+// Keystore passphrase is read from the environment so it never shows up in `ps`
+const PASSPHRASE_VAR: &str = "PRIVACY_PASSPHRASE";
+const DEFAULT_KEYSTORE: &str = "keystore.bin";
+
+const USAGE: &str = "usage: privacy-cli [--keystore <path>] <command>
+
+commands:
+  gen-user <name> [--scheme x25519|rsa|hybrid] [--rsa-bits 2048|3072|4096]
+                                             create a user and add it to the keystore
+  encrypt --from <user> --to <user> --message <text>
+                                             print the encrypted message as base64
+  decrypt --as <user> [--input <file|->]     decrypt a base64 message (default: stdin)
+  self-test                                  check the crypto primitives against known answers
+
+The keystore passphrase is read from $PRIVACY_PASSPHRASE.";
+
+// Parsed `--flag value` pairs following the subcommand
+struct Flags(HashMap<String, String>);
+
+impl Flags {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut flags = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument '{}'", arg))?;
+            let value = args.next().ok_or_else(|| format!("missing value for --{}", name))?;
+            flags.insert(name.to_string(), value.clone());
+        }
+        Ok(Self(flags))
+    }
+
+    fn required(&self, name: &str) -> Result<&str, String> {
+        self.optional(name).ok_or_else(|| format!("missing --{}", name))
+    }
+
+    fn optional(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+// Load every user from the keystore
+fn open_keystore(path: &Path, passphrase: &str) -> Result<SignatureSystem, String> {
+    let mut system = SignatureSystem::default();
+    system.users = keystore::load(path, passphrase)
+        .map_err(|err| format!("could not open keystore {}: {}", path.display(), err))?;
+    Ok(system)
+}
+
+fn gen_user(path: &Path, passphrase: &str, username: &str, flags: &Flags) -> Result<(), String> {
+    let mut keystore = if path.exists() {
+        KeystoreFile::open(path, passphrase)
+    } else {
+        KeystoreFile::create(path, passphrase)
+    }
+    .map_err(|err| format!("could not open keystore {}: {}", path.display(), err))?;
+    let mut system = SignatureSystem::default();
+    system.users = keystore.users().map_err(|err| format!("could not open keystore {}: {}", path.display(), err))?;
+    system.key_scheme = match flags.optional("scheme") {
+        None | Some("x25519") => KeyScheme::X25519,
+        Some("rsa") => KeyScheme::Rsa,
+        Some("hybrid") => KeyScheme::Hybrid,
+        Some(other) => return Err(format!("unknown scheme '{}', expected x25519, rsa or hybrid", other)),
+    };
+    if let Some(rsa_bits) = flags.optional("rsa-bits") {
+        let rsa_bits = rsa_bits.parse().map_err(|_| format!("invalid RSA key size '{}'", rsa_bits))?;
+        system.key_config = KeyConfig { rsa_bits };
+    }
+    if system.users.contains_key(username) {
+        return Err(format!("user {} already exists", username));
+    }
+    system
+        .create_user(username.to_string())
+        .map_err(|err| format!("could not create user: {}", err))?;
+    // Only the new user's record is encrypted; everyone else's is written back as it was
+    keystore
+        .update_user(&system.users[username])
+        .map_err(|err| format!("could not save keystore: {}", err))?;
+
+    println!("{}  [{}]", username, system.users[username].fingerprint());
+    Ok(())
+}
+
+fn encrypt(path: &Path, passphrase: &str, flags: &Flags) -> Result<(), String> {
+    let system = open_keystore(path, passphrase)?;
+    let sender = lookup(&system, flags.required("from")?)?;
+    let recipient = lookup(&system, flags.required("to")?)?;
+
+    let encrypted = system
+        .encrypt_message(sender, &recipient.contact(), flags.required("message")?)
+        .map_err(|err| format!("could not encrypt message: {}", err))?;
+    let json = encrypted.to_json().map_err(|err| err.to_string())?;
+
+    println!("{}", BASE64.encode(json));
+    Ok(())
+}
+
+fn decrypt(path: &Path, passphrase: &str, flags: &Flags) -> Result<(), String> {
+    let system = open_keystore(path, passphrase)?;
+    let recipient = lookup(&system, flags.required("as")?)?;
+
+    let encoded = match flags.optional("input") {
+        None | Some("-") => {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .map_err(|err| format!("could not read stdin: {}", err))?;
+            input
+        }
+        Some(file) => fs::read_to_string(file).map_err(|err| format!("could not read {}: {}", file, err))?,
+    };
+    let json = BASE64
+        .decode(encoded.trim())
+        .map_err(|err| format!("input is not valid base64: {}", err))?;
+    let json = String::from_utf8(json).map_err(|_| "input is not a message".to_string())?;
+    let message = EncryptedMessage::from_json(&json).map_err(|err| err.to_string())?;
+
+    let plaintext = system
+        .decrypt_message(recipient, &message)
+        .map_err(|err| format!("could not decrypt message: {}", err))?;
+    println!("{}", plaintext);
+    Ok(())
+}
+
+fn lookup<'a>(system: &'a SignatureSystem, username: &str) -> Result<&'a User, String> {
+    system
+        .users
+        .get(username)
+        .ok_or_else(|| format!("no user named {} in the keystore", username))
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    // Every command runs this first; a build that fails it must not touch any keys
+    run_self_test().map_err(|err| format!("refusing to run: {}", err))?;
+    if let [command] = args {
+        if command == "self-test" {
+            println!("self-test passed");
+            return Ok(());
+        }
+    }
+
+    let (keystore_path, args) = match args {
+        [flag, path, rest @ ..] if flag == "--keystore" => (PathBuf::from(path), rest),
+        rest => (PathBuf::from(DEFAULT_KEYSTORE), rest),
+    };
+    let passphrase = std::env::var(PASSPHRASE_VAR).map_err(|_| format!("${} is not set", PASSPHRASE_VAR))?;
+
+    match args {
+        [command, username, rest @ ..] if command == "gen-user" => {
+            gen_user(&keystore_path, &passphrase, username, &Flags::parse(rest)?)
+        }
+        [command, rest @ ..] if command == "encrypt" => encrypt(&keystore_path, &passphrase, &Flags::parse(rest)?),
+        [command, rest @ ..] if command == "decrypt" => decrypt(&keystore_path, &passphrase, &Flags::parse(rest)?),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::error::CryptoError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+// Message keys of burn-after-read messages already opened once. The nonce log forgets messages
+// once they're too old to replay, so this set is kept separately and must be persisted with
+// to_json; a restart that loses it makes every burned message readable again.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BurnedSet {
+    keys: BTreeSet<[u8; 32]>,            // Digest of each message key, scoped to the recipient key it was read with
+}
+
+impl BurnedSet {
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub(crate) fn contains(&self, key_id: &[u8; 32]) -> bool {
+        self.keys.contains(key_id)
+    }
+
+    pub(crate) fn insert(&mut self, key_id: [u8; 32]) {
+        self.keys.insert(key_id);
+    }
+
+    // Merge sets, e.g. one restored from disk into the running system's
+    pub fn extend(&mut self, other: BurnedSet) {
+        self.keys.extend(other.keys);
+    }
+
+    pub fn to_json(&self) -> Result<String, CryptoError> {
+        serde_json::to_string(self).map_err(|err| CryptoError::Serialization(err.to_string()))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, CryptoError> {
+        serde_json::from_str(json).map_err(|err| CryptoError::Serialization(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::system_with_users;
+    use crate::error::DecryptError;
+    use crate::SignatureSystem;
+
+    #[test]
+    fn burned_message_reads_once() {
+        let system = system_with_users(&["alice", "bob"]);
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+
+        let secret = system.encrypt_burn_after_read(alice, bob, "the vault code is 0451").expect("encrypt");
+        assert!(secret.burn_after_read);
+        assert_eq!(system.decrypt_message(bob, &secret).expect("decrypt"), "the vault code is 0451");
+        assert_eq!(system.decrypt_message(bob, &secret), Err(DecryptError::AlreadyRead));
+        assert_eq!(system.burned().len(), 1);
+
+        // Ordinary messages don't touch the set
+        let plain = system.encrypt_message(alice, bob, "gg").expect("encrypt");
+        assert_eq!(system.decrypt_message(bob, &plain).expect("decrypt"), "gg");
+        assert_eq!(system.burned().len(), 1);
+    }
+
+    #[test]
+    fn restored_set_still_refuses_burned_message() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        let secret = system
+            .encrypt_burn_after_read(&system.users["alice"], &system.users["bob"], "once only")
+            .expect("encrypt");
+        system.decrypt_message(&system.users["bob"], &secret).expect("decrypt");
+        let saved = system.burned().to_json().expect("to_json");
+
+        // A restart forgets the nonce log but not the restored burned set
+        let mut restarted = SignatureSystem::default();
+        restarted.users = std::mem::take(&mut system.users);
+        restarted.burned().extend(BurnedSet::from_json(&saved).expect("from_json"));
+        assert_eq!(restarted.decrypt_message(&restarted.users["bob"], &secret), Err(DecryptError::AlreadyRead));
+    }
+}
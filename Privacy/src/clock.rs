@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use web_time::{SystemTime, UNIX_EPOCH};
+
+// Where send times, freshness checks and history expiry get the current time from. Tests hand
+// SignatureSystem and MessagingService a FixedClock or ManualClock instead of waiting in real time.
+pub trait Clock: Send + Sync {
+    // Unix millis
+    fn now_millis(&self) -> u64;
+}
+
+// The wall clock, used unless another one is set
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+// Always the same instant
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now_millis(&self) -> u64 {
+        self.0
+    }
+}
+
+// Stands still until a test moves it; shared through an Arc so the test keeps a handle to it
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(start_millis: u64) -> Self {
+        Self { now: AtomicU64::new(start_millis) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, millis: u64) {
+        self.now.store(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+// The clock a SignatureSystem reads, SystemClock by default. Clones are handles to the same slot,
+// so a MessagingService's history follows every later set_clock on its system.
+#[derive(Clone)]
+pub(crate) struct SharedClock(Arc<RwLock<Arc<dyn Clock>>>);
+
+impl SharedClock {
+    pub(crate) fn set(&self, clock: Arc<dyn Clock>) {
+        *self.0.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = clock;
+    }
+
+    pub(crate) fn now_millis(&self) -> u64 {
+        self.0.read().unwrap_or_else(|poisoned| poisoned.into_inner()).now_millis()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(SystemClock))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_moves_only_when_told() {
+        let clock = ManualClock::new(1_000);
+        assert_eq!(clock.now_millis(), 1_000);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(clock.now_millis(), 3_000);
+        clock.set(500);
+        assert_eq!(clock.now_millis(), 500);
+
+        // Every handle sees a clock set through any one of them
+        let shared = SharedClock::default();
+        let handle = shared.clone();
+        shared.set(Arc::new(FixedClock(42)));
+        assert_eq!(handle.now_millis(), 42);
+    }
+}
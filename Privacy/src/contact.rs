@@ -0,0 +1,563 @@
+use crate::error::ImportError;
+use crate::keys::{EncryptionKey, KemEncapsulationKey, KEM_PUBLIC_LEN};
+use crate::signing::VerifyingKey;
+use crate::user::{key_fingerprint, User};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ed25519_dalek::PublicKey;
+use ml_kem::{Encoded, EncodedSizeUser};
+use rsa::pkcs1::{DecodeRsaPublicKey, EncodeRsaPublicKey};
+use rsa::pkcs8::der::pem::{self, LineEnding};
+use rsa::pkcs8::spki::SubjectPublicKeyInfoRef;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use std::collections::HashMap;
+use x25519_dalek::PublicKey as X25519PublicKey;
+
+// PEM labels used in a public-key bundle
+const SPKI_LABEL: &str = "PUBLIC KEY";
+const RSA_LABEL: &str = "RSA PUBLIC KEY";
+const KEM_LABEL: &str = "MLKEM768 PUBLIC KEY";      // RFC 7468 labels can't contain '-'
+
+// Single-line bundle small enough for a QR code:
+// prefix, then base64(Ed25519 key | X25519 key or RSA PKCS#1 DER, followed by the ML-KEM key for hybrid users)
+const COMPACT_PREFIX: &str = "pgfi1:";
+
+// The only OpenSSH key type whose identity can verify our signatures
+const SSH_ED25519: &str = "ssh-ed25519";
+
+// Safety numbers hash each party's keys this many times so finding a colliding key is expensive
+const SAFETY_NUMBER_ITERATIONS: usize = 5200;
+const SAFETY_NUMBER_VERSION: [u8; 2] = [0, 1];
+
+// DER SubjectPublicKeyInfo headers for Ed25519 (OID 1.3.101.112) and X25519 (OID 1.3.101.110) keys
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+const X25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x6e, 0x03, 0x21, 0x00];
+
+// Public keys of someone we can message but whose private keys we don't hold
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contact {
+    pub signing: VerifyingKey,           // For verifying their signatures
+    pub encryption: EncryptionKey,       // For delivering message keys to them
+}
+
+impl Contact {
+    pub fn fingerprint(&self) -> String {
+        key_fingerprint(&self.signing, &self.encryption)
+    }
+
+    // Same bundle as User::export_public_pem
+    pub fn export_public_pem(&self) -> String {
+        public_pem(&self.signing, &self.encryption)
+    }
+}
+
+// 60 digits two people compare aloud to rule out a man in the middle: 30 derived from each
+// side's keys, lower half first so both ends print the same string whatever the argument order
+pub fn safety_number(a: &Contact, b: &Contact) -> String {
+    let (a, b) = (safety_digits(a), safety_digits(b));
+    if a <= b { a + &b } else { b + &a }
+}
+
+// Thirty digits for one party: six 5-digit groups, each a 40-bit slice of an iterated SHA-512 reduced mod 100000
+fn safety_digits(contact: &Contact) -> String {
+    let mut key = contact.signing.to_bytes();
+    key.extend_from_slice(&contact.encryption.fingerprint_bytes());
+
+    let mut hash = Sha512::new()
+        .chain_update(SAFETY_NUMBER_VERSION)
+        .chain_update(&key)
+        .finalize();
+    for _ in 1..SAFETY_NUMBER_ITERATIONS {
+        hash = Sha512::new().chain_update(hash).chain_update(&key).finalize();
+    }
+    hash[..30]
+        .chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, byte| acc << 8 | u64::from(*byte));
+            format!("{:05}", value % 100_000)
+        })
+        .collect()
+}
+
+// Public keys needed to encrypt to someone, whether or not we hold their private keys
+pub trait RecipientKeys {
+    fn encryption_key(&self) -> &EncryptionKey;
+    fn verifying_key(&self) -> VerifyingKey;
+    fn fingerprint(&self) -> String;
+
+    // Whether message keys go to the signing key itself as KeyExchange::Hpke, see IdentityRecipient
+    fn delivers_to_identity(&self) -> bool {
+        false
+    }
+}
+
+impl RecipientKeys for Contact {
+    fn encryption_key(&self) -> &EncryptionKey {
+        &self.encryption
+    }
+
+    fn verifying_key(&self) -> VerifyingKey {
+        self.signing
+    }
+
+    fn fingerprint(&self) -> String {
+        Contact::fingerprint(self)
+    }
+}
+
+// Someone known only by their Ed25519 identity key. Message keys reach them through
+// KeyExchange::Hpke under the X25519 form of that key, so they need share just the one key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdentityRecipient {
+    signing: VerifyingKey,
+    encryption: EncryptionKey,           // `signing` converted to X25519
+}
+
+impl IdentityRecipient {
+    // InvalidKey for a key that isn't a point on the curve, or one of small order
+    pub fn new(signing: VerifyingKey) -> Result<Self, ImportError> {
+        let encryption = EncryptionKey::from_ed25519(&signing).ok_or(ImportError::InvalidKey)?;
+        Ok(Self { signing, encryption })
+    }
+
+    pub fn fingerprint(&self) -> String {
+        key_fingerprint(&self.signing, &self.encryption)
+    }
+}
+
+impl RecipientKeys for IdentityRecipient {
+    fn encryption_key(&self) -> &EncryptionKey {
+        &self.encryption
+    }
+
+    fn verifying_key(&self) -> VerifyingKey {
+        self.signing
+    }
+
+    fn fingerprint(&self) -> String {
+        IdentityRecipient::fingerprint(self)
+    }
+
+    fn delivers_to_identity(&self) -> bool {
+        true
+    }
+}
+
+impl RecipientKeys for User {
+    fn encryption_key(&self) -> &EncryptionKey {
+        &self.encryption_key
+    }
+
+    fn verifying_key(&self) -> VerifyingKey {
+        self.keypair.public()
+    }
+
+    fn fingerprint(&self) -> String {
+        User::fingerprint(self)
+    }
+}
+
+impl User {
+    // Public half of this user's identity
+    pub fn contact(&self) -> Contact {
+        Contact {
+            signing: self.keypair.public(),
+            encryption: self.encryption_key.clone(),
+        }
+    }
+
+    // Ed25519 key as SPKI "PUBLIC KEY" followed by the encryption key, either another SPKI "PUBLIC KEY" (X25519),
+    // PKCS#1 "RSA PUBLIC KEY", or for hybrid keys the RSA block and a raw "MLKEM768 PUBLIC KEY"
+    pub fn export_public_pem(&self) -> String {
+        public_pem(&self.keypair.public(), &self.encryption_key)
+    }
+
+    // Same keys as export_public_pem without the PEM armour, for QR codes
+    pub fn export_public_compact(&self) -> String {
+        let mut bytes = self.keypair.public().to_bytes();
+        match &self.encryption_key {
+            EncryptionKey::X25519(public) => bytes.extend_from_slice(public.as_bytes()),
+            EncryptionKey::Rsa(public) => {
+                if let Ok(der) = public.to_pkcs1_der() {
+                    bytes.extend_from_slice(der.as_bytes());
+                }
+            }
+            EncryptionKey::Hybrid { rsa, kem } => {
+                if let Ok(der) = rsa.to_pkcs1_der() {
+                    bytes.extend_from_slice(der.as_bytes());
+                }
+                bytes.extend_from_slice(&kem.as_bytes());
+            }
+        }
+        format!("{}{}", COMPACT_PREFIX, BASE64.encode(bytes))
+    }
+
+    // RSA encryption key alone as the SPKI "PUBLIC KEY" PEM that OpenSSL writes; None for X25519 users
+    pub fn export_rsa_public_spki_pem(&self) -> Option<String> {
+        let rsa = match &self.encryption_key {
+            EncryptionKey::Rsa(rsa) | EncryptionKey::Hybrid { rsa, .. } => rsa,
+            EncryptionKey::X25519(_) => return None,
+        };
+        rsa.to_public_key_pem(LineEnding::LF).ok()
+    }
+}
+
+fn public_pem(signing: &VerifyingKey, encryption: &EncryptionKey) -> String {
+    let VerifyingKey::Ed25519(signing) = signing;
+    let mut bundle = pem::encode_string(SPKI_LABEL, LineEnding::LF, &spki(&ED25519_SPKI_PREFIX, signing.as_bytes()))
+        .unwrap_or_default();
+    match encryption {
+        EncryptionKey::X25519(public) => bundle.push_str(
+            &pem::encode_string(SPKI_LABEL, LineEnding::LF, &spki(&X25519_SPKI_PREFIX, public.as_bytes())).unwrap_or_default(),
+        ),
+        EncryptionKey::Rsa(public) => bundle.push_str(&public.to_pkcs1_pem(LineEnding::LF).unwrap_or_default()),
+        EncryptionKey::Hybrid { rsa, kem } => {
+            bundle.push_str(&rsa.to_pkcs1_pem(LineEnding::LF).unwrap_or_default());
+            bundle.push_str(&pem::encode_string(KEM_LABEL, LineEnding::LF, &kem.as_bytes()).unwrap_or_default());
+        }
+    }
+    bundle
+}
+
+// DER SubjectPublicKeyInfo for a 32-byte curve key
+fn spki(prefix: &[u8; 12], key: &[u8; 32]) -> Vec<u8> {
+    let mut der = prefix.to_vec();
+    der.extend_from_slice(key);
+    der
+}
+
+// Split concatenated PEM text into individual blocks
+fn pem_blocks(text: &str) -> Vec<&str> {
+    const END: &str = "-----END ";
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("-----BEGIN ") {
+        let block = &rest[start..];
+        let Some(end) = block.find(END) else { break };
+        let Some(close) = block[end + END.len()..].find("-----") else { break };
+        let stop = end + END.len() + close + "-----".len();
+        blocks.push(&block[..stop]);
+        rest = &block[stop..];
+    }
+    blocks
+}
+
+// Raw ML-KEM-768 encapsulation key
+fn kem_public_key(bytes: &[u8]) -> Result<Box<KemEncapsulationKey>, ImportError> {
+    let encoded = Encoded::<KemEncapsulationKey>::try_from(bytes).map_err(|_| ImportError::InvalidKey)?;
+    Ok(Box::new(KemEncapsulationKey::from_bytes(&encoded)))
+}
+
+// Parse a bundle produced by `User::export_public_compact`
+fn import_compact_contact(encoded: &str) -> Result<Contact, ImportError> {
+    let bytes = BASE64.decode(encoded).map_err(|_| ImportError::MalformedCompact)?;
+    if bytes.len() <= 32 {
+        return Err(ImportError::MissingEncryptionKey);
+    }
+    // An RSA key's DER is always far longer than a bare 32-byte X25519 key, and a hybrid key
+    // is RSA DER that doesn't parse on its own until the trailing ML-KEM key is split off
+    let key = &bytes[32..];
+    let encryption = if let Ok(x25519) = <[u8; 32]>::try_from(key) {
+        EncryptionKey::X25519(X25519PublicKey::from(x25519))
+    } else if let Ok(rsa) = RsaPublicKey::from_pkcs1_der(key) {
+        EncryptionKey::Rsa(rsa)
+    } else if key.len() > KEM_PUBLIC_LEN {
+        let (rsa, kem) = key.split_at(key.len() - KEM_PUBLIC_LEN);
+        EncryptionKey::Hybrid {
+            rsa: RsaPublicKey::from_pkcs1_der(rsa).map_err(|_| ImportError::InvalidKey)?,
+            kem: kem_public_key(kem)?,
+        }
+    } else {
+        return Err(ImportError::InvalidKey);
+    };
+    Ok(Contact {
+        signing: VerifyingKey::from_bytes(&bytes[..32]).ok_or(ImportError::InvalidKey)?,
+        encryption,
+    })
+}
+
+// Parse a bundle produced by `User::export_public_pem` or `User::export_public_compact`
+pub fn import_public_contact(text: &str) -> Result<Contact, ImportError> {
+    if let Some(encoded) = text.trim().strip_prefix(COMPACT_PREFIX) {
+        return import_compact_contact(encoded);
+    }
+
+    let mut ed25519 = None;
+    let mut encryption = None;
+    let mut kem = None;
+
+    for block in pem_blocks(text) {
+        let (label, der) = pem::decode_vec(block.as_bytes()).map_err(|_| ImportError::MalformedPem)?;
+        match label {
+            // Ed25519 and X25519 share the SPKI label and differ only in the algorithm OID
+            SPKI_LABEL => {
+                if der.len() != ED25519_SPKI_PREFIX.len() + 32 {
+                    return Err(ImportError::InvalidKey);
+                }
+                let (prefix, key) = der.split_at(ED25519_SPKI_PREFIX.len());
+                if prefix == ED25519_SPKI_PREFIX {
+                    ed25519 = Some(VerifyingKey::Ed25519(PublicKey::from_bytes(key).map_err(|_| ImportError::InvalidKey)?));
+                } else if prefix == X25519_SPKI_PREFIX {
+                    let key: [u8; 32] = key.try_into().map_err(|_| ImportError::InvalidKey)?;
+                    encryption = Some(EncryptionKey::X25519(X25519PublicKey::from(key)));
+                } else {
+                    return Err(ImportError::InvalidKey);
+                }
+            }
+            RSA_LABEL => {
+                encryption = Some(EncryptionKey::Rsa(RsaPublicKey::from_pkcs1_der(&der).map_err(|_| ImportError::InvalidKey)?));
+            }
+            KEM_LABEL => kem = Some(kem_public_key(&der)?),
+            _ => return Err(ImportError::UnexpectedLabel(label.to_string())),
+        }
+    }
+
+    let signing = ed25519.ok_or(ImportError::MissingEd25519Key)?;
+    // An ML-KEM block only makes sense paired with the RSA block of a hybrid key
+    let encryption = match (encryption, kem) {
+        (Some(EncryptionKey::Rsa(rsa)), Some(kem)) => EncryptionKey::Hybrid { rsa, kem },
+        (_, Some(_)) => return Err(ImportError::InvalidKey),
+        (encryption, None) => encryption.ok_or(ImportError::MissingEncryptionKey)?,
+    };
+    Ok(Contact { signing, encryption })
+}
+
+// One named contact in a contacts bundle; `keys` is anything import_public_contact reads
+#[derive(Serialize, Deserialize)]
+struct BundleEntry {
+    name: String,
+    keys: String,
+}
+
+// JSON array of named public key bundles, sorted by name
+pub(crate) fn contacts_bundle(contacts: &HashMap<String, Contact>) -> String {
+    let mut entries: Vec<BundleEntry> = contacts
+        .iter()
+        .map(|(name, contact)| BundleEntry { name: name.clone(), keys: contact.export_public_pem() })
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    serde_json::to_string_pretty(&entries).unwrap_or_default()
+}
+
+// Parse every entry of a contacts bundle on its own, so one bad entry doesn't lose the rest
+pub(crate) fn parse_contacts_bundle(text: &str) -> Vec<Result<(String, Contact), ImportError>> {
+    let Ok(entries) = serde_json::from_str::<Vec<serde_json::Value>>(text) else {
+        return vec![Err(ImportError::MalformedBundle)];
+    };
+    entries
+        .into_iter()
+        .map(|entry| {
+            let entry: BundleEntry = serde_json::from_value(entry).map_err(|_| ImportError::MalformedBundle)?;
+            Ok((entry.name, import_public_contact(&entry.keys)?))
+        })
+        .collect()
+}
+
+// Parse an SPKI "PUBLIC KEY" PEM holding an RSA key, such as `openssl pkey -pubout` writes.
+// PKCS#1 "RSA PUBLIC KEY" blocks are refused with UnexpectedLabel, other algorithms with NotRsaKey.
+pub fn import_rsa_public_spki_pem(text: &str) -> Result<RsaPublicKey, ImportError> {
+    let (label, der) = pem::decode_vec(text.trim().as_bytes()).map_err(|_| ImportError::MalformedPem)?;
+    if label != SPKI_LABEL {
+        return Err(ImportError::UnexpectedLabel(label.to_string()));
+    }
+    let info = SubjectPublicKeyInfoRef::try_from(der.as_slice()).map_err(|_| ImportError::InvalidKey)?;
+    if info.algorithm.oid != rsa::pkcs1::ALGORITHM_OID {
+        return Err(ImportError::NotRsaKey);
+    }
+    RsaPublicKey::try_from(info).map_err(|_| ImportError::InvalidKey)
+}
+
+// Parse an OpenSSH `ssh-ed25519 AAAA... comment` line into a key for verifying that identity's signatures
+pub fn import_ssh_ed25519(line: &str) -> Result<VerifyingKey, ImportError> {
+    let mut parts = line.split_whitespace();
+    let key_type = parts.next().ok_or(ImportError::MalformedSshKey)?;
+    if key_type != SSH_ED25519 {
+        return Err(ImportError::UnsupportedSshKeyType(key_type.to_string()));
+    }
+    let blob = BASE64
+        .decode(parts.next().ok_or(ImportError::MalformedSshKey)?)
+        .map_err(|_| ImportError::MalformedSshKey)?;
+
+    // The blob repeats the key type, then carries the key, each as a u32-length-prefixed string
+    let mut rest = blob.as_slice();
+    let blob_type = ssh_string(&mut rest)?;
+    if blob_type != SSH_ED25519.as_bytes() {
+        return Err(ImportError::UnsupportedSshKeyType(String::from_utf8_lossy(blob_type).into_owned()));
+    }
+    let key = ssh_string(&mut rest)?;
+    if key.len() != 32 || !rest.is_empty() {
+        return Err(ImportError::MalformedSshKey);
+    }
+    VerifyingKey::from_bytes(key).ok_or(ImportError::InvalidKey)
+}
+
+// Take one length-prefixed string off the front of an OpenSSH key blob
+fn ssh_string<'a>(rest: &mut &'a [u8]) -> Result<&'a [u8], ImportError> {
+    let (len, tail) = rest.split_first_chunk::<4>().ok_or(ImportError::MalformedSshKey)?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if tail.len() < len {
+        return Err(ImportError::MalformedSshKey);
+    }
+    let (field, tail) = tail.split_at(len);
+    *rest = tail;
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyScheme;
+    use crate::SignatureSystem;
+
+    #[test]
+    fn pem_round_trip() {
+        for scheme in [KeyScheme::X25519, KeyScheme::Rsa, KeyScheme::Hybrid] {
+            let alice = User::generate("alice".to_string(), scheme).expect("generate");
+
+            let bundle = alice.export_public_pem();
+            assert!(bundle.contains("-----BEGIN PUBLIC KEY-----"));
+            assert_eq!(bundle.contains("-----BEGIN RSA PUBLIC KEY-----"), scheme != KeyScheme::X25519);
+            assert_eq!(bundle.contains("-----BEGIN MLKEM768 PUBLIC KEY-----"), scheme == KeyScheme::Hybrid);
+
+            let contact = import_public_contact(&bundle).expect("import");
+            assert_eq!(contact, alice.contact());
+            assert_eq!(contact.fingerprint(), alice.fingerprint());
+        }
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        for scheme in [KeyScheme::X25519, KeyScheme::Rsa, KeyScheme::Hybrid] {
+            let alice = User::generate("alice".to_string(), scheme).expect("generate");
+
+            let compact = alice.export_public_compact();
+            assert!(compact.starts_with(COMPACT_PREFIX));
+            assert!(!compact.contains('\n'));
+            assert_eq!(import_public_contact(&compact).expect("import"), alice.contact());
+        }
+        assert!(matches!(import_public_contact("pgfi1:AAAA"), Err(ImportError::MissingEncryptionKey)));
+        assert!(matches!(import_public_contact("pgfi1:!!"), Err(ImportError::MalformedCompact)));
+    }
+
+    #[test]
+    fn malformed_pem_rejected() {
+        assert!(matches!(
+            import_public_contact("-----BEGIN PUBLIC KEY-----\n!!!!\n-----END PUBLIC KEY-----\n"),
+            Err(ImportError::MalformedPem)
+        ));
+        assert!(matches!(import_public_contact("no keys here"), Err(ImportError::MissingEd25519Key)));
+
+        // Correct framing but the body isn't an Ed25519 SPKI
+        let bogus = pem::encode_string(SPKI_LABEL, LineEnding::LF, &[0u8; 44]).expect("encode");
+        assert!(matches!(import_public_contact(&bogus), Err(ImportError::InvalidKey)));
+    }
+
+    #[test]
+    fn missing_encryption_block_rejected() {
+        let mut system = SignatureSystem::default();
+        system.create_user("alice".to_string()).expect("create user");
+        let bundle = system.users["alice"].export_public_pem();
+        let end = "-----END PUBLIC KEY-----";
+        let ed25519_only = &bundle[..bundle.find(end).expect("ed25519 block") + end.len()];
+        assert!(matches!(import_public_contact(ed25519_only), Err(ImportError::MissingEncryptionKey)));
+    }
+    #[test]
+    fn openssh_ed25519_line_imported() {
+        // Generated with `ssh-keygen -t ed25519 -C player1@arena`
+        let line = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIIMle2MvqubAgi8d0GdrUwmPmzn9Rf+TD9RWmT1taEao player1@arena";
+        let key = import_ssh_ed25519(line).expect("import");
+        let blob = BASE64.decode(line.split(' ').nth(1).expect("blob")).expect("base64");
+        assert_eq!(key.as_bytes(), &blob[blob.len() - 32..]);
+
+        // The imported key verifies signatures from the matching private key
+        let mut system = SignatureSystem::default();
+        system.create_user("alice".to_string()).expect("create user");
+        system.create_user("bob".to_string()).expect("create user");
+        let alice = &system.users["alice"];
+        let mut blob = Vec::new();
+        for field in [SSH_ED25519.as_bytes(), alice.keypair.public().as_bytes()] {
+            blob.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            blob.extend_from_slice(field);
+        }
+        let alice_ssh = import_ssh_ed25519(&format!("{} {}", SSH_ED25519, BASE64.encode(blob))).expect("import");
+        let message = system.encrypt_message(alice, &system.users["bob"], "gg").expect("encrypt");
+        assert_eq!(system.verify_signature_only(&message, &alice_ssh), Ok(()));
+    }
+
+    #[test]
+    fn safety_number_is_order_independent_and_key_bound() {
+        let alice = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate").contact();
+        let mut bob = User::generate("bob".to_string(), KeyScheme::Rsa).expect("generate");
+
+        let number = safety_number(&alice, &bob.contact());
+        assert_eq!(number.len(), 60);
+        assert!(number.bytes().all(|byte| byte.is_ascii_digit()));
+        assert_eq!(safety_number(&bob.contact(), &alice), number);
+
+        // Changing either half of either side's identity changes the number
+        let mallory = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate").contact();
+        assert_ne!(safety_number(&mallory, &bob.contact()), number);
+        bob.rotate_keys(1_000).expect("rotate");
+        assert_ne!(safety_number(&alice, &bob.contact()), number);
+        let swapped = Contact { encryption: mallory.encryption, ..alice.clone() };
+        assert_ne!(safety_number(&swapped, &bob.contact()), safety_number(&alice, &bob.contact()));
+    }
+
+    #[test]
+    fn other_openssh_key_types_rejected() {
+        let rsa = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQCuvGeRp9UoutQBX4LCOB8m x";
+        assert_eq!(import_ssh_ed25519(rsa), Err(ImportError::UnsupportedSshKeyType("ssh-rsa".to_string())));
+
+        // A relabelled RSA blob is caught by the type inside the blob
+        let relabelled = rsa.replacen("ssh-rsa", SSH_ED25519, 1);
+        assert_eq!(import_ssh_ed25519(&relabelled), Err(ImportError::UnsupportedSshKeyType("ssh-rsa".to_string())));
+
+        assert_eq!(import_ssh_ed25519(""), Err(ImportError::MalformedSshKey));
+        assert_eq!(import_ssh_ed25519("ssh-ed25519"), Err(ImportError::MalformedSshKey));
+        assert_eq!(import_ssh_ed25519("ssh-ed25519 !!!!"), Err(ImportError::MalformedSshKey));
+        assert_eq!(import_ssh_ed25519("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"), Err(ImportError::MalformedSshKey));
+    }
+
+    #[test]
+    fn rsa_spki_round_trip() {
+        let alice = User::generate("alice".to_string(), KeyScheme::Rsa).expect("generate");
+        let pem = alice.export_rsa_public_spki_pem().expect("rsa key");
+        assert!(pem.starts_with("-----BEGIN PUBLIC KEY-----"));
+        assert_eq!(EncryptionKey::Rsa(import_rsa_public_spki_pem(&pem).expect("import")), alice.encryption_key);
+
+        // PKCS#1 and non-RSA SPKI blocks are the wrong format, not just invalid
+        let EncryptionKey::Rsa(public) = &alice.encryption_key else { unreachable!() };
+        let pkcs1 = public.to_pkcs1_pem(LineEnding::LF).expect("pkcs1");
+        assert_eq!(import_rsa_public_spki_pem(&pkcs1), Err(ImportError::UnexpectedLabel(RSA_LABEL.to_string())));
+        let x25519 = User::generate("bob".to_string(), KeyScheme::X25519).expect("generate");
+        let ed25519 = x25519.export_public_pem().split_inclusive("-----END PUBLIC KEY-----\n").next().expect("block").to_string();
+        assert_eq!(import_rsa_public_spki_pem(&ed25519), Err(ImportError::NotRsaKey));
+        assert!(x25519.export_rsa_public_spki_pem().is_none());
+    }
+
+    #[test]
+    fn bad_bundle_entry_leaves_the_rest() {
+        let mut guild = SignatureSystem::default();
+        for name in ["alice", "bob", "carol"] {
+            let member = User::generate(name.to_string(), KeyScheme::X25519).expect("generate");
+            guild.add_contact(name.to_string(), &member.export_public_pem()).expect("add");
+        }
+        let mut entries: Vec<serde_json::Value> = serde_json::from_str(&guild.export_contacts_bundle()).expect("json");
+        entries[1]["keys"] = "-----BEGIN PUBLIC KEY-----\n!!!!\n-----END PUBLIC KEY-----\n".into();
+        let bundle = serde_json::to_string(&entries).expect("json");
+
+        let mut system = SignatureSystem::default();
+        let results = system.import_contacts_bundle(&bundle);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().ok(), Some(&guild.contacts["alice"]));
+        assert_eq!(results[1], Err(ImportError::MalformedPem));
+        assert_eq!(results[2].as_ref().ok(), Some(&guild.contacts["carol"]));
+        assert_eq!(system.contacts.len(), 2);
+        assert!(!system.contacts.contains_key("bob"));
+
+        assert_eq!(system.import_contacts_bundle("not json"), vec![Err(ImportError::MalformedBundle)]);
+        assert_eq!(system.import_contacts_bundle(r#"[{"name": "dave"}]"#), vec![Err(ImportError::MalformedBundle)]);
+    }
+}
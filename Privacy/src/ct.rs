@@ -0,0 +1,28 @@
+use subtle::ConstantTimeEq;
+
+// Compare digests, fingerprints and key ids without an early exit. Equal-length inputs take
+// the same time however many leading bytes match, so a caller timing the check learns nothing
+// about where two values first differ; the lengths themselves are not treated as secret.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ct_eq_matches_ordinary_equality() {
+        let digest = [0x5au8; 32];
+        assert!(ct_eq(&digest, &digest));
+        assert!(ct_eq(b"", b""));
+
+        for position in [0, 15, 31] {
+            let mut other = digest;
+            other[position] ^= 1;
+            assert!(!ct_eq(&digest, &other));
+        }
+        assert!(!ct_eq(&digest, &digest[..31]));
+        assert!(!ct_eq(b"", &digest));
+    }
+}
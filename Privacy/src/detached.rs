@@ -0,0 +1,115 @@
+use crate::error::CryptoError;
+use crate::signing::{MessageSignature, VerifyingKey};
+use crate::user::User;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use sha2::{Digest, Sha512};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Domain separation for detached file signatures
+const FILE_SIGNATURE_CONTEXT: &[u8] = b"pgfi-file-v1";
+
+// Extension appended to the signed file's name for its detached signature
+const SIGNATURE_EXTENSION: &str = "sig";
+
+fn io_error(err: io::Error) -> CryptoError {
+    CryptoError::Io(err.to_string())
+}
+
+// SHA-512 of the file, read in pieces so large mods and patches are never held in memory whole
+fn file_digest(path: &Path) -> io::Result<[u8; 64]> {
+    let mut hasher = Sha512::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+fn file_signed_bytes(digest: &[u8; 64]) -> Vec<u8> {
+    let mut bytes = FILE_SIGNATURE_CONTEXT.to_vec();
+    bytes.extend_from_slice(digest);
+    bytes
+}
+
+impl User {
+    // Detached signature over the file's digest, for publishing alongside a mod or patch
+    pub fn sign_file(&self, path: &Path) -> Result<MessageSignature, CryptoError> {
+        let digest = file_digest(path).map_err(io_error)?;
+        Ok(self.keypair.sign(&file_signed_bytes(&digest)))
+    }
+}
+
+// True only if `signer_public` signed exactly the file's current contents; unreadable files never verify
+pub fn verify_file(path: &Path, signature: &MessageSignature, signer_public: &VerifyingKey) -> bool {
+    file_digest(path).is_ok_and(|digest| signer_public.verify(&file_signed_bytes(&digest), signature).is_ok())
+}
+
+// Where the detached signature for `path` lives: the same name with ".sig" appended
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(SIGNATURE_EXTENSION);
+    PathBuf::from(name)
+}
+
+// Write the signature next to the signed file as one line of base64, returning the path written
+pub fn write_signature_file(path: &Path, signature: &MessageSignature) -> Result<PathBuf, CryptoError> {
+    let sig_path = signature_path(path);
+    fs::write(&sig_path, format!("{}\n", BASE64.encode(signature.to_bytes()))).map_err(io_error)?;
+    Ok(sig_path)
+}
+
+// Read a signature written by write_signature_file, for a signer using `signer_public`'s algorithm
+pub fn read_signature_file(sig_path: &Path, signer_public: &VerifyingKey) -> Result<MessageSignature, CryptoError> {
+    let text = fs::read_to_string(sig_path).map_err(io_error)?;
+    let bytes = BASE64
+        .decode(text.trim())
+        .map_err(|_| CryptoError::Serialization("signature file is not base64".to_string()))?;
+    MessageSignature::from_bytes(signer_public.algorithm(), &bytes)
+        .ok_or_else(|| CryptoError::Serialization("signature file holds no valid signature".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyScheme;
+
+    #[test]
+    fn signed_file_verifies_until_modified() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("mod.pak");
+        let mut contents: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        fs::write(&path, &contents).expect("write");
+
+        let alice = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate");
+        let signature = alice.sign_file(&path).expect("sign");
+        assert!(verify_file(&path, &signature, &alice.keypair.public()));
+
+        let mallory = User::generate("mallory".to_string(), KeyScheme::X25519).expect("generate");
+        assert!(!verify_file(&path, &signature, &mallory.keypair.public()));
+
+        contents[100_000] ^= 0x01;
+        fs::write(&path, &contents).expect("write");
+        assert!(!verify_file(&path, &signature, &alice.keypair.public()));
+        assert!(!verify_file(&dir.path().join("missing.pak"), &signature, &alice.keypair.public()));
+    }
+
+    #[test]
+    fn signature_file_round_trip() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("patch-1.2.zip");
+        fs::write(&path, b"patch notes").expect("write");
+
+        let alice = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate");
+        let sig_path = write_signature_file(&path, &alice.sign_file(&path).expect("sign")).expect("write signature");
+        assert_eq!(sig_path, dir.path().join("patch-1.2.zip.sig"));
+
+        let signature = read_signature_file(&sig_path, &alice.keypair.public()).expect("read signature");
+        assert!(verify_file(&path, &signature, &alice.keypair.public()));
+
+        fs::write(&sig_path, "not a signature").expect("write");
+        assert!(matches!(
+            read_signature_file(&sig_path, &alice.keypair.public()),
+            Err(CryptoError::Serialization(_))
+        ));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::message::SuiteComponent;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    #[error("Key generation failed")]
+    KeyGeneration,
+    #[error("Invalid recovery phrase")]
+    InvalidMnemonic,
+    #[error("Encryption failed")]
+    Encryption,
+    #[error("Invalid key")]
+    InvalidKey,
+    #[error("Unsupported RSA key size {0} bits")]
+    UnsupportedKeySize(usize),
+    #[error("Recipient's RSA key is only {0} bits")]
+    WeakRecipientKey(usize),
+    #[error("Not a member of this group")]
+    NotGroupMember,
+    #[error("Recipient's keys have not been verified")]
+    UntrustedRecipient,                  // Refused under TrustPolicy::RequireVerified
+    #[error("Session can't send until the other side's first message arrives")]
+    SessionNotEstablished,
+    #[error("Keys are locked; enter the keystore passphrase again")]
+    Locked,                              // Wiped by SignatureSystem's idle lock
+    #[error("Malformed message: {0}")]
+    Serialization(String),
+    #[error("Could not read the message to forward: {0}")]
+    Forward(DecryptError),               // Raised by SignatureSystem::reencrypt
+    #[error("Message is larger than the {0}-byte limit")]
+    TooLarge(usize),                     // See MessagePolicy::max_message_bytes
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("I/O error: {0}")]
+    Io(String),
+}
+
+// Why a user could not be created
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CreateError {
+    #[error("Another user is still being created, try again shortly")]
+    Busy,                                // Refused by CreateThrottle
+    #[error("A user with that name already exists")]
+    DuplicateUsername,                   // The existing user and keys are left as they were
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+}
+
+// Why an incoming message could not be read
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DecryptError {
+    #[error("Message is not addressed to this user")]
+    WrongRecipient,                      // None of our keys unwraps the symmetric key
+    #[error("Message is corrupted")]
+    CorruptCiphertext,                   // Key unwrapped but the payload fails authentication
+    #[error("Sender signature is invalid")]
+    InvalidSignature,
+    #[error("Message is not valid text")]
+    MalformedUtf8,
+    #[error("Message envelope was modified in transit")]
+    TamperedEnvelope,
+    #[error("Legacy PKCS#1 v1.5 message is no longer supported")]
+    LegacyPadding,
+    #[error("Unsupported message version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Message uses an unsupported {0} algorithm")]
+    UnsupportedSuite(SuiteComponent),
+    #[error("Message was sent under a different group key")]
+    StaleGroupKey,                       // Our copy of the group is older or newer than the message
+    #[error("Message has expired")]
+    Expired,
+    #[error("Message timestamp is too far in the future")]
+    FutureTimestamp,
+    #[error("Stream ended before its final chunk")]
+    Truncated,
+    #[error("Message was already received")]
+    NonceReused,
+    #[error("Keys are locked; enter the keystore passphrase again")]
+    Locked,
+    #[error("Message was already read once and has been burned")]
+    AlreadyRead,
+    #[error("Too many messages were skipped")]
+    TooManySkipped,                      // Session message is further ahead than we will derive keys for
+    #[error("Sender's signing key has been revoked")]
+    Revoked,
+    #[error("Message padding is invalid")]
+    BadPadding,                          // Authenticated, but the recorded length overruns the payload
+    #[error("Message could not be decompressed")]
+    Decompression,                       // Authenticated, but not valid DEFLATE or larger than allowed
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("I/O error: {0}")]
+    Io(String),
+}
+
+// Why MessagingService could not send or receive
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError {
+    #[error("No user is signed in")]
+    NotSignedIn,
+    #[error("No user named {0}")]
+    UnknownUser(String),
+    #[error("No user or contact named {0}")]
+    UnknownRecipient(String),
+    #[error(transparent)]
+    Wire(#[from] WireError),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    #[error(transparent)]
+    Decrypt(#[from] DecryptError),
+}
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("Wrong keystore passphrase")]
+    BadPassphrase,
+    #[error("Keystore file is corrupt")]
+    Corrupt,
+    #[error("Unsupported keystore version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Key derivation failed")]
+    KeyDerivation,
+    #[error("Signing key is held in hardware and can't be saved")]
+    NotExportable,                       // The user's Signer has no secret_bytes
+    #[error("Keystore I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Key backend error: {0}")]
+    Backend(String),                     // Reported by a KeyBackend that isn't file based
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+    #[error("Malformed PEM")]
+    MalformedPem,
+    #[error("Malformed compact key bundle")]
+    MalformedCompact,
+    #[error("Malformed contacts bundle entry")]
+    MalformedBundle,                     // Not a JSON array, or an entry without a name and keys
+    #[error("Unexpected PEM block {0}")]
+    UnexpectedLabel(String),
+    #[error("Missing Ed25519 public key")]
+    MissingEd25519Key,
+    #[error("Missing encryption public key")]
+    MissingEncryptionKey,
+    #[error("Invalid public key")]
+    InvalidKey,
+    #[error("Key is not an RSA key")]
+    NotRsaKey,                           // Well-formed SPKI or PKCS#8, but for another algorithm
+    #[error("Malformed OpenSSH public key line")]
+    MalformedSshKey,
+    #[error("Unsupported OpenSSH key type {0}, expected ssh-ed25519")]
+    UnsupportedSshKeyType(String),
+    #[error("Invalid revocation certificate")]
+    InvalidRevocation,
+    #[error(transparent)]
+    KeyChanged(#[from] TofuWarning),     // Nothing was imported; see SignatureSystem::accept_contact
+    #[error("Wrong identity passphrase")]
+    WrongPassphrase,
+    #[error("Identity export is corrupt")]
+    Corrupt,
+}
+
+// A known peer presented different keys from the ones first seen under their name
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TofuWarning {
+    #[error("Keys for {username} changed from {old} to {new}; confirm with them before trusting the new keys")]
+    KeyChanged {
+        username: String,
+        old: String,                     // Fingerprint first seen
+        new: String,                     // Fingerprint just presented
+    },
+}
+
+// Why a binary wire message could not be parsed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    #[error("Not a wire-format message")]
+    BadMagic,
+    #[error("Message is not valid base64")]
+    InvalidBase64,
+    #[error("Unsupported wire format version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Wire message is truncated")]
+    Truncated,
+    #[error("Invalid {0} field in wire message")]
+    InvalidField(&'static str),
+    #[error("Unexpected bytes after wire message")]
+    TrailingBytes,
+    #[error("Wire message declares a field larger than the {0}-byte limit")]
+    TooLarge(usize),
+}
+
+// Why armored text could not be turned back into wire bytes
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ArmorError {
+    #[error("No -----BEGIN PGFI MESSAGE----- line")]
+    MissingHeader,
+    #[error("No -----END PGFI MESSAGE----- line; the paste may be cut off")]
+    MissingFooter,
+    #[error("Armored message has no checksum line")]
+    MissingChecksum,
+    #[error("Armored message is not valid base64")]
+    InvalidBase64,
+    #[error("Armor checksum doesn't match; the message was changed while copying")]
+    BadChecksum,
+    #[error(transparent)]
+    Wire(#[from] WireError),             // Checksum passed but the bytes aren't a message
+}
+
+// Which known-answer test failed; the build's crypto can't be trusted for anything
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SelfTestError {
+    #[error("AES-256-GCM self-test failed")]
+    Aes256Gcm,
+    #[error("Ed25519 self-test failed")]
+    Ed25519,
+    #[error("RSA-OAEP self-test failed")]
+    RsaOaep,
+    #[error("Encrypt and decrypt round trip failed")]
+    RoundTrip,
+}
+
+// Why a Transport could not deliver a message
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    #[error("Message could not be sent: {0}")]
+    Send(String),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AnchorError {
+    #[error("Anchor submission failed: {0}")]
+    Submission(String),
+    #[error("Too many anchors are waiting for the chain")]
+    QueueFull,                           // AnchorQueue is at RetryPolicy::capacity
+    #[error("Pending anchors could not be saved: {0}")]
+    Io(String),
+}
@@ -0,0 +1,298 @@
+use crate::contact::RecipientKeys;
+use crate::error::{CryptoError, DecryptError};
+use crate::keys::{EncryptionKey, KeyExchange};
+use crate::signing::{MessageSignature, VerifyingKey};
+use crate::system::{SignatureSystem, SymmetricKey};
+use crate::user::User;
+use aes_gcm::{
+    aead::{Aead, AeadCore, OsRng as AesOsRng, Payload},
+    Aes256Gcm,
+    Nonce,
+};
+use rand::rngs::OsRng;
+use std::collections::HashMap;
+
+// Domain separation for group message signatures
+const GROUP_SIGNATURE_CONTEXT: &[u8] = b"pgfi-group-v1";
+
+// Persistent set of members sharing one AES key, replaced whenever someone leaves.
+// Only the current key is kept, so messages from earlier epochs can no longer be read.
+#[derive(Clone)]
+pub struct Group {
+    pub group_id: String,
+    pub members: HashMap<String, EncryptionKey>, // Member fingerprint -> public encryption key
+    pub epoch: u32,                              // Incremented on every key rotation
+    pub key_grants: HashMap<String, KeyExchange>, // Current group key delivered to each member
+}
+
+// A message encrypted under a group's current key
+#[derive(Clone)]
+pub struct GroupMessage {
+    pub group_id: String,
+    pub epoch: u32,                      // Group key epoch the message was encrypted under
+    pub encrypted_data: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub signature: MessageSignature,     // Sender's signature over the header and plaintext
+    pub sender_public: VerifyingKey,
+    pub timestamp: u64,
+}
+
+// Header authenticated by AES-GCM so ciphertext can't be moved to another group or epoch
+fn associated_data(group_id: &str, epoch: u32) -> Vec<u8> {
+    let mut bytes = (group_id.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(group_id.as_bytes());
+    bytes.extend_from_slice(&epoch.to_be_bytes());
+    bytes
+}
+
+// Bytes covered by the sender's signature: context, group, epoch, timestamp, plaintext
+fn group_signed_bytes(group_id: &str, epoch: u32, timestamp: u64, message: &[u8]) -> Vec<u8> {
+    let mut bytes = GROUP_SIGNATURE_CONTEXT.to_vec();
+    bytes.extend_from_slice(&associated_data(group_id, epoch));
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    bytes.extend_from_slice(message);
+    bytes
+}
+
+// Deliver the same group key to every member
+fn grant_all(members: &HashMap<String, EncryptionKey>, group_key: &SymmetricKey) -> Result<HashMap<String, KeyExchange>, CryptoError> {
+    members
+        .iter()
+        .map(|(fingerprint, key)| Ok((fingerprint.clone(), key.wrap(group_key, &mut OsRng)?)))
+        .collect()
+}
+
+impl Group {
+    // Unwrap the current group key with one of the member's keys, newest first
+    fn group_key(&self, member: &User) -> Result<SymmetricKey, DecryptError> {
+        for (fingerprint, decryption_key) in member.decryption_keys() {
+            if let Some(grant) = self.key_grants.get(&fingerprint) {
+                return decryption_key.unwrap(grant);
+            }
+        }
+        Err(DecryptError::WrongRecipient)
+    }
+
+    pub fn is_member(&self, fingerprint: &str) -> bool {
+        self.members.contains_key(fingerprint)
+    }
+
+    // Give a new member the current key; `by` must already be a member to unwrap it
+    pub fn add_member(&mut self, by: &User, member: &dyn RecipientKeys) -> Result<(), CryptoError> {
+        let group_key = self.group_key(by).map_err(|_| CryptoError::NotGroupMember)?;
+        let grant = member.encryption_key().wrap(&group_key, &mut OsRng)?;
+        self.members.insert(member.fingerprint(), member.encryption_key().clone());
+        self.key_grants.insert(member.fingerprint(), grant);
+        Ok(())
+    }
+
+    // Drop a member and rotate the key so they can't read anything sent afterwards
+    pub fn remove_member(&mut self, fingerprint: &str) -> Result<(), CryptoError> {
+        if self.members.remove(fingerprint).is_none() {
+            return Ok(());
+        }
+        self.key_grants.remove(fingerprint);
+        self.rotate_group_key()
+    }
+
+    // Replace the group key and re-wrap it to the remaining members
+    pub fn rotate_group_key(&mut self) -> Result<(), CryptoError> {
+        let group_key = SymmetricKey::generate(&mut OsRng);
+        let key_grants = grant_all(&self.members, &group_key)?;
+        self.epoch = self.epoch.checked_add(1).ok_or(CryptoError::Encryption)?;
+        self.key_grants = key_grants;
+        Ok(())
+    }
+}
+
+impl SignatureSystem {
+    // Start a group with a fresh key shared by the creator and the given members
+    pub fn create_group(&self, creator: &User, group_id: String, members: &[&dyn RecipientKeys]) -> Result<Group, CryptoError> {
+        let mut keys: HashMap<String, EncryptionKey> = members
+            .iter()
+            .map(|member| (member.fingerprint(), member.encryption_key().clone()))
+            .collect();
+        keys.insert(creator.fingerprint(), creator.encryption_key.clone());
+
+        let group_key = SymmetricKey::generate(&mut OsRng);
+        Ok(Group {
+            group_id,
+            key_grants: grant_all(&keys, &group_key)?,
+            members: keys,
+            epoch: 0,
+        })
+    }
+
+    // Encrypt and sign a text message under the group's current key
+    pub fn encrypt_to_group(&self, sender: &User, group: &Group, message: &str) -> Result<GroupMessage, CryptoError> {
+        let group_key = group.group_key(sender).map_err(|_| CryptoError::NotGroupMember)?;
+        let timestamp = self.now_millis();
+        let nonce = Aes256Gcm::generate_nonce(&mut AesOsRng);
+        let encrypted_data = group_key
+            .cipher()
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: message.as_bytes(),
+                    aad: &associated_data(&group.group_id, group.epoch),
+                },
+            )
+            .map_err(|_| CryptoError::Encryption)?;
+
+        let signature = sender
+            .keypair
+            .sign(&group_signed_bytes(&group.group_id, group.epoch, timestamp, message.as_bytes()));
+        Ok(GroupMessage {
+            group_id: group.group_id.clone(),
+            epoch: group.epoch,
+            encrypted_data,
+            nonce: nonce.to_vec(),
+            signature,
+            sender_public: sender.keypair.public(),
+            timestamp,
+        })
+    }
+
+    // Decrypt and verify a group message with the recipient's copy of the group
+    pub fn decrypt_group_message(&self, recipient: &User, group: &Group, message: &GroupMessage) -> Result<String, DecryptError> {
+        if message.group_id != group.group_id {
+            return Err(DecryptError::WrongRecipient);
+        }
+        if message.epoch != group.epoch {
+            return Err(DecryptError::StaleGroupKey);
+        }
+        self.revocations.check(&message.sender_public)?;
+        self.check_timestamp(message.timestamp, self.now_millis())?;
+        if message.nonce.len() != 12 {
+            return Err(DecryptError::CorruptCiphertext);
+        }
+
+        let group_key = group.group_key(recipient)?;
+        let data = group_key
+            .cipher()
+            .decrypt(
+                Nonce::from_slice(&message.nonce),
+                Payload {
+                    msg: &message.encrypted_data,
+                    aad: &associated_data(&message.group_id, message.epoch),
+                },
+            )
+            .map_err(|_| DecryptError::CorruptCiphertext)?;
+
+        message
+            .sender_public
+            .verify(
+                &group_signed_bytes(&message.group_id, message.epoch, message.timestamp, &data),
+                &message.signature,
+            )
+            .map_err(|_| DecryptError::InvalidSignature)?;
+        String::from_utf8(data).map_err(|_| DecryptError::MalformedUtf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::system_with_users;
+
+    fn party(system: &SignatureSystem) -> Group {
+        let members = [&system.users["bob"] as &dyn RecipientKeys, &system.users["carol"]];
+        system
+            .create_group(&system.users["alice"], "raid-night".to_string(), &members)
+            .expect("create group")
+    }
+
+    #[test]
+    fn group_round_trip() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let group = party(&system);
+        assert_eq!(group.members.len(), 3);
+
+        let message = system
+            .encrypt_to_group(&system.users["bob"], &group, "pull at 9")
+            .expect("encrypt");
+        for name in ["alice", "bob", "carol"] {
+            assert_eq!(
+                system.decrypt_group_message(&system.users[name], &group, &message).expect("decrypt"),
+                "pull at 9"
+            );
+        }
+    }
+
+    #[test]
+    fn added_member_reads_current_key() {
+        let system = system_with_users(&["alice", "bob", "carol", "dave", "eve"]);
+        let mut group = party(&system);
+        let dave = &system.users["dave"];
+
+        group.add_member(&system.users["carol"], dave).expect("add member");
+        assert!(group.is_member(&dave.fingerprint()));
+        let message = system.encrypt_to_group(dave, &group, "hi all").expect("encrypt");
+        assert_eq!(
+            system.decrypt_group_message(&system.users["alice"], &group, &message).expect("decrypt"),
+            "hi all"
+        );
+
+        // Outsiders can neither invite nor post
+        let eve = &system.users["eve"];
+        assert_eq!(group.add_member(eve, eve), Err(CryptoError::NotGroupMember));
+        assert!(matches!(system.encrypt_to_group(eve, &group, "let me in"), Err(CryptoError::NotGroupMember)));
+        assert_eq!(
+            system.decrypt_group_message(eve, &group, &message),
+            Err(DecryptError::WrongRecipient)
+        );
+    }
+
+    #[test]
+    fn removed_member_cannot_read_after_rotation() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let mut group = party(&system);
+        let carol = &system.users["carol"];
+        let carols_copy = group.clone();
+
+        group.remove_member(&carol.fingerprint()).expect("remove member");
+        assert_eq!(group.epoch, 1);
+        assert!(!group.is_member(&carol.fingerprint()));
+
+        let message = system
+            .encrypt_to_group(&system.users["alice"], &group, "carol is out")
+            .expect("encrypt");
+        assert_eq!(
+            system.decrypt_group_message(&system.users["bob"], &group, &message).expect("decrypt"),
+            "carol is out"
+        );
+        assert_eq!(
+            system.decrypt_group_message(carol, &group, &message),
+            Err(DecryptError::WrongRecipient)
+        );
+
+        // Her old copy still holds the previous key, which no longer decrypts anything new
+        assert_eq!(
+            system.decrypt_group_message(carol, &carols_copy, &message),
+            Err(DecryptError::StaleGroupKey)
+        );
+        let mut forged = carols_copy;
+        forged.epoch = group.epoch;
+        assert_eq!(
+            system.decrypt_group_message(carol, &forged, &message),
+            Err(DecryptError::CorruptCiphertext)
+        );
+    }
+
+    #[test]
+    fn message_moved_to_another_epoch_rejected() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let group = party(&system);
+        let mut message = system
+            .encrypt_to_group(&system.users["alice"], &group, "hello")
+            .expect("encrypt");
+
+        message.epoch += 1;
+        let mut relabelled = group.clone();
+        relabelled.epoch = message.epoch;
+        assert_eq!(
+            system.decrypt_group_message(&system.users["bob"], &relabelled, &message),
+            Err(DecryptError::CorruptCiphertext)
+        );
+    }
+}
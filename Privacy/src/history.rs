@@ -0,0 +1,277 @@
+use crate::clock::SharedClock;
+use crate::message::MessageId;
+use crate::user::ConversationId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ops::Range;
+use zeroize::Zeroize;
+
+// A decrypted message kept for the history view
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub sender: String,                  // Base64 Ed25519 public key of the sender
+    pub recipient: String,               // Username the message was read as
+    pub timestamp: u64,                  // Unix millis the sender signed
+    pub body: String,
+    pub expires_at: Option<u64>,         // Unix millis after which the message is purged, None keeps it
+    #[serde(default)]
+    pub conversation_id: ConversationId, // NO_CONVERSATION for party messages
+    #[serde(default)]
+    pub message_id: MessageId,           // message_id of the EncryptedMessage or MultiRecipientMessage it came from
+    #[serde(default)]
+    pub read: bool,                      // Seen by the user; false when first received
+}
+
+// Decrypted messages in the order they were read
+#[derive(Default)]
+pub struct MessageStore {
+    messages: Vec<StoredMessage>,
+    seen_ids: HashSet<MessageId>,        // EncryptedMessage::message_id of everything read, purged or not
+    clock: SharedClock,                  // Decides what has expired; MessagingService shares its system's
+}
+
+impl StoredMessage {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    // Case-insensitive substring match on the body, an exact sender and a half-open time range;
+    // expired messages never match, even before they are purged
+    fn matches(&self, query: &str, sender_filter: Option<&str>, time_range: Option<&Range<u64>>, now: u64) -> bool {
+        !self.is_expired(now)
+            && sender_filter.is_none_or(|sender| self.sender == sender)
+            && time_range.is_none_or(|range| range.contains(&self.timestamp))
+            && self.body.to_lowercase().contains(&query.to_lowercase())
+    }
+}
+
+impl MessageStore {
+    // Judge expiry by `clock`, a handle that follows whatever clock is set on its system
+    pub(crate) fn follow_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    pub fn add(&mut self, message: StoredMessage) {
+        self.messages.push(message);
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    // Record that the message with this id was read, returning false if it already had been
+    pub fn mark_seen(&mut self, message_id: MessageId) -> bool {
+        self.seen_ids.insert(message_id)
+    }
+
+    pub fn has_seen(&self, message_id: &MessageId) -> bool {
+        self.seen_ids.contains(message_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    // Mark the message with this id as seen by the user, returning whether it was unread
+    pub fn mark_read(&mut self, message_id: &MessageId) -> bool {
+        let mut changed = false;
+        for message in self.messages.iter_mut().filter(|message| message.message_id == *message_id && !message.read) {
+            message.read = true;
+            changed = true;
+        }
+        changed
+    }
+
+    // Unexpired messages the user hasn't marked read, for the history badge
+    pub fn unread_count(&self) -> usize {
+        let now = self.clock.now_millis();
+        self.messages.iter().filter(|message| !message.read && !message.is_expired(now)).count()
+    }
+
+    // One page of matching messages; an empty query matches everything
+    pub fn search(
+        &self,
+        query: &str,
+        sender_filter: Option<&str>,
+        time_range: Option<Range<u64>>,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<&StoredMessage> {
+        let now = self.clock.now_millis();
+        self.messages
+            .iter()
+            .filter(|message| message.matches(query, sender_filter, time_range.as_ref(), now))
+            .skip(offset)
+            .take(limit)
+            .collect()
+    }
+
+    // Total matches across all pages, for deciding whether there is a next page
+    pub fn count(&self, query: &str, sender_filter: Option<&str>, time_range: Option<Range<u64>>) -> usize {
+        let now = self.clock.now_millis();
+        self.messages
+            .iter()
+            .filter(|message| message.matches(query, sender_filter, time_range.as_ref(), now))
+            .count()
+    }
+
+    // Unexpired messages from or to `peer`, oldest first
+    pub fn conversation(&self, peer: &str) -> Vec<&StoredMessage> {
+        let now = self.clock.now_millis();
+        self.messages
+            .iter()
+            .filter(|message| !message.is_expired(now) && (message.sender == peer || message.recipient == peer))
+            .collect()
+    }
+
+    // Wipe and drop the copy of a burn-after-read message, returning whether there was one
+    pub fn burn(&mut self, message_id: &MessageId) -> bool {
+        let before = self.messages.len();
+        for message in self.messages.iter_mut().filter(|message| message.message_id == *message_id) {
+            message.body.zeroize();
+        }
+        self.messages.retain(|message| message.message_id != *message_id);
+        self.messages.len() != before
+    }
+
+    // Wipe and drop every message past its expiry, returning how many went
+    pub fn purge_expired(&mut self) -> usize {
+        self.purge_expired_at(self.clock.now_millis())
+    }
+
+    fn purge_expired_at(&mut self, now: u64) -> usize {
+        self.wipe_expired(now);
+        let before = self.messages.len();
+        self.messages.retain(|message| !message.is_expired(now));
+        before - self.messages.len()
+    }
+
+    // Overwrite expired plaintext in place so none of it is left behind in freed memory
+    fn wipe_expired(&mut self, now: u64) {
+        for message in self.messages.iter_mut().filter(|message| message.is_expired(now)) {
+            message.body.zeroize();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> MessageStore {
+        let mut store = MessageStore::default();
+        for (i, (sender, body)) in [
+            ("alice", "Raid at nine"),
+            ("bob", "who is bringing potions?"),
+            ("alice", "I have the raid potions"),
+            ("carol", "late, start without me"),
+            ("alice", "gg"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            store.add(StoredMessage {
+                sender: sender.to_string(),
+                recipient: "dave".to_string(),
+                timestamp: 1_000 * (i as u64 + 1),
+                body: body.to_string(),
+                expires_at: None,
+                conversation_id: [0; 32],
+                message_id: [i as u8; 32],
+                read: false,
+            });
+        }
+        store
+    }
+
+    fn bodies(page: Vec<&StoredMessage>) -> Vec<&str> {
+        page.into_iter().map(|message| message.body.as_str()).collect()
+    }
+
+    #[test]
+    fn substring_match_ignores_case() {
+        let store = store();
+        assert_eq!(
+            bodies(store.search("RAID", None, None, 0, 10)),
+            ["Raid at nine", "I have the raid potions"]
+        );
+        assert_eq!(store.count("", None, None), store.len());
+        assert!(store.search("dragon", None, None, 0, 10).is_empty());
+    }
+
+    #[test]
+    fn sender_and_time_filters_combine() {
+        let store = store();
+        assert_eq!(
+            bodies(store.search("", Some("alice"), None, 0, 10)),
+            ["Raid at nine", "I have the raid potions", "gg"]
+        );
+        assert_eq!(bodies(store.search("potions", Some("alice"), None, 0, 10)), ["I have the raid potions"]);
+        assert_eq!(bodies(store.search("", Some("alice"), Some(2_000..5_000), 0, 10)), ["I have the raid potions"]);
+        assert!(store.search("", Some("mallory"), None, 0, 10).is_empty());
+    }
+
+    #[test]
+    fn pagination_boundaries() {
+        let store = store();
+        assert_eq!(store.search("", None, None, 0, 2).len(), 2);
+        assert_eq!(bodies(store.search("", None, None, 4, 2)), ["gg"]);
+        assert!(store.search("", None, None, 5, 2).is_empty());
+        assert!(store.search("", None, None, 0, 0).is_empty());
+
+        // Pages don't overlap and together cover every match
+        let pages: Vec<_> = (0..3).flat_map(|page| store.search("", None, None, page * 2, 2)).collect();
+        assert_eq!(pages.len(), store.count("", None, None));
+        assert_eq!(pages[2].body, "I have the raid potions");
+    }
+
+    #[test]
+    fn expired_message_wiped_and_purged() {
+        let mut store = store();
+        store.add(StoredMessage {
+            sender: "alice".to_string(),
+            recipient: "dave".to_string(),
+            timestamp: 6_000,
+            body: "burn after reading".to_string(),
+            expires_at: Some(7_000),
+            conversation_id: [0; 32],
+            message_id: [5; 32],
+            read: false,
+        });
+        assert_eq!(store.count("burn", None, None), 0);
+
+        // Nothing has expired yet at 6.999 seconds
+        assert_eq!(store.purge_expired_at(6_999), 0);
+
+        store.wipe_expired(7_000);
+        assert!(store.messages[5].body.is_empty());
+        assert!(store.messages[..5].iter().all(|message| !message.body.is_empty()));
+
+        assert_eq!(store.purge_expired_at(7_000), 1);
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.search("", None, None, 0, 10).len(), 5);
+    }
+
+    #[test]
+    fn burn_removes_only_that_message() {
+        let mut store = store();
+        assert!(store.burn(&[2; 32]));
+        assert!(!store.burn(&[2; 32]));
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.count("raid potions", None, None), 0);
+        assert_eq!(store.count("raid", None, None), 1);
+    }
+
+    #[test]
+    fn mark_read_lowers_unread_count() {
+        let mut store = store();
+        assert_eq!(store.unread_count(), 5);
+        assert!(store.mark_read(&[1; 32]));
+        assert_eq!(store.unread_count(), 4);
+
+        // Marking it again, or a message we don't have, changes nothing
+        assert!(!store.mark_read(&[1; 32]));
+        assert!(!store.mark_read(&[9; 32]));
+        assert_eq!(store.unread_count(), 4);
+    }
+}
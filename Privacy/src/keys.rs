@@ -0,0 +1,579 @@
+use crate::error::{CryptoError, DecryptError};
+use crate::signing::VerifyingKey;
+use crate::system::SymmetricKey;
+use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Key, Nonce};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{Ciphertext, EncodedSizeUser, KemCore, MlKem768, SharedKey};
+use rand::{CryptoRng, RngCore};
+use rsa::pkcs1::EncodeRsaPublicKey;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey, SharedSecret, StaticSecret};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// Domain separation for keys derived from an X25519 shared secret
+const X25519_KDF_CONTEXT: &[u8] = b"pgfi-x25519-v1";
+
+// Domain separation for keys derived from an X25519 secret shared with an Ed25519 identity
+const HPKE_KDF_CONTEXT: &[u8] = b"pgfi-hpke-ed25519-v1";
+
+// Domain separation for keys derived from the combined RSA and ML-KEM secrets
+const HYBRID_KDF_CONTEXT: &[u8] = b"pgfi-hybrid-v1";
+
+// Domain separation for blinded key ids
+const BLINDED_KEY_CONTEXT: &[u8] = b"pgfi-blinded-key-v1";
+
+// Each derived key wraps exactly one message key, so a fixed nonce is never reused
+const WRAP_NONCE: [u8; 12] = [0u8; 12];
+
+// RSA modulus sizes users may choose, smallest first
+pub const SUPPORTED_RSA_BITS: [usize; 3] = [2048, 3072, 4096];
+
+// Smallest RSA modulus we will wrap a message key under; imported contacts may hold less
+pub const MIN_RSA_BITS: usize = 2048;
+
+// Encoded ML-KEM-768 encapsulation key and ciphertext sizes
+pub(crate) const KEM_PUBLIC_LEN: usize = 1184;
+const KEM_CIPHERTEXT_LEN: usize = 1088;
+
+pub(crate) type KemEncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+pub(crate) type KemDecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+
+// Short handle for an encryption public key: the first 8 bytes of its SHA-256
+pub type KeyId = [u8; 8];
+
+// An encryption key's tag in one multi-recipient message, see EncryptionKey::blinded_key_id
+pub type BlindedKeyId = [u8; 32];
+
+// Tags for KeyExchange::to_bytes
+const RSA_TAG: u8 = 0;
+const X25519_TAG: u8 = 1;
+const HYBRID_TAG: u8 = 2;
+const HPKE_TAG: u8 = 3;
+
+// How message keys are delivered to a user
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyScheme {
+    #[default]
+    X25519,                              // Ephemeral ECDH per message, keys generate in microseconds
+    Rsa,                                 // Legacy RSA-2048 with OAEP, slow to generate
+    Hybrid,                              // RSA-OAEP and ML-KEM-768 together, safe while either one holds
+}
+
+// Parameters for newly generated encryption keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyConfig {
+    pub rsa_bits: usize,                 // Modulus size for RSA keys, one of SUPPORTED_RSA_BITS
+}
+
+impl Default for KeyConfig {
+    fn default() -> Self {
+        Self { rsa_bits: SUPPORTED_RSA_BITS[0] }
+    }
+}
+
+impl KeyConfig {
+    pub fn validate(&self) -> Result<(), CryptoError> {
+        if SUPPORTED_RSA_BITS.contains(&self.rsa_bits) {
+            Ok(())
+        } else {
+            Err(CryptoError::UnsupportedKeySize(self.rsa_bits))
+        }
+    }
+}
+
+// Public half of a user's encryption key
+#[derive(Clone, Debug, PartialEq)]
+pub enum EncryptionKey {
+    X25519(X25519PublicKey),
+    Rsa(RsaPublicKey),
+    Hybrid {
+        rsa: RsaPublicKey,
+        kem: Box<KemEncapsulationKey>,
+    },
+}
+
+// Private half, recovers message keys wrapped to the matching EncryptionKey
+pub enum DecryptionKey {
+    X25519(StaticSecret),
+    Rsa(Box<RsaPrivateKey>),
+    Hybrid {
+        rsa: Box<RsaPrivateKey>,
+        kem: Box<KemDecapsulationKey>,
+    },
+}
+
+// StaticSecret only implements Zeroize, so wipe it here; RSA and ML-KEM keys wipe themselves
+impl Drop for DecryptionKey {
+    fn drop(&mut self) {
+        if let Self::X25519(secret) = self {
+            secret.zeroize();
+        }
+    }
+}
+
+impl ZeroizeOnDrop for DecryptionKey {}
+
+// How the message key reached one recipient
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyExchange {
+    Rsa {
+        wrapped_key: Vec<u8>,            // RSA-OAEP (SHA-256) encrypted message key
+    },
+    X25519 {
+        ephemeral_public: [u8; 32],      // Sender's one-off public key for this message
+        wrapped_key: Vec<u8>,            // Message key under the HKDF-derived key
+    },
+    Hybrid {
+        rsa_wrapped: Vec<u8>,            // RSA-OAEP encrypted random secret
+        kem_ciphertext: Vec<u8>,         // ML-KEM-768 encapsulation of a second secret
+        wrapped_key: Vec<u8>,            // Message key under the key HKDF derives from both secrets
+    },
+    Hpke {
+        ephemeral_public: [u8; 32],      // Sender's one-off public key for this message
+        wrapped_key: Vec<u8>,            // Message key under a key derived with the recipient's Ed25519 identity as X25519
+    },
+}
+
+// AES key derived from an ECDH shared secret, bound to both public keys
+fn x25519_wrapping_cipher(context: &[u8], shared: &SharedSecret, ephemeral_public: &[u8; 32], recipient: &X25519PublicKey) -> Aes256Gcm {
+    let mut info = context.to_vec();
+    info.extend_from_slice(ephemeral_public);
+    info.extend_from_slice(recipient.as_bytes());
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, shared.as_bytes())
+        .expand(&info, key.as_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_ref()))
+}
+
+// AES key derived from both hybrid secrets, bound to both encapsulations and the recipient's ML-KEM key;
+// breaking either RSA or ML-KEM alone leaves the other secret unknown
+fn hybrid_wrapping_cipher(
+    rsa_secret: &[u8],
+    mut kem_shared: SharedKey<MlKem768>,
+    rsa_wrapped: &[u8],
+    kem_ciphertext: &[u8],
+    recipient: &KemEncapsulationKey,
+) -> Aes256Gcm {
+    let mut secret = Zeroizing::new(vec![0u8; rsa_secret.len() + kem_shared.len()]);
+    secret[..rsa_secret.len()].copy_from_slice(rsa_secret);
+    secret[rsa_secret.len()..].copy_from_slice(&kem_shared);
+    kem_shared.as_mut_slice().zeroize();
+
+    let mut info = HYBRID_KDF_CONTEXT.to_vec();
+    info.extend_from_slice(kem_ciphertext);
+    info.extend_from_slice(rsa_wrapped);
+    info.extend_from_slice(&recipient.as_bytes());
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, &secret)
+        .expand(&info, key.as_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_ref()))
+}
+
+// X25519 public key on the Montgomery curve birationally equivalent to `identity`'s Edwards curve.
+// The matching secret is DecryptionKey::from_ed25519_secret. None for a point that isn't on the
+// curve or has small order, whose shared secrets an attacker could predict.
+fn ed25519_to_x25519(identity: &VerifyingKey) -> Option<X25519PublicKey> {
+    match identity {
+        VerifyingKey::Ed25519(public) => {
+            let point = CompressedEdwardsY(public.to_bytes()).decompress()?;
+            if point.is_small_order() {
+                return None;
+            }
+            Some(X25519PublicKey::from(point.to_montgomery().to_bytes()))
+        }
+    }
+}
+
+impl EncryptionKey {
+    // X25519 form of an Ed25519 identity key, for contacts who share only that key
+    pub(crate) fn from_ed25519(identity: &VerifyingKey) -> Option<Self> {
+        ed25519_to_x25519(identity).map(Self::X25519)
+    }
+
+    // RSA keys below MIN_RSA_BITS, hybrid ones included; X25519 keys are never weak
+    pub fn weak_rsa_bits(&self) -> Option<usize> {
+        match self {
+            Self::Rsa(public) | Self::Hybrid { rsa: public, .. } if public.size() * 8 < MIN_RSA_BITS => Some(public.size() * 8),
+            _ => None,
+        }
+    }
+
+    pub fn scheme(&self) -> KeyScheme {
+        match self {
+            Self::X25519(_) => KeyScheme::X25519,
+            Self::Rsa(_) => KeyScheme::Rsa,
+            Self::Hybrid { .. } => KeyScheme::Hybrid,
+        }
+    }
+
+    // Bytes that identify this key in a fingerprint
+    pub(crate) fn fingerprint_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::X25519(public) => public.as_bytes().to_vec(),
+            Self::Rsa(public) => public
+                .to_public_key_der()
+                .map(|der| der.as_bytes().to_vec())
+                .unwrap_or_default(),
+            Self::Hybrid { rsa, kem } => {
+                let mut bytes = rsa.to_pkcs1_der().map(|der| der.as_bytes().to_vec()).unwrap_or_default();
+                bytes.extend_from_slice(&kem.as_bytes());
+                bytes
+            }
+        }
+    }
+
+    pub fn key_id(&self) -> KeyId {
+        let digest = Sha256::digest(self.fingerprint_bytes());
+        let mut key_id = [0u8; 8];
+        key_id.copy_from_slice(&digest[..8]);
+        key_id
+    }
+
+    // Tags a wrapped key in a multi-recipient message: HMAC-SHA256 of the key under the message's
+    // salt. Someone holding a key can find its slot, but the slots don't name the recipients,
+    // and the same key gets unrelated tags in different messages.
+    pub fn blinded_key_id(&self, salt: &[u8; 16]) -> BlindedKeyId {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(salt).expect("HMAC takes any key length");
+        mac.update(BLINDED_KEY_CONTEXT);
+        mac.update(&self.fingerprint_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    // Deliver a message key to the holder of the matching DecryptionKey
+    pub(crate) fn wrap<R: RngCore + CryptoRng>(&self, symmetric_key: &SymmetricKey, csprng: &mut R) -> Result<KeyExchange, CryptoError> {
+        if let Some(bits) = self.weak_rsa_bits() {
+            return Err(CryptoError::WeakRecipientKey(bits));
+        }
+        match self {
+            Self::Rsa(public) => {
+                let wrapped_key = public
+                    .encrypt(csprng, Oaep::new::<Sha256>(), symmetric_key.as_bytes())
+                    .map_err(|_| CryptoError::InvalidKey)?;
+                Ok(KeyExchange::Rsa { wrapped_key })
+            }
+            Self::X25519(recipient) => {
+                let ephemeral = EphemeralSecret::random_from_rng(csprng);
+                let ephemeral_public = X25519PublicKey::from(&ephemeral).to_bytes();
+                let shared = ephemeral.diffie_hellman(recipient);
+                if !shared.was_contributory() {
+                    return Err(CryptoError::InvalidKey);
+                }
+                let wrapped_key = x25519_wrapping_cipher(X25519_KDF_CONTEXT, &shared, &ephemeral_public, recipient)
+                    .encrypt(Nonce::from_slice(&WRAP_NONCE), symmetric_key.as_bytes())
+                    .map_err(|_| CryptoError::Encryption)?;
+                Ok(KeyExchange::X25519 { ephemeral_public, wrapped_key })
+            }
+            Self::Hybrid { rsa, kem } => {
+                let mut rsa_secret = Zeroizing::new([0u8; 32]);
+                csprng.fill_bytes(rsa_secret.as_mut());
+                let rsa_wrapped = rsa
+                    .encrypt(csprng, Oaep::new::<Sha256>(), rsa_secret.as_ref())
+                    .map_err(|_| CryptoError::InvalidKey)?;
+                let (kem_ciphertext, kem_shared) = kem.encapsulate(csprng).map_err(|_| CryptoError::Encryption)?;
+                let wrapped_key = hybrid_wrapping_cipher(rsa_secret.as_ref(), kem_shared, &rsa_wrapped, &kem_ciphertext, kem)
+                    .encrypt(Nonce::from_slice(&WRAP_NONCE), symmetric_key.as_bytes())
+                    .map_err(|_| CryptoError::Encryption)?;
+                Ok(KeyExchange::Hybrid {
+                    rsa_wrapped,
+                    kem_ciphertext: kem_ciphertext.to_vec(),
+                    wrapped_key,
+                })
+            }
+        }
+    }
+
+    // Deliver a message key to the holder of `identity`'s signing key, in one pass: ephemeral
+    // X25519 against the identity's Montgomery form, then HKDF and AES-GCM as for X25519 keys
+    pub(crate) fn wrap_hpke<R: RngCore + CryptoRng>(identity: &VerifyingKey, symmetric_key: &SymmetricKey, csprng: &mut R) -> Result<KeyExchange, CryptoError> {
+        let recipient = ed25519_to_x25519(identity).ok_or(CryptoError::InvalidKey)?;
+        let ephemeral = EphemeralSecret::random_from_rng(csprng);
+        let ephemeral_public = X25519PublicKey::from(&ephemeral).to_bytes();
+        let shared = ephemeral.diffie_hellman(&recipient);
+        if !shared.was_contributory() {
+            return Err(CryptoError::InvalidKey);
+        }
+        let wrapped_key = x25519_wrapping_cipher(HPKE_KDF_CONTEXT, &shared, &ephemeral_public, &recipient)
+            .encrypt(Nonce::from_slice(&WRAP_NONCE), symmetric_key.as_bytes())
+            .map_err(|_| CryptoError::Encryption)?;
+        Ok(KeyExchange::Hpke { ephemeral_public, wrapped_key })
+    }
+}
+
+impl DecryptionKey {
+    // Fresh key for the given scheme
+    pub(crate) fn generate<R: RngCore + CryptoRng>(scheme: KeyScheme, config: KeyConfig, csprng: &mut R) -> Result<Self, CryptoError> {
+        config.validate()?;
+        match scheme {
+            KeyScheme::X25519 => {
+                let mut secret = Zeroizing::new([0u8; 32]);
+                csprng.fill_bytes(secret.as_mut());
+                Ok(Self::X25519(StaticSecret::from(*secret)))
+            }
+            KeyScheme::Rsa => RsaPrivateKey::new(csprng, config.rsa_bits)
+                .map(|private| Self::Rsa(Box::new(private)))
+                .map_err(|_| CryptoError::KeyGeneration),
+            KeyScheme::Hybrid => {
+                let rsa = RsaPrivateKey::new(csprng, config.rsa_bits).map_err(|_| CryptoError::KeyGeneration)?;
+                let (kem, _) = MlKem768::generate(csprng);
+                Ok(Self::Hybrid {
+                    rsa: Box::new(rsa),
+                    kem: Box::new(kem),
+                })
+            }
+        }
+    }
+
+    // X25519 form of an Ed25519 signing key: the scalar Ed25519 signs with, the first half of
+    // SHA-512 over the seed, so it pairs with EncryptionKey::from_ed25519 of the public key
+    pub(crate) fn from_ed25519_secret(seed: &[u8]) -> Option<Self> {
+        if seed.len() != 32 {
+            return None;
+        }
+        let mut hash = Sha512::digest(seed);
+        let mut scalar = Zeroizing::new([0u8; 32]);
+        scalar.copy_from_slice(&hash[..32]);
+        hash.as_mut_slice().zeroize();
+        Some(Self::X25519(StaticSecret::from(*scalar)))
+    }
+
+    pub fn scheme(&self) -> KeyScheme {
+        match self {
+            Self::X25519(_) => KeyScheme::X25519,
+            Self::Rsa(_) => KeyScheme::Rsa,
+            Self::Hybrid { .. } => KeyScheme::Hybrid,
+        }
+    }
+
+    // Config that regenerates a key of the same size
+    pub fn config(&self) -> KeyConfig {
+        match self {
+            Self::X25519(_) => KeyConfig::default(),
+            Self::Rsa(private) | Self::Hybrid { rsa: private, .. } => KeyConfig { rsa_bits: private.size() * 8 },
+        }
+    }
+
+    pub fn encryption_key(&self) -> EncryptionKey {
+        match self {
+            Self::X25519(secret) => EncryptionKey::X25519(X25519PublicKey::from(secret)),
+            Self::Rsa(private) => EncryptionKey::Rsa(private.to_public_key()),
+            Self::Hybrid { rsa, kem } => EncryptionKey::Hybrid {
+                rsa: rsa.to_public_key(),
+                kem: Box::new(kem.encapsulation_key().clone()),
+            },
+        }
+    }
+
+    // Recover the message key; any failure means it was delivered to a different key
+    pub(crate) fn unwrap(&self, exchange: &KeyExchange) -> Result<SymmetricKey, DecryptError> {
+        let unwrapped = match (self, exchange) {
+            (Self::Rsa(private), KeyExchange::Rsa { wrapped_key }) => private
+                .decrypt(Oaep::new::<Sha256>(), wrapped_key)
+                .map_err(|_| DecryptError::WrongRecipient)?,
+            (Self::X25519(secret), KeyExchange::X25519 { ephemeral_public, wrapped_key }) => {
+                x25519_unwrap(X25519_KDF_CONTEXT, secret, ephemeral_public, wrapped_key)?
+            }
+            // Only a key from from_ed25519_secret can match, but any X25519 key may try
+            (Self::X25519(secret), KeyExchange::Hpke { ephemeral_public, wrapped_key }) => {
+                x25519_unwrap(HPKE_KDF_CONTEXT, secret, ephemeral_public, wrapped_key)?
+            }
+            // ML-KEM rejects a bad ciphertext implicitly with a garbage secret, so tampering surfaces here as a failed unwrap
+            (Self::Hybrid { rsa, kem }, KeyExchange::Hybrid { rsa_wrapped, kem_ciphertext, wrapped_key }) => {
+                let rsa_secret = Zeroizing::new(
+                    rsa.decrypt(Oaep::new::<Sha256>(), rsa_wrapped)
+                        .map_err(|_| DecryptError::WrongRecipient)?,
+                );
+                let ciphertext = Ciphertext::<MlKem768>::try_from(kem_ciphertext.as_slice()).map_err(|_| DecryptError::WrongRecipient)?;
+                let kem_shared = kem.decapsulate(&ciphertext).map_err(|_| DecryptError::WrongRecipient)?;
+                hybrid_wrapping_cipher(&rsa_secret, kem_shared, rsa_wrapped, kem_ciphertext, kem.encapsulation_key())
+                    .decrypt(Nonce::from_slice(&WRAP_NONCE), wrapped_key.as_ref())
+                    .map_err(|_| DecryptError::WrongRecipient)?
+            }
+            _ => return Err(DecryptError::WrongRecipient),
+        };
+        SymmetricKey::from_bytes(&Zeroizing::new(unwrapped))
+    }
+}
+
+fn x25519_unwrap(context: &[u8], secret: &StaticSecret, ephemeral_public: &[u8; 32], wrapped_key: &[u8]) -> Result<Vec<u8>, DecryptError> {
+    let shared = secret.diffie_hellman(&X25519PublicKey::from(*ephemeral_public));
+    if !shared.was_contributory() {
+        return Err(DecryptError::WrongRecipient);
+    }
+    x25519_wrapping_cipher(context, &shared, ephemeral_public, &X25519PublicKey::from(secret))
+        .decrypt(Nonce::from_slice(&WRAP_NONCE), wrapped_key)
+        .map_err(|_| DecryptError::WrongRecipient)
+}
+
+impl KeyExchange {
+    pub fn scheme(&self) -> KeyScheme {
+        match self {
+            Self::Rsa { .. } => KeyScheme::Rsa,
+            Self::X25519 { .. } | Self::Hpke { .. } => KeyScheme::X25519,
+            Self::Hybrid { .. } => KeyScheme::Hybrid,
+        }
+    }
+
+    // Tagged binary form for envelopes and stream headers
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Rsa { wrapped_key } => {
+                let mut bytes = vec![RSA_TAG];
+                bytes.extend_from_slice(wrapped_key);
+                bytes
+            }
+            Self::X25519 { ephemeral_public, wrapped_key } | Self::Hpke { ephemeral_public, wrapped_key } => {
+                let tag = if matches!(self, Self::Hpke { .. }) { HPKE_TAG } else { X25519_TAG };
+                let mut bytes = vec![tag];
+                bytes.extend_from_slice(ephemeral_public);
+                bytes.extend_from_slice(wrapped_key);
+                bytes
+            }
+            // The ML-KEM ciphertext has a fixed size; the RSA one depends on the modulus, so it is length-prefixed
+            Self::Hybrid { rsa_wrapped, kem_ciphertext, wrapped_key } => {
+                let mut bytes = vec![HYBRID_TAG];
+                bytes.extend_from_slice(kem_ciphertext);
+                bytes.extend_from_slice(&(rsa_wrapped.len() as u16).to_be_bytes());
+                bytes.extend_from_slice(rsa_wrapped);
+                bytes.extend_from_slice(wrapped_key);
+                bytes
+            }
+        }
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.split_first()? {
+            (&RSA_TAG, wrapped_key) => Some(Self::Rsa {
+                wrapped_key: wrapped_key.to_vec(),
+            }),
+            (&X25519_TAG, rest) if rest.len() >= 32 => {
+                let (ephemeral_public, wrapped_key) = rest.split_at(32);
+                Some(Self::X25519 {
+                    ephemeral_public: ephemeral_public.try_into().ok()?,
+                    wrapped_key: wrapped_key.to_vec(),
+                })
+            }
+            (&HPKE_TAG, rest) if rest.len() >= 32 => {
+                let (ephemeral_public, wrapped_key) = rest.split_at(32);
+                Some(Self::Hpke {
+                    ephemeral_public: ephemeral_public.try_into().ok()?,
+                    wrapped_key: wrapped_key.to_vec(),
+                })
+            }
+            (&HYBRID_TAG, rest) if rest.len() >= KEM_CIPHERTEXT_LEN + 2 => {
+                let (kem_ciphertext, rest) = rest.split_at(KEM_CIPHERTEXT_LEN);
+                let (rsa_len, rest) = rest.split_at(2);
+                let rsa_len = u16::from_be_bytes(rsa_len.try_into().ok()?) as usize;
+                if rest.len() < rsa_len {
+                    return None;
+                }
+                let (rsa_wrapped, wrapped_key) = rest.split_at(rsa_len);
+                Some(Self::Hybrid {
+                    rsa_wrapped: rsa_wrapped.to_vec(),
+                    kem_ciphertext: kem_ciphertext.to_vec(),
+                    wrapped_key: wrapped_key.to_vec(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selftest::hex;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn wrap_unwrap_round_trip_for_each_scheme() {
+        for scheme in [KeyScheme::X25519, KeyScheme::Rsa, KeyScheme::Hybrid] {
+            let private = DecryptionKey::generate(scheme, KeyConfig::default(), &mut OsRng).expect("generate");
+            let symmetric_key = SymmetricKey::generate(&mut OsRng);
+
+            let exchange = private.encryption_key().wrap(&symmetric_key, &mut OsRng).expect("wrap");
+            let unwrapped = private.unwrap(&exchange).expect("unwrap");
+            assert_eq!(unwrapped.as_bytes(), symmetric_key.as_bytes());
+            assert_eq!(KeyExchange::from_bytes(&exchange.to_bytes()), Some(exchange));
+        }
+    }
+
+    #[test]
+    fn ed25519_secret_matches_converted_public_key() {
+        // RFC 8032 test 1
+        let seed = hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let public = hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let identity = VerifyingKey::from_bytes(&public).expect("public key");
+
+        let secret = DecryptionKey::from_ed25519_secret(&seed).expect("secret");
+        assert_eq!(Some(secret.encryption_key()), EncryptionKey::from_ed25519(&identity));
+
+        let symmetric_key = SymmetricKey::generate(&mut OsRng);
+        let exchange = EncryptionKey::wrap_hpke(&identity, &symmetric_key, &mut OsRng).expect("wrap");
+        assert_eq!(secret.unwrap(&exchange).expect("unwrap").as_bytes(), symmetric_key.as_bytes());
+        assert_eq!(KeyExchange::from_bytes(&exchange.to_bytes()), Some(exchange));
+    }
+
+    #[test]
+    fn small_order_identity_refused() {
+        let mut identity = [0u8; 32];
+        identity[0] = 1;                 // The Edwards identity point
+        let identity = VerifyingKey::from_bytes(&identity).expect("public key");
+        assert_eq!(EncryptionKey::from_ed25519(&identity), None);
+    }
+
+    #[test]
+    fn each_x25519_wrap_uses_fresh_ephemeral_key() {
+        let private = DecryptionKey::generate(KeyScheme::X25519, KeyConfig::default(), &mut OsRng).expect("generate");
+        let symmetric_key = SymmetricKey::generate(&mut OsRng);
+        let first = private.encryption_key().wrap(&symmetric_key, &mut OsRng).expect("wrap");
+        let second = private.encryption_key().wrap(&symmetric_key, &mut OsRng).expect("wrap");
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn corrupt_kem_ciphertext_fails_hybrid_unwrap() {
+        let private = DecryptionKey::generate(KeyScheme::Hybrid, KeyConfig::default(), &mut OsRng).expect("generate");
+        let mut exchange = private.encryption_key().wrap(&SymmetricKey::generate(&mut OsRng), &mut OsRng).expect("wrap");
+        let KeyExchange::Hybrid { kem_ciphertext, .. } = &mut exchange else {
+            panic!("expected a hybrid key exchange");
+        };
+        kem_ciphertext[0] ^= 1;
+        assert!(matches!(private.unwrap(&exchange), Err(DecryptError::WrongRecipient)));
+    }
+
+    #[test]
+    fn rsa_key_size_is_configurable() {
+        let config = KeyConfig { rsa_bits: 4096 };
+        let private = DecryptionKey::generate(KeyScheme::Rsa, config, &mut OsRng).expect("generate");
+        assert_eq!(private.config(), config);
+        let exchange = private.encryption_key().wrap(&SymmetricKey::generate(&mut OsRng), &mut OsRng).expect("wrap");
+        assert!(private.unwrap(&exchange).is_ok());
+
+        for rsa_bits in [0, 1024, 2047, 8192] {
+            assert_eq!(
+                DecryptionKey::generate(KeyScheme::Rsa, KeyConfig { rsa_bits }, &mut OsRng).err(),
+                Some(CryptoError::UnsupportedKeySize(rsa_bits))
+            );
+        }
+    }
+
+    #[test]
+    fn mismatched_scheme_is_wrong_recipient() {
+        let x25519 = DecryptionKey::generate(KeyScheme::X25519, KeyConfig::default(), &mut OsRng).expect("generate");
+        let rsa = DecryptionKey::generate(KeyScheme::Rsa, KeyConfig::default(), &mut OsRng).expect("generate");
+        let exchange = rsa.encryption_key().wrap(&SymmetricKey::generate(&mut OsRng), &mut OsRng).expect("wrap");
+        assert!(matches!(x25519.unwrap(&exchange), Err(DecryptError::WrongRecipient)));
+    }
+}
@@ -1,43 +1,67 @@
-use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
-use rand::rngs::OsRng;
-use rsa::{PaddingScheme, PublicKey as RsaPublicKey, RsaPrivateKey, RsaPublicKey as _};
-use std::collections::HashMap;
-use eframe::egui;
-use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng as AesOsRng},
-    Aes256Gcm,
-    Nonce,
-};
-use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+//! Hybrid-encrypted, signed messaging between users.
+//!
+//! Messages are encrypted with AES-256-GCM under a fresh key, which is delivered to each
+//! recipient through an ephemeral X25519 exchange (or legacy RSA-OAEP) and signed by the
+//! sender with Ed25519. The GUI in main.rs is one front end for this library; it builds
+//! only with the `gui` feature. On `wasm32` the `wasm` module wraps encryption for browsers.
 
+pub mod anchor;
+pub mod armor;
+pub mod audit;
+pub mod burn;
+pub mod clock;
+pub mod contact;
+pub mod ct;
+pub mod detached;
+pub mod error;
+pub mod group;
+pub mod history;
+pub mod keys;
+pub mod keystore;
+pub mod message;
+pub mod mnemonic;
+pub mod notice;
+pub mod outbox;
+pub mod qr;
+pub mod receipt;
+pub mod revocation;
+pub mod selftest;
+pub mod service;
+pub mod session;
+pub mod signing;
+pub mod stream;
+pub mod system;
+pub mod tofu;
+pub mod user;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
 
-
-// Main application state
-struct SignatureApp {
-    users: HashMap<String, User>,
-    current_user: Option<String>,
-    recipient: String,
-    message: String,
-    encrypted_messages: Vec<(String, EncryptedMessage)>,
-    decrypted_messages: Vec<(String, String)>,
-    new_username: String,
-    new_userCount: String,
-}
-
-impl Default for SignatureApp {
-    fn default() -> Self {
-        Self {
-            users: HashMap::new(),
-            current_user: None,
-            recipient: String::new(),
-            message: String::new(),
-            encrypted_messages: Vec::new(),
-            decrypted_messages: Vec::new(),
-            new_username: String::new(),
-            new_userCount: String::new(),
-        }
-    }
-}
-
-
-   
\ No newline at end of file
+pub use anchor::{AnchorClient, AnchorQueue, AnchorReceipt, ChainAnchor, RetryPolicy};
+pub use armor::{armor, dearmor};
+pub use audit::{AuditEntry, AuditLog};
+pub use burn::BurnedSet;
+pub use clock::{Clock, FixedClock, ManualClock, SystemClock};
+pub use contact::{import_public_contact, import_rsa_public_spki_pem, import_ssh_ed25519, safety_number, Contact, IdentityRecipient, RecipientKeys};
+pub use ct::ct_eq;
+pub use detached::{read_signature_file, signature_path, verify_file, write_signature_file};
+pub use error::{AnchorError, ArmorError, CreateError, CryptoError, DecryptError, ImportError, KeystoreError, SelfTestError, ServiceError, TofuWarning, TransportError, WireError};
+pub use group::{Group, GroupMessage};
+pub use history::{MessageStore, StoredMessage};
+pub use keys::{BlindedKeyId, DecryptionKey, EncryptionKey, KeyConfig, KeyExchange, KeyId, KeyScheme, MIN_RSA_BITS, SUPPORTED_RSA_BITS};
+pub use keystore::{import_conversation, import_identity, import_rsa_private_pkcs8_pem, Argon2Params, FileBackend, KeyBackend, Keystore, KeystoreFile};
+pub use message::{AlgorithmSuite, AnonymousMessage, BatchEntry, BatchMessage, Cipher, CompressionAlgo, ContentType, DecryptedText, EncryptedMessage, MessageId, MultiRecipientMessage, PaddingMode, SignMode, SuiteComponent, WrappedKey, DEFAULT_MAX_MESSAGE_BYTES, INVALID_TEXT_BADGE, LEGACY_PKCS1_VERSION, MESSAGE_VERSION, NO_CONVERSATION};
+pub use mnemonic::generate_mnemonic;
+pub use notice::SignedMessage;
+pub use outbox::{DeliveryStatus, Outbox, OutboxEntry, Transport};
+pub use receipt::Receipt;
+pub use revocation::{RevocationCert, RevocationReason, RevocationStore};
+pub use selftest::run_self_test;
+pub use service::{MessagingService, ReceiveOutcome, Received};
+pub use session::{Session, SessionHeader, SessionMessage};
+pub use signing::{Ed25519, MessageSignature, SignatureAlgorithm, SignatureScheme, Signer, SigningKey, VerifyingKey};
+pub use stream::{Progress, StreamControl, CHUNK_SIZE};
+pub use system::{MessagePolicy, Rewrap, SecureRng, SignatureSystem};
+pub use tofu::{TofuStore, TrustPolicy};
+pub use user::{conversation_id, key_fingerprint, ConversationId, CreateThrottle, PendingUser, RetiredKey, User};
+#[cfg(target_arch = "wasm32")]
+pub use wasm::Messenger;
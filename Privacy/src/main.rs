@@ -1,234 +1,838 @@
-use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
-use rand::rngs::OsRng;
-use rsa::{PaddingScheme, PublicKey as RsaPublicKey, RsaPrivateKey, RsaPublicKey as _};
-use std::collections::HashMap;
-use eframe::egui;
-use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng as AesOsRng},
-    Aes256Gcm,
-    Nonce,
-};
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use digital_signature_system::{generate_mnemonic, keystore, run_self_test, ArmorError, BurnedSet, Cipher, Contact, ConversationId, CreateThrottle, ContentType, CreateError, CryptoError, DecryptError, ImportError, EncryptedMessage, KeyScheme, MessagingService, MultiRecipientMessage, PaddingMode, PendingUser, ReceiveOutcome, RecipientKeys, safety_number, SelfTestError, ServiceError, SignatureSystem, SignMode, StoredMessage, StreamControl, TofuWarning, TrustPolicy, User, WireError, NO_CONVERSATION, SUPPORTED_RSA_BITS};
+use eframe::egui;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+// Minimum gap between starting two user creations
+const USER_CREATE_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(1);
+
+// How long keys may sit unused before they are wiped, once a keystore can bring them back
+const IDLE_LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
 
-// Structure to hold user information
-#[derive(Clone)]
-struct User {
+// Messages shown per history page
+const HISTORY_PAGE_SIZE: usize = 20;
+
+// Choices for how long read messages are kept, in millis
+const MESSAGE_LIFETIMES: [(&str, Option<u64>); 4] = [
+    ("Never", None),
+    ("1 minute", Some(60_000)),
+    ("1 hour", Some(60 * 60_000)),
+    ("1 day", Some(24 * 60 * 60_000)),
+];
+
+// File being encrypted to disk on a worker thread. The sender's keys travel with it,
+// since User can't be shared with the UI thread, and come back when it ends.
+struct PendingStream {
     username: String,
-    keypair: Keypair,                    // For signatures
-    rsa_private: RsaPrivateKey,          // For encryption
-    rsa_public: RsaPublicKey,            // For encryption
+    output: String,
+    total: u64,
+    done: Arc<AtomicU64>,                                         // Plaintext bytes encrypted so far
+    cancel: Arc<AtomicBool>,
+    receiver: Receiver<(User, Result<(), CryptoError>)>,
 }
 
-// Structure to hold an encrypted message
-#[derive(Clone)]
-struct EncryptedMessage {
-    encrypted_data: Vec<u8>,             // The encrypted message
-    signature: Signature,                // Signature of the original message
-    sender_public: PublicKey,            // Sender's public key for verification
-    symmetric_key: Vec<u8>,              // Encrypted symmetric key
-    nonce: Vec<u8>,                      // Nonce for AES-GCM
+impl PendingStream {
+    // `total` is the input file's length, for the progress bar
+    fn spawn(user: User, recipient: Contact, input: String, total: u64) -> Self {
+        let output = format!("{}.pgst", input);
+        let done = Arc::new(AtomicU64::new(0));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let stream = Self {
+            username: user.username.clone(),
+            output: output.clone(),
+            total,
+            done: Arc::clone(&done),
+            cancel: Arc::clone(&cancel),
+            receiver,
+        };
+
+        thread::spawn(move || {
+            let result = File::open(&input)
+                .and_then(|reader| Ok((reader, File::create(&output)?)))
+                .map_err(|err| CryptoError::Io(err.to_string()))
+                .and_then(|(reader, writer)| {
+                    let control = StreamControl {
+                        total,
+                        progress: &mut |bytes_done, _| done.store(bytes_done, Ordering::Relaxed),
+                        cancel: &cancel,
+                    };
+                    SignatureSystem::default().encrypt_stream_with(&user, &recipient, BufReader::new(reader), BufWriter::new(writer), control)
+                });
+            // A cancelled or failed stream leaves nothing behind that looks like a finished file
+            if result.is_err() {
+                let _ = fs::remove_file(&output);
+            }
+            let _ = sender.send((user, result));
+        });
+        stream
+    }
+
+    fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.done.load(Ordering::Relaxed) as f32 / self.total as f32
+    }
+
+    // The user and outcome once the thread has finished
+    fn poll(&self) -> Option<(User, Result<(), CryptoError>)> {
+        match self.receiver.try_recv() {
+            Ok(finished) => Some(finished),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
 }
 
 // Main application state
+#[derive(Default)]
 struct SignatureApp {
-    users: HashMap<String, User>,
-    current_user: Option<String>,
+    service: MessagingService,                                    // Users, the signed-in user and history
     recipient: String,
     message: String,
+    burn_after_read: bool,                                        // Send the next message as readable once
     encrypted_messages: Vec<(String, EncryptedMessage)>,
-    decrypted_messages: Vec<(String, String)>,
+    history_query: String,
+    history_page: usize,
     new_username: String,
-}
-
-impl Default for SignatureApp {
-    fn default() -> Self {
-        Self {
-            users: HashMap::new(),
-            current_user: None,
-            recipient: String::new(),
-            message: String::new(),
-            encrypted_messages: Vec::new(),
-            decrypted_messages: Vec::new(),
-            new_username: String::new(),
-        }
-    }
+    status: String,
+    last_sent_json: String,
+    last_sent_armored: String,                                    // Armored wire form of the same message
+    import_json: String,
+    paste_base64: String,
+    keystore_path: String,
+    keystore_passphrase: String,
+    unlock_passphrase: String,                                    // Entered on the lock screen
+    party_messages: Vec<(HashSet<String>, MultiRecipientMessage)>, // Fingerprints yet to read it, message
+    recipient_pem: String,
+    contact_name: String,
+    key_change: Option<TofuWarning>,                              // Changed contact keys awaiting confirmation
+    attachment_path: String,
+    attachments: Vec<(String, Vec<u8>)>,                          // Sender, decrypted file contents
+    mnemonic: String,
+    qr_texture: Option<(String, egui::TextureHandle)>,           // Username the QR belongs to, image
+    pending_users: Vec<PendingUser>,                              // Key generation still running
+    pending_stream: Option<PendingStream>,                        // File encryption still running
+    self_test_failure: Option<SelfTestError>,                     // Set at launch if the crypto can't be trusted
 }
 
 impl SignatureApp {
-    // Create a new user with keypair
-    fn create_user(&mut self, username: String) {
-        let mut csprng = OsRng;
-        
-        // Generate Ed25519 keypair for signatures
-        let keypair = Keypair::generate(&mut csprng);
-        
-        // Generate RSA keypair for encryption
-        let rsa_private = RsaPrivateKey::new(&mut csprng, 2048).expect("Failed to generate RSA key");
-        let rsa_public = rsa_private.to_public_key();
-        
-        let user = User {
-            username: username.clone(),
-            keypair,
-            rsa_private,
-            rsa_public,
-        };
-        
-        self.users.insert(username, user);
+    // Burned messages are remembered next to the keystore, or a reload would make them readable again
+    fn burned_path(&self) -> String {
+        format!("{}.burned", self.keystore_path)
     }
-    
-    // Encrypt and sign a message
-    fn encrypt_message(&self, sender: &User, recipient: &User, message: &str) -> EncryptedMessage {
-        // Generate a random symmetric key
-        let symmetric_key = Aes256Gcm::generate_key(&mut AesOsRng);
-        
-        // Create cipher
-        let cipher = Aes256Gcm::new(&symmetric_key);
-        let nonce = Aes256Gcm::generate_nonce(&mut AesOsRng);
-        
-        // Encrypt the message using AES-GCM
-        let encrypted_data = cipher
-            .encrypt(&nonce, message.as_bytes().as_ref())
-            .expect("Encryption failed");
-        
-        // Sign the original message
-        let signature = sender.keypair.sign(message.as_bytes());
-        
-        // Encrypt the symmetric key with recipient's RSA public key
-        let padding = PaddingScheme::new_pkcs1v15_encrypt();
-        let encrypted_symmetric_key = recipient
-            .rsa_public
-            .encrypt(&mut OsRng, padding, &symmetric_key)
-            .expect("Failed to encrypt symmetric key");
-        
-        EncryptedMessage {
-            encrypted_data,
-            signature,
-            sender_public: sender.keypair.public,
-            symmetric_key: encrypted_symmetric_key,
-            nonce: nonce.to_vec(),
-        }
+
+    fn save_burned(&self) -> Result<(), String> {
+        let json = self.service.system.burned().to_json().map_err(|err| err.to_string())?;
+        fs::write(self.burned_path(), json).map_err(|err| err.to_string())
     }
-    
-    // Decrypt and verify a message
-    fn decrypt_message(&self, recipient: &User, message: &EncryptedMessage) -> Option<String> {
-        // Decrypt the symmetric key using recipient's private key
-        let padding = PaddingScheme::new_pkcs1v15_encrypt();
-        let symmetric_key = recipient
-            .rsa_private
-            .decrypt(padding, &message.symmetric_key)
-            .ok()?;
-        
-        // Create cipher
-        let cipher = Aes256Gcm::new_from_slice(&symmetric_key).ok()?;
-        let nonce = Nonce::from_slice(&message.nonce);
-        
-        // Decrypt the message
-        let decrypted_data = cipher
-            .decrypt(nonce, message.encrypted_data.as_ref())
-            .ok()?;
-        
-        let decrypted_message = String::from_utf8(decrypted_data).ok()?;
-        
-        // Verify the signature
-        message
-            .sender_public
-            .verify(
-                decrypted_message.as_bytes(),
-                &message.signature,
-            )
-            .ok()?;
-        
-
-        Some(decrypted_message)
+
+    fn load_burned(&mut self) -> Result<(), String> {
+        let json = match fs::read_to_string(self.burned_path()) {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.to_string()),
+        };
+        let loaded = BurnedSet::from_json(&json).map_err(|err| err.to_string())?;
+        self.service.system.burned().extend(loaded);
+        Ok(())
     }
 }
 
 impl eframe::App for SignatureApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // A build that fails its known-answer tests must not touch any keys or messages
+        if let Some(err) = &self.self_test_failure {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.heading("Cryptography self-test failed");
+                ui.colored_label(egui::Color32::RED, err.to_string());
+                ui.label("This build can't be trusted to protect messages. Reinstall it from a trusted source.");
+            });
+            return;
+        }
+
+        // Wipe keys left unattended; the passphrase has to be typed again to get them back
+        if self.service.system.lock_if_idle() {
+            self.keystore_passphrase.clear();
+            self.status = "Keys were locked after inactivity".to_string();
+        }
+        if self.service.system.is_locked() {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.heading("Keys locked");
+                for (username, contact) in self.service.system.locked_users().into_iter().flatten() {
+                    ui.label(format!("{}  [{}]", username, contact.fingerprint()));
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Passphrase for ");
+                    ui.monospace(&self.keystore_path);
+                    ui.add(egui::TextEdit::singleline(&mut self.unlock_passphrase).password(true));
+                    if ui.button("Unlock").clicked() {
+                        let passphrase = std::mem::take(&mut self.unlock_passphrase);
+                        self.status = match self.service.system.unlock(Path::new(&self.keystore_path), &passphrase) {
+                            Ok(()) => "Unlocked".to_string(),
+                            Err(err) => format!("Could not unlock: {}", err),
+                        };
+                    }
+                });
+                ui.label(&self.status);
+            });
+            return;
+        }
+        if self.service.system.idle_timeout.is_some() {
+            ctx.request_repaint_after(std::time::Duration::from_secs(1));
+        }
+
+        // Pick up users whose keys finished generating since the last frame
+        for pending in std::mem::take(&mut self.pending_users) {
+            match pending.poll() {
+                None => self.pending_users.push(pending),
+                // Another user may have taken the name while these keys were generating
+                Some(Ok(_)) if self.service.system.users.contains_key(&pending.username) => {
+                    self.status = format!("Could not create user {}: {}", pending.username, CreateError::DuplicateUsername);
+                }
+                Some(Ok(user)) => {
+                    self.status = format!("Created user {}", pending.username);
+                    self.service.system.users.insert(pending.username, user);
+                }
+                Some(Err(err)) => self.status = format!("Could not create user {}: {}", pending.username, err),
+            }
+        }
+        if !self.pending_users.is_empty() {
+            // Nothing else triggers a repaint while the user sits idle
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+
+        // Take the sender's keys back once a file encryption ends
+        let finished = self.pending_stream.as_ref().and_then(PendingStream::poll);
+        let stream = finished.as_ref().and_then(|_| self.pending_stream.take());
+        if let (Some((user, result)), Some(stream)) = (finished, stream) {
+            self.status = match result {
+                Ok(()) => format!("Encrypted file written to {}", stream.output),
+                Err(CryptoError::Cancelled) => "File encryption cancelled".to_string(),
+                Err(err) => format!("Could not encrypt file: {}", err),
+            };
+            self.service.system.users.insert(stream.username, user);
+        }
+        if self.pending_stream.is_some() {
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+
+        // Expired messages disappear even if nobody touches the window
+        self.service.history.purge_expired();
+        if !self.service.history.is_empty() {
+            ctx.request_repaint_after(std::time::Duration::from_secs(1));
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             // User Creation Section
             ui.heading("Create New User");
             ui.horizontal(|ui| {
                 ui.text_edit_singleline(&mut self.new_username);
                 if ui.button("Create User").clicked() && !self.new_username.is_empty() {
-                    self.create_user(self.new_username.clone());
-                    self.new_username.clear();
+                    match self.service.system.spawn_user(self.new_username.clone()) {
+                        Ok(pending) => {
+                            self.new_username.clear();
+                            self.pending_users.push(pending);
+                        }
+                        Err(err) => self.status = format!("Could not create user: {}", err),
+                    }
+                }
+            });
+            for pending in &self.pending_users {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!("Generating keys for {}…", pending.username));
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.label("Encryption: ");
+                ui.radio_value(&mut self.service.system.key_scheme, KeyScheme::X25519, "X25519");
+                ui.radio_value(&mut self.service.system.key_scheme, KeyScheme::Rsa, "RSA (legacy, slow)");
+                ui.radio_value(&mut self.service.system.key_scheme, KeyScheme::Hybrid, "RSA + ML-KEM (post-quantum)");
+                if matches!(self.service.system.key_scheme, KeyScheme::Rsa | KeyScheme::Hybrid) {
+                    egui::ComboBox::from_id_source("rsa-bits")
+                        .selected_text(format!("{} bits", self.service.system.key_config.rsa_bits))
+                        .show_ui(ui, |ui| {
+                            for rsa_bits in SUPPORTED_RSA_BITS {
+                                ui.selectable_value(&mut self.service.system.key_config.rsa_bits, rsa_bits, format!("{} bits", rsa_bits));
+                            }
+                        });
+                }
+            });
+
+            // Recoverable users: the same phrase always recreates the same keys
+            ui.horizontal(|ui| {
+                ui.label("Recovery phrase: ");
+                ui.text_edit_singleline(&mut self.mnemonic);
+                if ui.button("New Phrase").clicked() {
+                    self.mnemonic = generate_mnemonic();
+                    self.status = "Write this phrase down before creating the user".to_string();
+                }
+                if ui.button("Create From Phrase").clicked() && !self.new_username.is_empty() && !self.mnemonic.is_empty() {
+                    let phrase = self.mnemonic.trim().to_string();
+                    match self.service.system.spawn_user_from_mnemonic(self.new_username.clone(), phrase) {
+                        Ok(pending) => {
+                            self.new_username.clear();
+                            self.mnemonic.clear();
+                            self.pending_users.push(pending);
+                        }
+                        Err(err) => self.status = format!("Could not create user: {}", err),
+                    }
+                }
+            });
+
+            // Keystore Section
+            ui.heading("Keystore");
+            ui.horizontal(|ui| {
+                ui.label("File: ");
+                ui.text_edit_singleline(&mut self.keystore_path);
+                ui.label("Passphrase: ");
+                ui.add(egui::TextEdit::singleline(&mut self.keystore_passphrase).password(true));
+            });
+            ui.horizontal(|ui| {
+                let ready = !self.keystore_path.is_empty() && !self.keystore_passphrase.is_empty();
+                if ui.button("Save Users").clicked() && ready {
+                    self.status = match keystore::save(Path::new(&self.keystore_path), &self.keystore_passphrase, &self.service.system.users)
+                        .map_err(|err| err.to_string())
+                        .and_then(|()| self.save_burned())
+                    {
+                        Ok(()) => {
+                            self.service.system.idle_timeout = Some(IDLE_LOCK_TIMEOUT);
+                            format!("Saved {} users", self.service.system.users.len())
+                        }
+                        Err(err) => format!("Could not save keystore: {}", err),
+                    };
+                }
+                if ui.button("Load Users").clicked() && ready {
+                    match keystore::load(Path::new(&self.keystore_path), &self.keystore_passphrase) {
+                        Ok(users) => {
+                            self.status = match self.load_burned() {
+                                Ok(()) => format!("Loaded {} users", users.len()),
+                                Err(err) => format!("Loaded {} users, but not the burned message list: {}", users.len(), err),
+                            };
+                            self.service.system.users.extend(users);
+                            self.service.system.idle_timeout = Some(IDLE_LOCK_TIMEOUT);
+                        }
+                        Err(err) => self.status = format!("Could not load keystore: {}", err),
+                    }
                 }
             });
-            
 
             ui.separator();
 
-            // User Selection
+            // Identity switcher: messages go out as this user, but text to any of our users is read
             ui.heading("Select User");
-            egui::ComboBox::from_label("Current User")
-                .selected_text(self.current_user.as_deref().unwrap_or("None"))
+            egui::ComboBox::from_label("Send As")
+                .selected_text(self.service.current_user.as_deref().unwrap_or("None"))
                 .show_ui(ui, |ui| {
-                    for username in self.users.keys() {
-                        ui.selectable_value(&mut self.current_user, Some(username.clone()), username);
+                    for user in self.service.system.users.values() {
+                        let label = format!("{}  [{}]", user.username, user.fingerprint());
+                        ui.selectable_value(&mut self.service.current_user, Some(user.username.clone()), label);
                     }
                 });
 
             // Message Sending Section
-            if let Some(current_user) = &self.current_user {
+            if let Some(current_user) = self.service.current_user.clone() {
                 ui.separator();
                 ui.heading("Send Encrypted Message");
-                
+                ui.horizontal(|ui| {
+                    ui.label("Cipher: ");
+                    ui.radio_value(&mut self.service.system.cipher, Cipher::Gcm, "AES-GCM");
+                    ui.radio_value(&mut self.service.system.cipher, Cipher::GcmSiv, "AES-GCM-SIV (nonce-misuse resistant)");
+                    ui.radio_value(&mut self.service.system.cipher, Cipher::ChaCha20Poly1305, "ChaCha20-Poly1305");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Recipients: ");
+                    ui.radio_value(&mut self.service.system.trust_policy, TrustPolicy::AllowAll, "Anyone");
+                    ui.radio_value(&mut self.service.system.trust_policy, TrustPolicy::RequireVerified, "Verified contacts only");
+                });
+
                 ui.horizontal(|ui| {
                     ui.label("To: ");
                     egui::ComboBox::from_label("")
                         .selected_text(&self.recipient)
                         .show_ui(ui, |ui| {
-                            for username in self.users.keys() {
-                                if username != current_user {
+                            for username in self.service.system.users.keys() {
+                                if username != &current_user {
                                     ui.selectable_value(&mut self.recipient, username.clone(), username);
                                 }
                             }
+                            for (name, contact) in &self.service.system.contacts {
+                                if !self.service.system.users.contains_key(name) {
+                                    let label = format!("{} (contact) [{}]", name, contact.fingerprint());
+                                    ui.selectable_value(&mut self.recipient, name.clone(), label);
+                                }
+                            }
                         });
                 });
 
+                // Read aloud with the contact to rule out a swapped key; the mark resets if their keys change
+                if let (Some(sender), Some(contact)) = (
+                    self.service.system.users.get(&current_user),
+                    self.service.system.contacts.get(&self.recipient).filter(|_| !self.service.system.users.contains_key(&self.recipient)),
+                ) {
+                    let number = safety_number(&sender.contact(), contact);
+                    let groups: Vec<&str> = (0..number.len()).step_by(5).map(|start| &number[start..start + 5]).collect();
+                    ui.label(format!("Safety number with {}:", self.recipient));
+                    ui.monospace(groups[..6].join(" "));
+                    ui.monospace(groups[6..].join(" "));
+                    let fingerprint = contact.fingerprint();
+                    let mut verified = self.service.system.known_peers.is_verified(&self.recipient, &fingerprint);
+                    if ui.checkbox(&mut verified, "Verified in person").changed() {
+                        self.service.system.known_peers.set_verified(&self.recipient, &fingerprint, verified);
+                    }
+                }
+
                 ui.text_edit_multiline(&mut self.message);
 
+                ui.checkbox(&mut self.burn_after_read, "Burn after reading");
+                let mut padded = self.service.system.padding != PaddingMode::None;
+                if ui.checkbox(&mut padded, "Hide message length").changed() {
+                    self.service.system.padding = if padded { PaddingMode::PowerOfTwo } else { PaddingMode::None };
+                }
+                let mut relay_checkable = self.service.system.sign_mode == SignMode::OverCiphertext;
+                if ui.checkbox(&mut relay_checkable, "Let relays verify the sender").changed() {
+                    self.service.system.sign_mode = if relay_checkable { SignMode::OverCiphertext } else { SignMode::OverPlaintext };
+                }
                 if ui.button("Send Encrypted Message").clicked() && !self.message.is_empty() {
-                    if let (Some(sender), Some(recipient)) = (
-                        self.users.get(current_user),
-                        self.users.get(&self.recipient),
-                    ) {
-                        let encrypted = self.encrypt_message(sender, recipient, &self.message);
-                        self.encrypted_messages.push((self.recipient.clone(), encrypted));
-                        self.message.clear();
+                    let encrypted = if self.burn_after_read {
+                        self.service.send_burn_after_read(&current_user, &self.recipient, &self.message)
+                    } else {
+                        self.service.send(&current_user, &self.recipient, &self.message)
+                    };
+                    match encrypted {
+                        Ok(encrypted) => {
+                            self.last_sent_json = encrypted.to_json().unwrap_or_default();
+                            self.last_sent_armored = encrypted.to_armored();
+                            self.message.clear();
+                            // Contacts read their messages elsewhere; share the JSON with them
+                            if self.service.system.users.contains_key(&self.recipient) {
+                                self.encrypted_messages.push((self.recipient.clone(), encrypted));
+                                self.status = format!("Message sent to {}", self.recipient);
+                            } else {
+                                self.status = format!("Message encrypted for {}, share the JSON below", self.recipient);
+                            }
+                        }
+                        Err(ServiceError::UnknownRecipient(_)) => {}
+                        Err(err) => self.status = format!("Could not send message: {}", err),
+                    }
+                }
+
+                // Send a file to the selected recipient
+                ui.horizontal(|ui| {
+                    ui.label("File: ");
+                    ui.text_edit_singleline(&mut self.attachment_path);
+                    if ui.button("Send File").clicked() && !self.attachment_path.is_empty() {
+                        if let (Some(sender), Some(recipient)) = (
+                            self.service.system.users.get(&current_user),
+                            self.service.system.users.get(&self.recipient),
+                        ) {
+                            let encrypted = fs::read(&self.attachment_path)
+                                .map_err(|err| err.to_string())
+                                .and_then(|data| {
+                                    self.service.system
+                                        .encrypt_bytes(sender, recipient, &data)
+                                        .map_err(|err| err.to_string())
+                                });
+                            match encrypted {
+                                Ok(encrypted) => {
+                                    self.encrypted_messages.push((self.recipient.clone(), encrypted));
+                                    self.status = format!("File sent to {}", self.recipient);
+                                }
+                                Err(err) => self.status = format!("Could not send file: {}", err),
+                            }
+                        }
+                    }
+
+                    // Large files go straight to disk in chunks instead of through memory
+                    if ui.button("Encrypt to Disk").clicked() && !self.attachment_path.is_empty() && self.pending_stream.is_none() {
+                        let recipient = match self.service.system.users.get(&self.recipient) {
+                            Some(user) => Some(user.contact()),
+                            None => self.service.system.contacts.get(&self.recipient).cloned(),
+                        };
+                        match (recipient, fs::metadata(&self.attachment_path)) {
+                            (Some(recipient), _) if self.service.system.check_trust(&recipient).is_err() => {
+                                self.status = format!("Could not encrypt file: {}", CryptoError::UntrustedRecipient);
+                            }
+                            (Some(recipient), Ok(metadata)) => {
+                                if let Some(sender) = self.service.system.users.remove(&current_user) {
+                                    let input = self.attachment_path.clone();
+                                    self.pending_stream = Some(PendingStream::spawn(sender, recipient, input, metadata.len()));
+                                }
+                            }
+                            (_, Err(err)) => self.status = format!("Could not encrypt file: {}", err),
+                            (None, Ok(_)) => {}
+                        }
+                    }
+                });
+                if let Some(stream) = &self.pending_stream {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::ProgressBar::new(stream.fraction()).show_percentage());
+                        if ui.button("Cancel").clicked() {
+                            stream.cancel.store(true, Ordering::Relaxed);
+                        }
+                    });
+                }
+
+                if ui.button("Send to All").clicked() && !self.message.is_empty() {
+                    if let Some(sender) = self.service.system.users.get(&current_user) {
+                        let recipients: Vec<&dyn RecipientKeys> = self
+                            .service
+                            .system
+                            .users
+                            .values()
+                            .filter(|user| user.username != current_user)
+                            .map(|user| user as &dyn RecipientKeys)
+                            .collect();
+                        match self.service.system.encrypt_message_multi(sender, &recipients, &self.message) {
+                            Ok(encrypted) => {
+                                self.status = format!("Message sent to {} users", recipients.len());
+                                let unread = recipients.iter().map(|recipient| recipient.fingerprint()).collect();
+                                self.party_messages.push((unread, encrypted));
+                                self.message.clear();
+                            }
+                            Err(err) => self.status = format!("Could not send message: {}", err),
+                        }
+                    }
+                }
+
+                // Own public keys for sharing
+                if let Some(user) = self.service.system.users.get(&current_user) {
+                    ui.collapsing("My Public Keys", |ui| {
+                        ui.add(egui::TextEdit::multiline(&mut user.export_public_pem().as_str()).desired_rows(6));
+                    });
+
+                    // Scannable copy of the same keys for phones
+                    if ui.button("Show QR").clicked() {
+                        match image::load_from_memory(&user.public_key_qr()) {
+                            Ok(qr) => {
+                                let qr = qr.to_rgb8();
+                                let size = [qr.width() as usize, qr.height() as usize];
+                                let qr = egui::ColorImage::from_rgb(size, qr.as_raw());
+                                let texture = ctx.load_texture("public-key-qr", qr, Default::default());
+                                self.qr_texture = Some((current_user.clone(), texture));
+                            }
+                            Err(err) => self.status = format!("Could not render QR code: {}", err),
+                        }
+                    }
+                    if let Some((owner, texture)) = &self.qr_texture {
+                        if owner == &current_user {
+                            ui.image(texture, texture.size_vec2());
+                        }
+                    }
+                }
+
+                // Replace compromised keys; messages sent to the old ones stay readable, and unread
+                // ones are moved onto the new key straight away
+                if ui.button("Rotate My Keys").clicked() {
+                    let now = self.service.system.now_millis();
+                    let rotated = self.service.system.users.get_mut(&current_user).map(|user| user.rotate_keys(now).map(|()| user.fingerprint()));
+                    self.status = match rotated {
+                        Some(Ok(fingerprint)) => match self.service.rewrap_history(&current_user) {
+                            Ok(moved) => format!("New fingerprint [{}], share your new public keys; {} unread messages moved to it", fingerprint, moved),
+                            Err(err) => format!("New fingerprint [{}], but unread messages could not be moved: {}", fingerprint, err),
+                        },
+                        Some(Err(err)) => format!("Could not rotate keys: {}", err),
+                        None => self.status.clone(),
+                    };
+                }
+
+                // Address book: people known only by their public keys
+                ui.label("Add a contact from their public key bundle:");
+                ui.horizontal(|ui| {
+                    ui.label("Name: ");
+                    ui.text_edit_singleline(&mut self.contact_name);
+                });
+                ui.add(egui::TextEdit::multiline(&mut self.recipient_pem).desired_rows(3));
+                let mut added = None;
+                if ui.button("Add Contact").clicked() && !self.contact_name.is_empty() {
+                    self.key_change = None;
+                    match self.service.system.add_contact(self.contact_name.clone(), &self.recipient_pem) {
+                        Ok(()) => added = Some(self.contact_name.clone()),
+                        Err(ImportError::KeyChanged(warning)) => {
+                            self.status = warning.to_string();
+                            self.key_change = Some(warning);
+                        }
+                        Err(err) => self.status = format!("Could not add contact: {}", err),
+                    }
+                }
+
+                // A changed key is only taken after the user says they checked it with the contact
+                if let Some(TofuWarning::KeyChanged { username, old, new }) = self.key_change.clone() {
+                    ui.colored_label(egui::Color32::RED, format!("{}'s keys changed from [{}] to [{}]", username, old, new));
+                    ui.horizontal(|ui| {
+                        if ui.button("Trust New Keys").clicked() {
+                            match self.service.system.accept_contact(username.clone(), &self.recipient_pem) {
+                                Ok(()) => added = Some(username.clone()),
+                                Err(err) => self.status = format!("Could not add contact: {}", err),
+                            }
+                        }
+                        if ui.button("Keep Old Keys").clicked() {
+                            self.status = format!("Kept the keys first seen for {}", username);
+                            self.key_change = None;
+                        }
+                    });
+                }
+
+                // A whole address book at once, in the JSON export_contacts_bundle writes
+                ui.horizontal(|ui| {
+                    if ui.button("Import Bundle").clicked() {
+                        let results = self.service.system.import_contacts_bundle(&self.recipient_pem);
+                        let failed = results.iter().filter(|result| result.is_err()).count();
+                        self.status = match results.iter().find_map(|result| result.as_ref().err()) {
+                            Some(err) => format!("Imported {} contacts, {} failed (first error: {})", results.len() - failed, failed, err),
+                            None => format!("Imported {} contacts", results.len()),
+                        };
+                        if failed == 0 {
+                            self.recipient_pem.clear();
+                        }
+                    }
+                    if ui.button("Export Bundle").clicked() {
+                        ui.output_mut(|output| output.copied_text = self.service.system.export_contacts_bundle());
+                        self.status = format!("Copied {} contacts to the clipboard", self.service.system.contacts.len());
+                    }
+                });
+
+                if let Some((name, contact)) = added.and_then(|name| self.service.system.contacts.get(&name).map(|contact| (name, contact))) {
+                    self.status = match contact.encryption.weak_rsa_bits() {
+                        Some(bits) => format!(
+                            "Added contact {} [{}], but their {}-bit RSA key is too weak to encrypt to",
+                            name,
+                            contact.fingerprint(),
+                            bits
+                        ),
+                        None => format!("Added contact {} [{}]", name, contact.fingerprint()),
+                    };
+                    self.key_change = None;
+                    self.contact_name.clear();
+                    self.recipient_pem.clear();
+                }
+
+                // Shareable copy of the last sent message
+                if !self.last_sent_json.is_empty() {
+                    ui.label("Last sent message (JSON):");
+                    ui.add(egui::TextEdit::multiline(&mut self.last_sent_json.as_str()).desired_rows(3));
+                    if ui.button("Copy").clicked() {
+                        ui.output_mut(|output| output.copied_text = self.last_sent_armored.clone());
+                        self.status = "Copied the encrypted message to the clipboard".to_string();
+                    }
+                }
+
+                // Import a message received out of band
+                ui.separator();
+                ui.heading("Import Message");
+                ui.add(egui::TextEdit::multiline(&mut self.import_json).desired_rows(3));
+                if ui.button("Import Message").clicked() && !self.import_json.is_empty() {
+                    match EncryptedMessage::from_json(&self.import_json) {
+                        Ok(imported) => {
+                            self.encrypted_messages.push((current_user.clone(), imported));
+                            self.import_json.clear();
+                        }
+                        Err(err) => self.status = format!("Could not import message: {}", err),
+                    }
+                }
+
+                // Copied messages arrive armored, or as bare base64 from older versions; received messages below decrypt them
+                ui.label("Paste a copied message:");
+                ui.add(egui::TextEdit::multiline(&mut self.paste_base64).desired_rows(2));
+                if ui.button("Paste & Decrypt").clicked() && !self.paste_base64.trim().is_empty() {
+                    let max_message_bytes = self.service.system.policy.max_message_bytes;
+                    let pasted = match EncryptedMessage::from_armored_limited(&self.paste_base64, max_message_bytes) {
+                        Err(ArmorError::MissingHeader) => EncryptedMessage::from_base64_limited(&self.paste_base64, max_message_bytes).map_err(ArmorError::Wire),
+                        result => result,
+                    };
+                    match pasted {
+                        Ok(pasted) => {
+                            self.encrypted_messages.push((current_user.clone(), pasted));
+                            self.paste_base64.clear();
+                        }
+                        Err(ArmorError::Wire(WireError::InvalidBase64)) => self.status = "Pasted text is not a copied message".to_string(),
+                        Err(err) => self.status = format!("Pasted message is damaged: {}", err),
                     }
                 }
 
                 // Display received messages
                 ui.separator();
                 ui.heading("Received Messages");
-                let current_user_clone = current_user.clone();
-                self.encrypted_messages.retain(|(recipient, encrypted_msg)| {
-                    if recipient == &current_user_clone {
-                        if let Some(recipient_user) = self.users.get(&current_user_clone) {
-                            if let Some(decrypted) = self.decrypt_message(recipient_user, encrypted_msg) {
-                                self.decrypted_messages.push((
-                                    BASE64.encode(&encrypted_msg.sender_public.as_bytes()),
-                                    decrypted,
-                                ));
+                let (received, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.encrypted_messages)
+                    .into_iter()
+                    .partition(|(recipient, message)| {
+                        recipient == &current_user
+                            || (message.content_type == ContentType::Text && self.service.system.users.contains_key(recipient))
+                    });
+                self.encrypted_messages = pending;
+                // Text waits in the inbox for Decrypt All; files are read straight away
+                for (_, encrypted_msg) in received {
+                    let result = match encrypted_msg.content_type {
+                        ContentType::Text => {
+                            self.service.inbox.push(encrypted_msg);
+                            Ok(())
+                        }
+                        ContentType::Binary => self.service.receive_bytes(&encrypted_msg).map(|data| {
+                            self.attachments.push((BASE64.encode(encrypted_msg.sender_public.as_bytes()), data));
+                        }),
+                    };
+                    if let Err(err) = result {
+                        self.status = describe_service_error(&err);
+                    }
+                }
+                if !self.service.inbox.is_empty() {
+                    // Checked without reading, so nothing is burned or logged until Decrypt All
+                    let for_me = self.service.system.users.get(&current_user).map_or(0, |user| {
+                        self.service.inbox.iter().filter(|message| self.service.can_read(message, user)).count()
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{} unread, {} for {}", self.service.inbox.len(), for_me, current_user));
+                        if ui.button("Decrypt All").clicked() {
+                            let usernames: Vec<String> = self.service.system.users.keys().cloned().collect();
+                            let (mut read, mut failed) = (0, 0);
+                            // Every owned identity reads its own messages
+                            for username in usernames {
+                                for (_, outcome) in self.service.receive_all(&username).unwrap_or_default() {
+                                    match outcome {
+                                        Ok(ReceiveOutcome::Stored) => read += 1,
+                                        Ok(ReceiveOutcome::Duplicate) => {}
+                                        Err(_) => failed += 1,
+                                    }
+                                }
                             }
+                            self.status = format!("Decrypted {} messages, {} failed", read, failed);
                         }
-                        false
+                    });
+                }
+
+                // Party messages stay queued until every recipient has read them
+                if let Some(fingerprint) = self.service.system.users.get(&current_user).map(User::fingerprint) {
+                    let mut party_messages = std::mem::take(&mut self.party_messages);
+                    for (unread, party_msg) in &mut party_messages {
+                        if unread.remove(&fingerprint) {
+                            if let Err(err) = self.service.receive_multi(party_msg) {
+                                self.status = describe_service_error(&err);
+                            }
+                        }
+                    }
+                    party_messages.retain(|(unread, _)| !unread.is_empty());
+                    self.party_messages = party_messages;
+                }
+
+                // Decrypted history, filtered by the search box
+                ui.horizontal(|ui| {
+                    ui.label("Delete read messages after: ");
+                    let selected = MESSAGE_LIFETIMES.iter().find(|(_, lifetime)| *lifetime == self.service.message_lifetime);
+                    egui::ComboBox::from_id_source("message-lifetime")
+                        .selected_text(selected.map_or("Never", |(label, _)| *label))
+                        .show_ui(ui, |ui| {
+                            for (label, lifetime) in MESSAGE_LIFETIMES {
+                                ui.selectable_value(&mut self.service.message_lifetime, lifetime, label);
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Search: ");
+                    if ui.text_edit_singleline(&mut self.history_query).changed() {
+                        self.history_page = 0;
+                    }
+                });
+                let unread = self.service.history.unread_count();
+                if unread > 0 {
+                    ui.label(format!("{} unread", unread));
+                }
+                let query = self.history_query.trim();
+                let pages = self.service.history.count(query, None, None).div_ceil(HISTORY_PAGE_SIZE).max(1);
+                self.history_page = self.history_page.min(pages - 1);
+                // The page's messages under one heading per conversation, in order of each one's first message
+                let mut conversations: Vec<(ConversationId, Vec<&StoredMessage>)> = Vec::new();
+                for message in self.service.history.search(query, None, None, self.history_page * HISTORY_PAGE_SIZE, HISTORY_PAGE_SIZE) {
+                    match conversations.iter_mut().find(|(id, _)| *id == message.conversation_id) {
+                        Some((_, messages)) => messages.push(message),
+                        None => conversations.push((message.conversation_id, vec![message])),
+                    }
+                }
+                let mut marked = None;
+                for (id, messages) in conversations {
+                    if id == NO_CONVERSATION {
+                        ui.strong("Party messages");
                     } else {
-                        true
+                        let short: String = id[..4].iter().map(|byte| format!("{:02x}", byte)).collect();
+                        ui.strong(format!("Conversation {}", short));
+                    }
+                    for message in messages {
+                        ui.horizontal(|ui| {
+                            if message.read {
+                                ui.label(format!("From {}: {}", message.sender, message.body));
+                            } else {
+                                ui.strong(format!("From {}: {}", message.sender, message.body));
+                                if ui.small_button("Mark read").clicked() {
+                                    marked = Some(message.message_id);
+                                }
+                            }
+                        });
+                    }
+                }
+                if let Some(id) = marked {
+                    self.service.history.mark_read(&id);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Prev").clicked() && self.history_page > 0 {
+                        self.history_page -= 1;
+                    }
+                    ui.label(format!("Page {} of {}", self.history_page + 1, pages));
+                    if ui.button("Next").clicked() && self.history_page + 1 < pages {
+                        self.history_page += 1;
                     }
                 });
 
-                // Display decrypted messages
-                for (sender, message) in &self.decrypted_messages {
-                    ui.label(format!("From {}: {}", sender, message));
+                // Received files are saved to the path in the File field
+                for (sender, data) in &self.attachments {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("From {}: file ({} bytes)", sender, data.len()));
+                        if ui.button("Save").clicked() && !self.attachment_path.is_empty() {
+                            self.status = match fs::write(&self.attachment_path, data) {
+                                Ok(()) => format!("Saved file to {}", self.attachment_path),
+                                Err(err) => format!("Could not save file: {}", err),
+                            };
+                        }
+                    });
                 }
             }
+
+            // Status line
+            if !self.status.is_empty() {
+                ui.separator();
+                ui.label(&self.status);
+            }
         });
     }
 }
 
+// Tell the user what went wrong with an incoming message and what it means for them
+fn describe_decrypt_error(err: &DecryptError) -> String {
+    match err {
+        DecryptError::WrongRecipient => "This message was encrypted for someone else".to_string(),
+        DecryptError::CorruptCiphertext => "This message was corrupted in transit".to_string(),
+        DecryptError::InvalidSignature => "Sender signature is invalid; the message may be forged".to_string(),
+        DecryptError::MalformedUtf8 => "This message is not readable text".to_string(),
+        DecryptError::TamperedEnvelope => "This message was modified after it was sent".to_string(),
+        DecryptError::Revoked => "The sender revoked this signing key; the message may be forged".to_string(),
+        DecryptError::AlreadyRead => "This message could only be read once and has been burned".to_string(),
+        other => format!("Could not read message: {}", other),
+    }
+}
+
+fn describe_service_error(err: &ServiceError) -> String {
+    match err {
+        ServiceError::Decrypt(err) => describe_decrypt_error(err),
+        other => format!("Could not read message: {}", other),
+    }
+}
+
 fn main() -> Result<(), eframe::Error> {
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(800.0, 600.0)),
@@ -237,6 +841,15 @@ fn main() -> Result<(), eframe::Error> {
     eframe::run_native(
         "Digital Signature System",
         options,
-        Box::new(|_cc| Box::<SignatureApp>::default()),
+        Box::new(|_cc| {
+            // Key generation is expensive, so a held-down button mustn't be able to queue it up
+            let mut system = SignatureSystem::default();
+            system.create_throttle = CreateThrottle::new(USER_CREATE_COOLDOWN);
+            Box::new(SignatureApp {
+                service: MessagingService::new(system),
+                self_test_failure: run_self_test().err(),
+                ..SignatureApp::default()
+            })
+        }),
     )
-}
\ No newline at end of file
+}
@@ -0,0 +1,1018 @@
+use crate::ct::ct_eq;
+use crate::error::{CryptoError, DecryptError, WireError};
+use crate::audit::to_hex;
+use crate::keys::{BlindedKeyId, EncryptionKey, KeyExchange, KeyId};
+use crate::signing::{MessageSignature, SignatureAlgorithm, VerifyingKey};
+use crate::user::ConversationId;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroU32;
+
+// Domain separation for envelope signatures
+const ENVELOPE_CONTEXT: &[u8] = b"pgfi-envelope-v1";
+const MULTI_ENVELOPE_CONTEXT: &[u8] = b"pgfi-multi-envelope-v1";
+const BATCH_ENVELOPE_CONTEXT: &[u8] = b"pgfi-batch-envelope-v2";
+const ANONYMOUS_CONTEXT: &[u8] = b"pgfi-anonymous-v1";
+const CIPHERTEXT_SIGNATURE_CONTEXT: &[u8] = b"pgfi-ciphertext-v1";
+
+// First byte of every binary wire message. Integers after it are fixed-width big-endian, u64
+// for timestamps and u32 for lengths, counts and bucket sizes, whatever the host's byte order.
+const WIRE_MAGIC: u8 = 0xa7;
+
+// Largest payload a message may carry unless configured otherwise, see MessagePolicy::max_message_bytes
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+
+// Room in a wire message beyond its payload for keys, signatures and the other fields
+pub(crate) const WIRE_OVERHEAD: usize = 64 * 1024;
+
+// Message format versions
+pub const LEGACY_PKCS1_VERSION: u8 = 1;  // Symmetric key wrapped with PKCS#1 v1.5
+pub const MESSAGE_VERSION: u8 = 15;      // Current format: suite-tagged, signed envelope; layout frozen by tests/fixtures/wire-v15.bin
+
+// Canonical id of a message, see EncryptedMessage::message_id
+pub type MessageId = [u8; 32];
+
+// conversation_id of single-message views of multi-recipient and batch messages, which belong to no two-party conversation
+pub const NO_CONVERSATION: ConversationId = [0; 32];
+
+// AEAD a payload was sealed with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cipher {
+    #[default]
+    Gcm = 0,                             // AES-256-GCM; a repeated nonce under one key is fatal
+    GcmSiv = 1,                          // AES-256-GCM-SIV; a repeated nonce only reveals that two plaintexts are equal
+    ChaCha20Poly1305 = 2,                // ChaCha20-Poly1305; constant time without AES hardware
+}
+
+impl Cipher {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Gcm),
+            1 => Some(Self::GcmSiv),
+            2 => Some(Self::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+// Suite ids for key exchange, signature and hash; the cipher id is Cipher's discriminant
+const KEM_RSA_OAEP: u8 = 0;
+const KEM_X25519: u8 = 1;
+const KEM_HYBRID: u8 = 2;                // RSA-OAEP plus ML-KEM-768
+const KEM_HPKE_ED25519: u8 = 3;          // X25519 against the recipient's Ed25519 identity
+const SIG_ED25519: u8 = 0;
+const HASH_SHA256: u8 = 0;
+
+// One part of an AlgorithmSuite
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuiteComponent {
+    Kem,
+    Cipher,
+    Signature,
+    Hash,
+}
+
+impl SuiteComponent {
+    // Ids of this component this build implements
+    pub fn supported(self) -> &'static [u8] {
+        match self {
+            Self::Kem => &[KEM_RSA_OAEP, KEM_X25519, KEM_HYBRID, KEM_HPKE_ED25519],
+            Self::Cipher => &[Cipher::Gcm as u8, Cipher::GcmSiv as u8, Cipher::ChaCha20Poly1305 as u8],
+            Self::Signature => &[SIG_ED25519],
+            Self::Hash => &[HASH_SHA256],
+        }
+    }
+}
+
+impl fmt::Display for SuiteComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Kem => "key exchange",
+            Self::Cipher => "cipher",
+            Self::Signature => "signature",
+            Self::Hash => "hash",
+        })
+    }
+}
+
+// Every algorithm a message depends on, kept as raw ids so that a reader can name the component
+// it doesn't implement instead of failing to parse. Newer algorithms only need new ids here.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlgorithmSuite {
+    pub kem: u8,                         // How the message key is delivered
+    pub cipher: u8,                      // AEAD over the payload
+    pub sig: u8,                         // Sender's signatures
+    pub hash: u8,                        // Digest behind OAEP, HKDF and key ids
+}
+
+impl AlgorithmSuite {
+    pub(crate) fn new(key_exchange: &KeyExchange, cipher: Cipher, sender: &VerifyingKey) -> Self {
+        Self {
+            kem: match key_exchange {
+                KeyExchange::Rsa { .. } => KEM_RSA_OAEP,
+                KeyExchange::X25519 { .. } => KEM_X25519,
+                KeyExchange::Hybrid { .. } => KEM_HYBRID,
+                KeyExchange::Hpke { .. } => KEM_HPKE_ED25519,
+            },
+            cipher: cipher as u8,
+            sig: match sender.algorithm() {
+                SignatureAlgorithm::Ed25519 => SIG_ED25519,
+            },
+            hash: HASH_SHA256,
+        }
+    }
+
+    // Refuse the first component this build doesn't implement, naming it
+    pub fn check(&self) -> Result<(), DecryptError> {
+        let components = [
+            (SuiteComponent::Kem, self.kem),
+            (SuiteComponent::Cipher, self.cipher),
+            (SuiteComponent::Signature, self.sig),
+            (SuiteComponent::Hash, self.hash),
+        ];
+        match components.into_iter().find(|(component, id)| !component.supported().contains(id)) {
+            Some((component, _)) => Err(DecryptError::UnsupportedSuite(component)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn aead(&self) -> Result<Cipher, DecryptError> {
+        Cipher::from_u8(self.cipher).ok_or(DecryptError::UnsupportedSuite(SuiteComponent::Cipher))
+    }
+
+    fn to_bytes(self) -> [u8; 4] {
+        [self.kem, self.cipher, self.sig, self.hash]
+    }
+}
+
+// How the plaintext was compressed before encryption.
+//
+// Compression leaks the plaintext's redundancy through the ciphertext length, which lets an
+// attacker who can inject text next to a secret recover it (CRIME). So it is opt-in per
+// message, and a compressed message must only hold data from one trust context, never
+// attacker-supplied text alongside anything secret.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgo {
+    #[default]
+    None = 0,
+    Deflate = 1,                         // Raw DEFLATE, for long game logs and other repetitive text
+}
+
+impl CompressionAlgo {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::Deflate),
+            _ => None,
+        }
+    }
+}
+
+// What a single-recipient message's inner signature covers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignMode {
+    #[default]
+    OverPlaintext = 0,                   // The payload itself, so the recipient can show others what the sender wrote
+    OverCiphertext = 1,                  // Nonce and ciphertext, so a relay can check the sender without reading it
+}
+
+impl SignMode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::OverPlaintext),
+            1 => Some(Self::OverCiphertext),
+            _ => None,
+        }
+    }
+}
+
+// Bytes signed under SignMode::OverCiphertext. Only transmitted fields go in, so anyone holding the
+// message can rebuild them; the recipient is bound by the AEAD's associated data instead.
+pub(crate) fn ciphertext_sign_bytes(timestamp: u64, content_type: ContentType, nonce: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    let mut bytes = CIPHERTEXT_SIGNATURE_CONTEXT.to_vec();
+    push_field(&mut bytes, &timestamp.to_be_bytes());
+    push_field(&mut bytes, &[content_type as u8]);
+    push_field(&mut bytes, nonce);
+    push_field(&mut bytes, ciphertext);
+    bytes
+}
+
+// How the plaintext was padded before sealing so the ciphertext length doesn't give away short replies.
+// A padded plaintext starts with its real length as a u32, followed by the payload and then zeros.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaddingMode {
+    #[default]
+    None,
+    PowerOfTwo,                          // Up to the next power of two, so sizes only leak to within 2x
+    Bucket(NonZeroU32),                  // Up to the next multiple of this many bytes
+}
+
+impl PaddingMode {
+    // Length a payload of `len` bytes occupies once padded, length prefix included
+    pub fn padded_len(self, len: usize) -> Option<usize> {
+        let framed = len.checked_add(4)?;
+        match self {
+            Self::None => Some(len),
+            Self::PowerOfTwo => framed.checked_next_power_of_two(),
+            Self::Bucket(size) => framed.checked_next_multiple_of(size.get() as usize),
+        }
+    }
+
+    // Tag byte, then the bucket size or zero
+    fn to_bytes(self) -> [u8; 5] {
+        let (tag, size) = match self {
+            Self::None => (0u8, 0),
+            Self::PowerOfTwo => (1, 0),
+            Self::Bucket(size) => (2, size.get()),
+        };
+        let mut bytes = [tag, 0, 0, 0, 0];
+        bytes[1..].copy_from_slice(&size.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: [u8; 5]) -> Option<Self> {
+        let size = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+        match (bytes[0], size) {
+            (0, 0) => Some(Self::None),
+            (1, 0) => Some(Self::PowerOfTwo),
+            (2, size) => NonZeroU32::new(size).map(Self::Bucket),
+            _ => None,
+        }
+    }
+}
+
+// What the decrypted payload is, so the reader knows whether to render it or save it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    Text = 0,                            // UTF-8 chat message
+    Binary = 1,                          // Opaque bytes such as an image or replay file
+}
+
+// Shown in front of text that had to be repaired for display
+pub const INVALID_TEXT_BADGE: &str = "(binary/invalid text)";
+
+// Verified plaintext of a text message, kept as bytes because the sender may not have sent valid UTF-8
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecryptedText {
+    pub bytes: Vec<u8>,
+}
+
+impl DecryptedText {
+    pub fn is_valid_utf8(&self) -> bool {
+        std::str::from_utf8(&self.bytes).is_ok()
+    }
+
+    // Invalid sequences become U+FFFD, so this is for display only
+    pub fn lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.bytes)
+    }
+
+    // The lossy text, badged when it isn't what the sender's bytes actually said
+    pub fn display(&self) -> String {
+        if self.is_valid_utf8() {
+            self.lossy().into_owned()
+        } else {
+            format!("{} {}", INVALID_TEXT_BADGE, self.lossy())
+        }
+    }
+}
+
+// Structure to hold an encrypted message
+#[derive(Clone)]
+pub struct EncryptedMessage {
+    pub version: u8,                     // Format version, see MESSAGE_VERSION
+    pub encrypted_data: Vec<u8>,         // The encrypted message
+    pub signature: MessageSignature,     // Signature of the original message, or its ciphertext, see sign_mode
+    pub sender_public: VerifyingKey,     // Sender's public key for verification
+    pub key_exchange: KeyExchange,       // How the symmetric key reaches the recipient
+    pub nonce: Vec<u8>,                  // Nonce for `cipher`
+    pub timestamp: u64,                  // Unix millis when sent, covered by the signature
+    pub content_type: ContentType,       // Covered by the signature
+    pub suite: AlgorithmSuite,           // Key exchange, AEAD, signature and hash the message uses
+    pub compression: CompressionAlgo,    // Applied to the plaintext before sealing
+    pub padding: PaddingMode,            // Applied after compression, also before sealing
+    pub burn_after_read: bool,           // Recipient refuses to decrypt it a second time
+    pub conversation_id: ConversationId, // Sender and recipient's conversation, for grouping history
+    pub sign_mode: SignMode,             // What `signature` covers
+    pub in_reply_to: Option<MessageId>,  // message_id of the message this answers, bound into the ciphertext
+    pub recipient_key_id: KeyId,         // EncryptionKey::key_id of the key `key_exchange` is wrapped to
+    pub envelope_signature: MessageSignature, // Sender's signature over every other field, see envelope_bytes
+}
+
+// One recipient's copy of a multi-recipient message key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WrappedKey {
+    pub key_exchange: KeyExchange,
+}
+
+// One ciphertext readable by several recipients
+#[derive(Clone)]
+pub struct MultiRecipientMessage {
+    pub version: u8,
+    pub encrypted_data: Vec<u8>,
+    pub signature: MessageSignature,
+    pub sender_public: VerifyingKey,
+    pub recipient_salt: [u8; 16],        // Random per message, blinds the wrapped keys' ids
+    pub wrapped_keys: HashMap<BlindedKeyId, WrappedKey>, // Blinded recipient key id -> their copy of the symmetric key
+    pub nonce: Vec<u8>,
+    pub timestamp: u64,
+    pub content_type: ContentType,
+    pub cipher: Cipher,
+    pub envelope_signature: MessageSignature, // Covers every recipient's wrapped key
+}
+
+impl MultiRecipientMessage {
+    // Canonical identifier, as for EncryptedMessage: SHA-256 of envelope_bytes, whose context keeps
+    // it apart from every single-recipient message id
+    pub fn message_id(&self) -> MessageId {
+        Sha256::digest(self.envelope_bytes()).into()
+    }
+
+    // Canonical encoding of every transmitted field except the envelope signature itself
+    pub fn envelope_bytes(&self) -> Vec<u8> {
+        let mut wrapped_keys: Vec<_> = self.wrapped_keys.iter().collect();
+        wrapped_keys.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut bytes = MULTI_ENVELOPE_CONTEXT.to_vec();
+        bytes.push(self.version);
+        push_field(&mut bytes, &self.encrypted_data);
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        bytes.extend_from_slice(self.sender_public.as_bytes());
+        bytes.extend_from_slice(&self.recipient_salt);
+        bytes.extend_from_slice(&(wrapped_keys.len() as u32).to_be_bytes());
+        for (key_id, wrapped) in wrapped_keys {
+            bytes.extend_from_slice(key_id);
+            push_field(&mut bytes, &wrapped.key_exchange.to_bytes());
+        }
+        push_field(&mut bytes, &self.nonce);
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.push(self.content_type as u8);
+        bytes.push(self.cipher as u8);
+        bytes
+    }
+
+    // The recipient set as covered by the sender's signature, see blinded_recipients
+    pub(crate) fn recipients(&self) -> Vec<String> {
+        blinded_recipients(self.wrapped_keys.keys())
+    }
+
+    // Whether the message key is wrapped to `key`
+    pub fn wraps_for(&self, key: &EncryptionKey) -> bool {
+        self.wrapped_for(key).is_some()
+    }
+
+    fn wrapped_for(&self, key: &EncryptionKey) -> Option<&WrappedKey> {
+        let key_id = key.blinded_key_id(&self.recipient_salt);
+        self.wrapped_keys.iter().find(|(id, _)| ct_eq(*id, &key_id)).map(|(_, wrapped)| wrapped)
+    }
+
+    // Single-recipient view of the message for the holder of `key`'s private half.
+    // Its envelope signature still covers the whole multi-recipient message.
+    pub fn for_recipient(&self, key: &EncryptionKey) -> Option<EncryptedMessage> {
+        let key_exchange = &self.wrapped_for(key)?.key_exchange;
+        Some(EncryptedMessage {
+            version: self.version,
+            encrypted_data: self.encrypted_data.clone(),
+            signature: self.signature,
+            sender_public: self.sender_public,
+            suite: AlgorithmSuite::new(key_exchange, self.cipher, &self.sender_public),
+            key_exchange: key_exchange.clone(),
+            nonce: self.nonce.clone(),
+            timestamp: self.timestamp,
+            content_type: self.content_type,
+            compression: CompressionAlgo::None,
+            padding: PaddingMode::None,
+            burn_after_read: false,
+            conversation_id: NO_CONVERSATION,
+            sign_mode: SignMode::OverPlaintext,
+            in_reply_to: None,
+            recipient_key_id: key.key_id(),
+            envelope_signature: self.envelope_signature,
+        })
+    }
+}
+
+// Stand-in for a multi-recipient message's recipient set in its signature and associated data.
+// Recipients can't list each other's fingerprints, but every one sees the same blinded ids.
+pub(crate) fn blinded_recipients<'a>(key_ids: impl Iterator<Item = &'a BlindedKeyId>) -> Vec<String> {
+    let mut recipients: Vec<String> = key_ids.map(|key_id| to_hex(key_id)).collect();
+    recipients.sort();
+    recipients
+}
+
+// Many text messages to one recipient under a single wrapped key
+#[derive(Clone)]
+pub struct BatchMessage {
+    pub version: u8,
+    pub sender_public: VerifyingKey,
+    pub key_exchange: KeyExchange,       // Wrapped once for the whole batch
+    pub recipient_key_id: KeyId,         // EncryptionKey::key_id of the key it is wrapped to
+    pub entries: Vec<BatchEntry>,
+    pub timestamp: u64,
+    pub cipher: Cipher,                  // AEAD every entry was sealed with
+    pub nonce_base: [u8; 12],            // Entry nonces are derived from this and their index, see batch_nonce
+    pub envelope_signature: MessageSignature, // Covers every entry, so none can be dropped or reordered
+}
+
+// One message of a batch; its nonce under the shared key comes from its position
+#[derive(Clone)]
+pub struct BatchEntry {
+    pub encrypted_data: Vec<u8>,
+    pub signature: MessageSignature,     // Signature of the original message
+}
+
+// Nonce of entry `index`: the base with the index XORed into its last 8 bytes, as TLS 1.3 does with
+// record numbers. Distinct indices always give distinct nonces, where random ones could collide.
+pub fn batch_nonce(base: &[u8; 12], index: u64) -> [u8; 12] {
+    let mut nonce = *base;
+    for (byte, counter) in nonce[4..].iter_mut().zip(index.to_be_bytes()) {
+        *byte ^= counter;
+    }
+    nonce
+}
+
+impl BatchMessage {
+    // Canonical encoding of every transmitted field except the envelope signature itself
+    pub fn envelope_bytes(&self) -> Vec<u8> {
+        let mut bytes = BATCH_ENVELOPE_CONTEXT.to_vec();
+        bytes.push(self.version);
+        bytes.extend_from_slice(self.sender_public.as_bytes());
+        push_field(&mut bytes, &self.key_exchange.to_bytes());
+        bytes.extend_from_slice(&self.recipient_key_id);
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            push_field(&mut bytes, &entry.encrypted_data);
+            bytes.extend_from_slice(&entry.signature.to_bytes());
+        }
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.push(self.cipher as u8);
+        bytes.extend_from_slice(&self.nonce_base);
+        bytes
+    }
+
+    // Single-message view of one entry.
+    // Its envelope signature still covers the whole batch.
+    pub fn entry(&self, index: usize) -> Option<EncryptedMessage> {
+        let entry = self.entries.get(index)?;
+        Some(EncryptedMessage {
+            version: self.version,
+            encrypted_data: entry.encrypted_data.clone(),
+            signature: entry.signature,
+            sender_public: self.sender_public,
+            suite: AlgorithmSuite::new(&self.key_exchange, self.cipher, &self.sender_public),
+            key_exchange: self.key_exchange.clone(),
+            nonce: batch_nonce(&self.nonce_base, index as u64).to_vec(),
+            timestamp: self.timestamp,
+            content_type: ContentType::Text,
+            compression: CompressionAlgo::None,
+            padding: PaddingMode::None,
+            burn_after_read: false,
+            conversation_id: NO_CONVERSATION,
+            sign_mode: SignMode::OverPlaintext,
+            in_reply_to: None,
+            recipient_key_id: self.recipient_key_id,
+            envelope_signature: self.envelope_signature,
+        })
+    }
+}
+
+// Message that doesn't say who sent it, for reports the sender must not be identified by.
+//
+// There is no sender key and no signature. Every field is authenticated by the AEAD tag instead,
+// which is a MAC keyed by the symmetric key only the recipient can unwrap. The recipient learns
+// the message arrived intact, but not who wrote it: anyone holding the recipient's public key
+// could have, so it can't be attributed, replied to or blocked per sender. Revocations don't apply.
+#[derive(Clone)]
+pub struct AnonymousMessage {
+    pub version: u8,
+    pub encrypted_data: Vec<u8>,         // Tagged over authenticated_bytes
+    pub key_exchange: KeyExchange,       // Ephemeral X25519 or RSA-OAEP, neither names the sender
+    pub nonce: Vec<u8>,
+    pub timestamp: u64,
+    pub content_type: ContentType,
+    pub cipher: Cipher,
+}
+
+impl AnonymousMessage {
+    // Canonical encoding of every transmitted field outside the ciphertext, bound in as associated data
+    pub fn authenticated_bytes(&self) -> Vec<u8> {
+        let mut bytes = ANONYMOUS_CONTEXT.to_vec();
+        bytes.push(self.version);
+        push_field(&mut bytes, &self.key_exchange.to_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.push(self.content_type as u8);
+        bytes.push(self.cipher as u8);
+        bytes
+    }
+}
+
+// Length-prefixed variable-size field
+pub(crate) fn push_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(field);
+}
+
+// Cursor over a binary wire message that never indexes past the end
+struct WireReader<'a> {
+    bytes: &'a [u8],
+    max_field: usize,                    // Declared lengths above this are refused before they are read
+}
+
+impl<'a> WireReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        if self.bytes.len() < len {
+            return Err(WireError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    // One u32-length-prefixed field
+    fn field(&mut self) -> Result<&'a [u8], WireError> {
+        let len = u32::from_be_bytes(self.fixed::<4>()?) as usize;
+        if len > self.max_field {
+            return Err(WireError::TooLarge(self.max_field));
+        }
+        self.take(len)
+    }
+
+    // A length-prefixed field that must be exactly N bytes long
+    fn sized_field<const N: usize>(&mut self, name: &'static str) -> Result<[u8; N], WireError> {
+        self.field()?.try_into().map_err(|_| WireError::InvalidField(name))
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        let bytes = self.take(N)?;
+        let mut array = [0u8; N];
+        array.copy_from_slice(bytes);
+        Ok(array)
+    }
+}
+
+// Plain-bytes form of an EncryptedMessage for saving and sharing
+#[derive(Serialize, Deserialize)]
+struct SerializableMessage {
+    version: u8,
+    encrypted_data: Vec<u8>,
+    signature: Vec<u8>,                  // Raw signature bytes, algorithm given by sender_public
+    sender_public: Vec<u8>,              // Raw public key bytes
+    key_exchange: KeyExchange,
+    nonce: Vec<u8>,
+    timestamp: u64,
+    content_type: ContentType,
+    suite: AlgorithmSuite,
+    #[serde(default)]
+    compression: CompressionAlgo,
+    #[serde(default)]
+    padding: PaddingMode,
+    #[serde(default)]
+    burn_after_read: bool,
+    #[serde(default)]
+    conversation_id: ConversationId,
+    #[serde(default)]
+    sign_mode: SignMode,
+    #[serde(default)]
+    in_reply_to: Option<MessageId>,
+    recipient_key_id: KeyId,
+    envelope_signature: Vec<u8>,         // Raw signature bytes
+}
+
+impl From<&EncryptedMessage> for SerializableMessage {
+    fn from(message: &EncryptedMessage) -> Self {
+        Self {
+            version: message.version,
+            encrypted_data: message.encrypted_data.clone(),
+            signature: message.signature.to_bytes(),
+            sender_public: message.sender_public.to_bytes(),
+            key_exchange: message.key_exchange.clone(),
+            nonce: message.nonce.clone(),
+            timestamp: message.timestamp,
+            content_type: message.content_type,
+            suite: message.suite,
+            compression: message.compression,
+            padding: message.padding,
+            burn_after_read: message.burn_after_read,
+            conversation_id: message.conversation_id,
+            sign_mode: message.sign_mode,
+            in_reply_to: message.in_reply_to,
+            recipient_key_id: message.recipient_key_id,
+            envelope_signature: message.envelope_signature.to_bytes(),
+        }
+    }
+}
+
+impl TryFrom<SerializableMessage> for EncryptedMessage {
+    type Error = CryptoError;
+
+    fn try_from(message: SerializableMessage) -> Result<Self, Self::Error> {
+        let sender_public = VerifyingKey::from_bytes(&message.sender_public).ok_or(CryptoError::InvalidKey)?;
+        let signature = MessageSignature::from_bytes(sender_public.algorithm(), &message.signature)
+            .ok_or_else(|| CryptoError::Serialization("invalid signature".to_string()))?;
+        let envelope_signature = MessageSignature::from_bytes(sender_public.algorithm(), &message.envelope_signature)
+            .ok_or_else(|| CryptoError::Serialization("invalid envelope signature".to_string()))?;
+
+        Ok(Self {
+            version: message.version,
+            encrypted_data: message.encrypted_data,
+            signature,
+            sender_public,
+            key_exchange: message.key_exchange,
+            nonce: message.nonce,
+            timestamp: message.timestamp,
+            content_type: message.content_type,
+            suite: message.suite,
+            compression: message.compression,
+            padding: message.padding,
+            burn_after_read: message.burn_after_read,
+            conversation_id: message.conversation_id,
+            sign_mode: message.sign_mode,
+            in_reply_to: message.in_reply_to,
+            recipient_key_id: message.recipient_key_id,
+            envelope_signature,
+        })
+    }
+}
+
+impl EncryptedMessage {
+    // Canonical encoding of every transmitted field except the envelope signature itself
+    pub fn envelope_bytes(&self) -> Vec<u8> {
+        let mut bytes = ENVELOPE_CONTEXT.to_vec();
+        bytes.push(self.version);
+        push_field(&mut bytes, &self.encrypted_data);
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        bytes.extend_from_slice(self.sender_public.as_bytes());
+        push_field(&mut bytes, &self.key_exchange.to_bytes());
+        push_field(&mut bytes, &self.nonce);
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.push(self.content_type as u8);
+        bytes.extend_from_slice(&self.suite.to_bytes());
+        bytes.push(self.compression as u8);
+        bytes.extend_from_slice(&self.padding.to_bytes());
+        bytes.push(self.burn_after_read as u8);
+        bytes.extend_from_slice(&self.conversation_id);
+        bytes.push(self.sign_mode as u8);
+        push_field(&mut bytes, self.in_reply_to.as_ref().map_or(&[], |parent| parent));
+        bytes.extend_from_slice(&self.recipient_key_id);
+        bytes
+    }
+
+    // Canonical identifier: SHA-256 of envelope_bytes, so it covers the ciphertext, nonce, sender,
+    // timestamp and every other authenticated field. Replies, receipts and the audit log name a
+    // message by it. Serialization doesn't change it; any change to the message does.
+    pub fn message_id(&self) -> MessageId {
+        Sha256::digest(self.envelope_bytes()).into()
+    }
+
+    // Compact binary form for the network layer:
+    // WIRE_MAGIC | version | then every other field as u32-length-prefixed bytes.
+    // The layout is frozen per MESSAGE_VERSION; a golden fixture test in system.rs holds it to that.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut bytes = vec![WIRE_MAGIC, self.version];
+        push_field(&mut bytes, &self.encrypted_data);
+        push_field(&mut bytes, &self.signature.to_bytes());
+        push_field(&mut bytes, self.sender_public.as_bytes());
+        push_field(&mut bytes, &self.key_exchange.to_bytes());
+        push_field(&mut bytes, &self.nonce);
+        push_field(&mut bytes, &self.timestamp.to_be_bytes());
+        push_field(&mut bytes, &[self.content_type as u8]);
+        push_field(&mut bytes, &self.suite.to_bytes());
+        push_field(&mut bytes, &[self.compression as u8]);
+        push_field(&mut bytes, &self.padding.to_bytes());
+        push_field(&mut bytes, &[self.burn_after_read as u8]);
+        push_field(&mut bytes, &self.conversation_id);
+        push_field(&mut bytes, &[self.sign_mode as u8]);
+        push_field(&mut bytes, self.in_reply_to.as_ref().map_or(&[], |parent| parent));
+        push_field(&mut bytes, &self.recipient_key_id);
+        push_field(&mut bytes, &self.envelope_signature.to_bytes());
+        bytes
+    }
+
+    // Parse a message produced by to_wire, rejecting anything malformed without panicking
+    pub fn from_wire(bytes: &[u8]) -> Result<Self, WireError> {
+        Self::from_wire_limited(bytes, DEFAULT_MAX_MESSAGE_BYTES)
+    }
+
+    // from_wire refusing any field declared longer than `max_message_bytes`
+    pub fn from_wire_limited(bytes: &[u8], max_message_bytes: usize) -> Result<Self, WireError> {
+        let mut reader = WireReader { bytes, max_field: max_message_bytes };
+        let [magic, version] = reader.fixed::<2>()?;
+        if magic != WIRE_MAGIC {
+            return Err(WireError::BadMagic);
+        }
+        if version != MESSAGE_VERSION {
+            return Err(WireError::UnsupportedVersion(version));
+        }
+
+        let encrypted_data = reader.field()?.to_vec();
+        let signature = reader.field()?;
+        let sender_public = VerifyingKey::from_bytes(reader.field()?).ok_or(WireError::InvalidField("sender_public"))?;
+        let signature =
+            MessageSignature::from_bytes(sender_public.algorithm(), signature).ok_or(WireError::InvalidField("signature"))?;
+        let key_exchange = KeyExchange::from_bytes(reader.field()?).ok_or(WireError::InvalidField("key_exchange"))?;
+        let nonce = reader.field()?.to_vec();
+        let timestamp = u64::from_be_bytes(reader.sized_field::<8>("timestamp")?);
+        let content_type = match reader.sized_field::<1>("content_type")? {
+            [0] => ContentType::Text,
+            [1] => ContentType::Binary,
+            _ => return Err(WireError::InvalidField("content_type")),
+        };
+        // Unknown suite ids parse; decrypting refuses them with the component named
+        let [kem, cipher, sig, hash] = reader.sized_field::<4>("suite")?;
+        let suite = AlgorithmSuite { kem, cipher, sig, hash };
+        let [compression] = reader.sized_field::<1>("compression")?;
+        let compression = CompressionAlgo::from_u8(compression).ok_or(WireError::InvalidField("compression"))?;
+        let padding = PaddingMode::from_bytes(reader.sized_field::<5>("padding")?).ok_or(WireError::InvalidField("padding"))?;
+        let burn_after_read = match reader.sized_field::<1>("burn_after_read")? {
+            [0] => false,
+            [1] => true,
+            _ => return Err(WireError::InvalidField("burn_after_read")),
+        };
+        let conversation_id = reader.sized_field::<32>("conversation_id")?;
+        let [sign_mode] = reader.sized_field::<1>("sign_mode")?;
+        let sign_mode = SignMode::from_u8(sign_mode).ok_or(WireError::InvalidField("sign_mode"))?;
+        let in_reply_to = match reader.field()? {
+            [] => None,
+            parent => Some(parent.try_into().map_err(|_| WireError::InvalidField("in_reply_to"))?),
+        };
+        let recipient_key_id = reader.sized_field::<8>("recipient_key_id")?;
+        let envelope_signature = MessageSignature::from_bytes(sender_public.algorithm(), reader.field()?)
+            .ok_or(WireError::InvalidField("envelope_signature"))?;
+        if !reader.bytes.is_empty() {
+            return Err(WireError::TrailingBytes);
+        }
+
+        Ok(Self {
+            version,
+            encrypted_data,
+            signature,
+            sender_public,
+            key_exchange,
+            nonce,
+            timestamp,
+            content_type,
+            suite,
+            compression,
+            padding,
+            burn_after_read,
+            conversation_id,
+            sign_mode,
+            in_reply_to,
+            recipient_key_id,
+            envelope_signature,
+        })
+    }
+
+    // Wire form as base64 text, for copying through a clipboard or chat
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.to_wire())
+    }
+
+    // Parse to_base64 output, tolerating whitespace picked up while pasting
+    pub fn from_base64(text: &str) -> Result<Self, WireError> {
+        Self::from_base64_limited(text, DEFAULT_MAX_MESSAGE_BYTES)
+    }
+
+    // from_base64 with from_wire_limited's bound. Text too long to decode within it is refused unread.
+    pub fn from_base64_limited(text: &str, max_message_bytes: usize) -> Result<Self, WireError> {
+        if text.len() / 4 * 3 > max_message_bytes.saturating_add(WIRE_OVERHEAD) {
+            return Err(WireError::TooLarge(max_message_bytes));
+        }
+        let text: String = text.split_whitespace().collect();
+        let wire = BASE64.decode(text).map_err(|_| WireError::InvalidBase64)?;
+        Self::from_wire_limited(&wire, max_message_bytes)
+    }
+
+    // Serialize the message to JSON
+    pub fn to_json(&self) -> Result<String, CryptoError> {
+        serde_json::to_string(&SerializableMessage::from(self))
+            .map_err(|err| CryptoError::Serialization(err.to_string()))
+    }
+
+    // Parse a message previously produced by to_json
+    pub fn from_json(json: &str) -> Result<Self, CryptoError> {
+        let message: SerializableMessage = serde_json::from_str(json)
+            .map_err(|err| CryptoError::Serialization(err.to_string()))?;
+        message.try_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SignatureSystem;
+
+    #[test]
+    fn json_round_trip() {
+        let mut system = SignatureSystem::default();
+        system.create_user("alice".to_string()).expect("create user");
+        system.create_user("bob".to_string()).expect("create user");
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let encrypted = system.encrypt_message(alice, &bob.contact(), "meet at spawn").expect("encrypt");
+        let json = encrypted.to_json().expect("to_json");
+        let restored = EncryptedMessage::from_json(&json).expect("from_json");
+
+        assert_eq!(restored.signature.to_bytes(), encrypted.signature.to_bytes());
+        assert_eq!(restored.sender_public.as_bytes(), encrypted.sender_public.as_bytes());
+        assert_eq!(restored.envelope_bytes(), encrypted.envelope_bytes());
+        assert_eq!(system.decrypt_message(bob, &restored).expect("decrypt"), "meet at spawn");
+    }
+
+    #[test]
+    fn from_json_rejects_malformed_input() {
+        assert!(matches!(
+            EncryptedMessage::from_json("not json"),
+            Err(CryptoError::Serialization(_))
+        ));
+
+        let truncated_key = r#"{"version":4,"encrypted_data":[],"signature":[],"sender_public":[1,2,3],"key_exchange":{"rsa":{"wrapped_key":[]}},"nonce":[],"timestamp":0,"content_type":"text","envelope_signature":[]}"#;
+        assert!(EncryptedMessage::from_json(truncated_key).is_err());
+    }
+
+    fn sample_message(system: &mut SignatureSystem) -> EncryptedMessage {
+        system.create_user("alice".to_string()).expect("create user");
+        system.create_user("bob".to_string()).expect("create user");
+        system
+            .encrypt_message(&system.users["alice"], &system.users["bob"].contact(), "meet at spawn")
+            .expect("encrypt")
+    }
+
+    #[test]
+    fn cipher_tag_survives_serialization() {
+        let mut system = SignatureSystem::default();
+        system.cipher = Cipher::GcmSiv;
+        let encrypted = sample_message(&mut system);
+        assert_eq!(encrypted.suite.aead(), Ok(Cipher::GcmSiv));
+
+        let json = encrypted.to_json().expect("to_json");
+        assert!(json.contains(r#""suite":{"kem":1,"cipher":1,"sig":0,"hash":0}"#));
+        assert_eq!(EncryptedMessage::from_json(&json).expect("from_json").suite, encrypted.suite);
+        let from_wire = EncryptedMessage::from_wire(&encrypted.to_wire()).expect("from_wire");
+        assert_eq!(from_wire.suite, encrypted.suite);
+        assert_eq!(system.decrypt_message(&system.users["bob"], &from_wire).expect("decrypt"), "meet at spawn");
+
+        // Every message names its suite; there is no default to fall back on
+        let untagged = json.replace(r#""suite":{"kem":1,"cipher":1,"sig":0,"hash":0},"#, "");
+        assert!(EncryptedMessage::from_json(&untagged).is_err());
+    }
+
+    #[test]
+    fn wire_round_trip() {
+        let mut system = SignatureSystem::default();
+        let encrypted = sample_message(&mut system);
+
+        let wire = encrypted.to_wire();
+        assert!(wire.len() < encrypted.to_json().expect("to_json").len() / 2);
+        let restored = EncryptedMessage::from_wire(&wire).expect("from_wire");
+        assert_eq!(restored.envelope_bytes(), encrypted.envelope_bytes());
+        assert_eq!(restored.envelope_signature.to_bytes(), encrypted.envelope_signature.to_bytes());
+        assert_eq!(system.decrypt_message(&system.users["bob"], &restored).expect("decrypt"), "meet at spawn");
+    }
+
+    #[test]
+    fn message_id_survives_serialization() {
+        let mut system = SignatureSystem::default();
+        let encrypted = sample_message(&mut system);
+        let id = encrypted.message_id();
+
+        assert_eq!(EncryptedMessage::from_wire(&encrypted.to_wire()).expect("from_wire").message_id(), id);
+        assert_eq!(EncryptedMessage::from_json(&encrypted.to_json().expect("to_json")).expect("from_json").message_id(), id);
+
+        // Same text, same parties: a fresh nonce and key still make it a different message
+        let again = system.encrypt_message(&system.users["alice"], &system.users["bob"], "meet at spawn").expect("encrypt");
+        assert_ne!(again.message_id(), id);
+        let mut later = encrypted.clone();
+        later.timestamp += 1;
+        assert_ne!(later.message_id(), id);
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        let mut system = SignatureSystem::default();
+        let encrypted = sample_message(&mut system);
+
+        let text = encrypted.to_base64();
+        let wrapped: String = text
+            .as_bytes()
+            .chunks(64)
+            .map(|line| format!("  {}\n", std::str::from_utf8(line).expect("base64 is ascii")))
+            .collect();
+        let restored = EncryptedMessage::from_base64(&wrapped).expect("from_base64");
+        assert_eq!(system.decrypt_message(&system.users["bob"], &restored).expect("decrypt"), "meet at spawn");
+
+        assert_eq!(EncryptedMessage::from_base64("not base64!").err(), Some(WireError::InvalidBase64));
+        assert_eq!(EncryptedMessage::from_base64(&BASE64.encode(b"hello")).err(), Some(WireError::BadMagic));
+        assert_eq!(EncryptedMessage::from_base64("").err(), Some(WireError::Truncated));
+    }
+
+    #[test]
+    fn from_wire_rejects_bad_header() {
+        let mut system = SignatureSystem::default();
+        let mut wire = sample_message(&mut system).to_wire();
+
+        wire[1] = MESSAGE_VERSION + 1;
+        assert_eq!(EncryptedMessage::from_wire(&wire).err(), Some(WireError::UnsupportedVersion(MESSAGE_VERSION + 1)));
+        wire[0] ^= 0xff;
+        assert_eq!(EncryptedMessage::from_wire(&wire).err(), Some(WireError::BadMagic));
+
+        let mut padded = sample_message(&mut SignatureSystem::default()).to_wire();
+        padded.push(0);
+        assert_eq!(EncryptedMessage::from_wire(&padded).err(), Some(WireError::TrailingBytes));
+    }
+
+    #[test]
+    fn wire_integers_decode_big_endian() {
+        // Encoded by hand, as a client on any architecture must
+        let sender = [
+            0xd7, 0x5a, 0x98, 0x01, 0x82, 0xb1, 0x0a, 0xb7, 0xd5, 0x4b, 0xfe, 0xd3, 0xc9, 0x64, 0x07, 0x3a,
+            0x0e, 0xe1, 0x72, 0xf3, 0xda, 0xa6, 0x23, 0x25, 0xaf, 0x02, 0x1a, 0x68, 0xf7, 0x07, 0x51, 0x1a,
+        ];
+        let mut wire = vec![WIRE_MAGIC, MESSAGE_VERSION];
+        wire.extend_from_slice(&[0, 0, 0, 3]);
+        wire.extend_from_slice(b"abc");                            // encrypted_data
+        wire.extend_from_slice(&[0, 0, 0, 64]);
+        wire.extend_from_slice(&[0x11; 64]);                       // signature
+        wire.extend_from_slice(&[0, 0, 0, 32]);
+        wire.extend_from_slice(&sender);                           // sender_public
+        wire.extend_from_slice(&[0, 0, 0, 49, 1]);
+        wire.extend_from_slice(&[0x22; 48]);                       // key_exchange, X25519
+        wire.extend_from_slice(&[0, 0, 0, 12]);
+        wire.extend_from_slice(&[0x33; 12]);                       // nonce
+        wire.extend_from_slice(&[0, 0, 0, 8, 0x00, 0x00, 0x01, 0x8f, 0x0e, 0x1a, 0x2b, 0x3c]); // timestamp
+        wire.extend_from_slice(&[0, 0, 0, 1, 0]);                  // content_type
+        wire.extend_from_slice(&[0, 0, 0, 4, 1, 0, 0, 0]);         // suite
+        wire.extend_from_slice(&[0, 0, 0, 1, 0]);                  // compression
+        wire.extend_from_slice(&[0, 0, 0, 5, 2, 0x00, 0x01, 0x02, 0x00]); // padding, 66048-byte buckets
+        wire.extend_from_slice(&[0, 0, 0, 1, 0]);                  // burn_after_read
+        wire.extend_from_slice(&[0, 0, 0, 32]);
+        wire.extend_from_slice(&[0x44; 32]);                       // conversation_id
+        wire.extend_from_slice(&[0, 0, 0, 1, 0]);                  // sign_mode
+        wire.extend_from_slice(&[0, 0, 0, 0]);                     // in_reply_to
+        wire.extend_from_slice(&[0, 0, 0, 8, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66]); // recipient_key_id
+        wire.extend_from_slice(&[0, 0, 0, 64]);
+        wire.extend_from_slice(&[0x05; 64]);                       // envelope_signature
+
+        let message = EncryptedMessage::from_wire(&wire).expect("parse");
+        assert_eq!(message.timestamp, 0x0000_018f_0e1a_2b3c);
+        assert_eq!(message.encrypted_data, b"abc");
+        assert_eq!(message.padding, PaddingMode::Bucket(NonZeroU32::new(0x0001_0200).expect("nonzero")));
+        assert_eq!(message.to_wire(), wire);
+    }
+
+    #[test]
+    fn oversized_messages_refused() {
+        // A 4 GiB encrypted_data field in a six-byte buffer is refused on its declared length alone
+        let mut wire = vec![WIRE_MAGIC, MESSAGE_VERSION];
+        wire.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(EncryptedMessage::from_wire(&wire).err(), Some(WireError::TooLarge(DEFAULT_MAX_MESSAGE_BYTES)));
+
+        let mut system = SignatureSystem::default();
+        let message = sample_message(&mut system);
+        assert_eq!(EncryptedMessage::from_wire_limited(&message.to_wire(), 8).err(), Some(WireError::TooLarge(8)));
+        let pasted = "A".repeat(4 * 1024 * 1024);
+        assert_eq!(EncryptedMessage::from_base64_limited(&pasted, 1024).err(), Some(WireError::TooLarge(1024)));
+
+        system.policy.max_message_bytes = 16;
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        assert_eq!(system.encrypt_message(alice, bob, "sixteen bytes ok").map(|_| ()), Ok(()));
+        assert_eq!(system.encrypt_message(alice, bob, "seventeen bytes!!").err(), Some(CryptoError::TooLarge(16)));
+    }
+
+    #[test]
+    fn from_wire_survives_truncation_and_garbage() {
+        use rand::{rngs::OsRng, Rng, RngCore};
+
+        let mut system = SignatureSystem::default();
+        let wire = sample_message(&mut system).to_wire();
+
+        // Every strict prefix is an error, never a panic
+        for len in 0..wire.len() {
+            assert!(EncryptedMessage::from_wire(&wire[..len]).is_err(), "prefix of {} bytes accepted", len);
+        }
+
+        // Corrupted length prefixes and random bytes must not panic either
+        for _ in 0..2000 {
+            let mut mutated = wire.clone();
+            for _ in 0..OsRng.gen_range(1..8) {
+                let index = OsRng.gen_range(0..mutated.len());
+                mutated[index] = OsRng.gen();
+            }
+            let _ = EncryptedMessage::from_wire(&mutated);
+
+            let mut garbage = vec![0u8; OsRng.gen_range(0..256)];
+            OsRng.fill_bytes(&mut garbage);
+            let _ = EncryptedMessage::from_wire(&garbage);
+        }
+    }
+}
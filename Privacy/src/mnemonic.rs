@@ -0,0 +1,40 @@
+use crate::error::CryptoError;
+use bip39::Mnemonic;
+use rand::{rngs::OsRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+// Domain separation so the BIP39 seed isn't used directly as key material
+const KEY_SEED_CONTEXT: &[u8] = b"pgfi-user-keys-v1";
+
+// Fresh 24-word English recovery phrase
+pub fn generate_mnemonic() -> String {
+    let mut entropy = [0u8; 32];
+    OsRng.fill_bytes(&mut entropy);
+    Mnemonic::from_entropy(&entropy)
+        .map(|mnemonic| mnemonic.to_string())
+        .unwrap_or_default()
+}
+
+// Deterministic RNG for key generation, derived from a recovery phrase
+pub(crate) fn seeded_rng(phrase: &str) -> Result<ChaCha20Rng, CryptoError> {
+    let mnemonic = Mnemonic::parse(phrase).map_err(|_| CryptoError::InvalidMnemonic)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(KEY_SEED_CONTEXT);
+    hasher.update(mnemonic.to_seed(""));
+    Ok(ChaCha20Rng::from_seed(hasher.finalize().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_phrase_parses() {
+        let phrase = generate_mnemonic();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+        assert!(seeded_rng(&phrase).is_ok());
+        assert!(matches!(seeded_rng("not a recovery phrase"), Err(CryptoError::InvalidMnemonic)));
+    }
+}
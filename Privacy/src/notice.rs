@@ -0,0 +1,70 @@
+use crate::signing::{MessageSignature, VerifyingKey};
+use crate::system::SignatureSystem;
+use crate::user::User;
+
+// Domain separation, so a notice signature can't pass for a message, receipt or file signature
+const NOTICE_CONTEXT: &[u8] = b"pgfi-notice-v1";
+
+// Public announcement anyone can read and check came from the sender; nothing is encrypted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedMessage {
+    pub message: String,
+    pub sender_public: VerifyingKey,
+    pub signature: MessageSignature,     // Sender's Ed25519 signature over the message text
+}
+
+fn notice_signed_bytes(message: &str) -> Vec<u8> {
+    let mut bytes = NOTICE_CONTEXT.to_vec();
+    bytes.extend_from_slice(message.as_bytes());
+    bytes
+}
+
+impl SignatureSystem {
+    // Sign a cleartext notice such as a game announcement; only Ed25519 runs, no RSA or AES
+    pub fn sign_only(&self, sender: &User, message: &str) -> SignedMessage {
+        SignedMessage {
+            message: message.to_string(),
+            sender_public: sender.keypair.public(),
+            signature: sender.keypair.sign(&notice_signed_bytes(message)),
+        }
+    }
+
+    // True if the notice is unchanged since its sender signed it and their key isn't revoked.
+    // Check sender_public against the operator's known key before trusting who sent it.
+    pub fn verify_signed(&self, message: &SignedMessage) -> bool {
+        !self.revocations.is_revoked(&message.sender_public)
+            && message
+                .sender_public
+                .verify(&notice_signed_bytes(&message.message), &message.signature)
+                .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::system::system_with_users;
+
+    #[test]
+    fn signed_notice_verifies() {
+        let system = system_with_users(&["operator"]);
+        let notice = system.sign_only(&system.users["operator"], "Servers down for maintenance at 02:00 UTC");
+        assert_eq!(notice.message, "Servers down for maintenance at 02:00 UTC");
+        assert_eq!(notice.sender_public, system.users["operator"].keypair.public());
+        assert!(system.verify_signed(&notice));
+    }
+
+    #[test]
+    fn tampered_notice_rejected() {
+        let system = system_with_users(&["operator", "mallory"]);
+        let notice = system.sign_only(&system.users["operator"], "Double XP this weekend");
+
+        let mut altered = notice.clone();
+        altered.message = "Double XP this weekend, send your password to claim".to_string();
+        assert!(!system.verify_signed(&altered));
+
+        // Someone else's key doesn't vouch for the operator's signature
+        let mut impersonated = notice;
+        impersonated.sender_public = system.users["mallory"].keypair.public();
+        assert!(!system.verify_signed(&impersonated));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::error::TransportError;
+use crate::message::EncryptedMessage;
+
+// Network delivery used by Outbox; implement over the game's networking layer or a test double
+pub trait Transport {
+    // Hand one wire-format message to the network
+    fn send(&mut self, bytes: &[u8]) -> Result<(), TransportError>;
+}
+
+// Where a queued message is on its way out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Pending,                             // Not tried yet
+    Sent,
+    Failed(TransportError),              // Last attempt's error; the next flush tries again
+}
+
+#[derive(Clone)]
+pub struct OutboxEntry {
+    pub message: EncryptedMessage,
+    pub status: DeliveryStatus,
+}
+
+// Messages encrypted while offline, waiting for a transport. Encryption happens when the player
+// sends, so the keys and timestamp are fixed then; `flush` only moves the bytes.
+#[derive(Clone, Default)]
+pub struct Outbox {
+    entries: Vec<OutboxEntry>,           // Oldest first
+}
+
+impl Outbox {
+    pub fn queue(&mut self, message: EncryptedMessage) {
+        self.entries.push(OutboxEntry { message, status: DeliveryStatus::Pending });
+    }
+
+    pub fn entries(&self) -> &[OutboxEntry] {
+        &self.entries
+    }
+
+    // Messages not yet sent, failed ones included
+    pub fn pending(&self) -> usize {
+        self.entries.iter().filter(|entry| entry.status != DeliveryStatus::Sent).count()
+    }
+
+    // Send every unsent message, oldest first, and return how many went out. Sending stops at the
+    // first failure so later messages can't overtake it; sent messages are never sent again.
+    pub fn flush(&mut self, transport: &mut dyn Transport) -> usize {
+        let mut sent = 0;
+        for entry in self.entries.iter_mut().filter(|entry| entry.status != DeliveryStatus::Sent) {
+            match transport.send(&entry.message.to_wire()) {
+                Ok(()) => {
+                    entry.status = DeliveryStatus::Sent;
+                    sent += 1;
+                }
+                Err(err) => {
+                    entry.status = DeliveryStatus::Failed(err);
+                    break;
+                }
+            }
+        }
+        sent
+    }
+
+    // Forget messages that have been sent
+    pub fn clear_sent(&mut self) {
+        self.entries.retain(|entry| entry.status != DeliveryStatus::Sent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SignatureSystem;
+
+    // Keeps what it was given, or refuses everything while offline
+    #[derive(Default)]
+    struct MockTransport {
+        offline: bool,
+        delivered: Vec<Vec<u8>>,
+    }
+
+    impl Transport for MockTransport {
+        fn send(&mut self, bytes: &[u8]) -> Result<(), TransportError> {
+            if self.offline {
+                return Err(TransportError::Send("no route to game server".to_string()));
+            }
+            self.delivered.push(bytes.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_flush_retries_without_double_sending() {
+        let mut system = SignatureSystem::default();
+        for name in ["alice", "bob"] {
+            system.create_user(name.to_string()).expect("create user");
+        }
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let mut outbox = Outbox::default();
+        let messages: Vec<_> = ["ready", "set", "go"].iter().map(|text| system.encrypt_message(alice, bob, text).expect("encrypt")).collect();
+        for message in &messages {
+            outbox.queue(message.clone());
+        }
+
+        let mut transport = MockTransport { offline: true, ..MockTransport::default() };
+        assert_eq!(outbox.flush(&mut transport), 0);
+        assert_eq!(outbox.pending(), 3);
+        assert!(matches!(outbox.entries()[0].status, DeliveryStatus::Failed(_)));
+        assert_eq!(outbox.entries()[1].status, DeliveryStatus::Pending);
+
+        transport.offline = false;
+        assert_eq!(outbox.flush(&mut transport), 3);
+        assert_eq!(outbox.flush(&mut transport), 0);
+        assert_eq!(outbox.pending(), 0);
+        let wires: Vec<_> = messages.iter().map(EncryptedMessage::to_wire).collect();
+        assert_eq!(transport.delivered, wires);
+
+        // What arrived still reads, in the order it was sent
+        for (wire, text) in transport.delivered.iter().zip(["ready", "set", "go"]) {
+            let received = EncryptedMessage::from_wire(wire).expect("from_wire");
+            assert_eq!(system.decrypt_message(bob, &received).expect("decrypt"), text);
+        }
+        outbox.clear_sent();
+        assert!(outbox.entries().is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+use crate::user::User;
+use image::{DynamicImage, ImageOutputFormat, Luma};
+use qrcode::{EcLevel, QrCode};
+use std::io::Cursor;
+
+// Pixels per QR module, with the quiet zone the renderer adds around the code
+const MODULE_SIZE: u32 = 4;
+
+impl User {
+    // PNG of a QR code holding this user's compact public-key bundle
+    pub fn public_key_qr(&self) -> Vec<u8> {
+        // Medium error correction keeps a 2048-bit RSA bundle within a phone-scannable size
+        let Ok(code) = QrCode::with_error_correction_level(self.export_public_compact(), EcLevel::M) else {
+            return Vec::new();
+        };
+        let image = code
+            .render::<Luma<u8>>()
+            .module_dimensions(MODULE_SIZE, MODULE_SIZE)
+            .build();
+
+        let mut png = Cursor::new(Vec::new());
+        match DynamicImage::ImageLuma8(image).write_to(&mut png, ImageOutputFormat::Png) {
+            Ok(()) => png.into_inner(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::contact::import_public_contact;
+    use crate::keys::KeyScheme;
+    use crate::user::User;
+
+    #[test]
+    fn qr_decodes_to_public_keys() {
+        // Legacy RSA bundles are the largest payload a QR code has to carry
+        for scheme in [KeyScheme::X25519, KeyScheme::Rsa] {
+            let alice = User::generate("alice".to_string(), scheme).expect("generate");
+            let png = alice.public_key_qr();
+
+            let image = image::load_from_memory(&png).expect("png").to_luma8();
+            let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+                image.width() as usize,
+                image.height() as usize,
+                |x, y| image.get_pixel(x as u32, y as u32)[0],
+            );
+            let grids = prepared.detect_grids();
+            assert_eq!(grids.len(), 1);
+            let (_, content) = grids[0].decode().expect("decode");
+
+            let contact = import_public_contact(&content).expect("import");
+            assert_eq!(contact, alice.contact());
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::ct::ct_eq;
+use crate::message::EncryptedMessage;
+use crate::signing::{MessageSignature, VerifyingKey};
+use crate::system::SignatureSystem;
+use crate::user::User;
+
+// Domain separation for receipt signatures
+const RECEIPT_CONTEXT: &[u8] = b"pgfi-receipt-v1";
+
+// Recipient's signed statement that they read a particular message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Receipt {
+    pub message_digest: [u8; 32],        // The message's message_id, which covers the ciphertext
+    pub read_at: u64,                    // Unix millis when the receipt was made
+    pub signature: MessageSignature,     // Reader's signature over the digest and read_at
+}
+
+fn receipt_signed_bytes(message_digest: &[u8; 32], read_at: u64) -> Vec<u8> {
+    let mut bytes = RECEIPT_CONTEXT.to_vec();
+    bytes.extend_from_slice(message_digest);
+    bytes.extend_from_slice(&read_at.to_be_bytes());
+    bytes
+}
+
+impl SignatureSystem {
+    // Acknowledge `message` as `reader`, typically right after decrypting it
+    pub fn create_receipt(&self, reader: &User, message: &EncryptedMessage) -> Receipt {
+        let message_digest = message.message_id();
+        let read_at = self.now_millis();
+        Receipt {
+            message_digest,
+            read_at,
+            signature: reader.keypair.sign(&receipt_signed_bytes(&message_digest, read_at)),
+        }
+    }
+
+    // Check on the sender side that `reader_public` signed a receipt for exactly this message
+    pub fn verify_receipt(&self, receipt: &Receipt, message: &EncryptedMessage, reader_public: &VerifyingKey) -> bool {
+        ct_eq(&receipt.message_digest, &message.message_id())
+            && !self.revocations.is_revoked(reader_public)
+            && reader_public
+                .verify(&receipt_signed_bytes(&receipt.message_digest, receipt.read_at), &receipt.signature)
+                .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::system_with_users;
+
+    #[test]
+    fn valid_receipt_verifies() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let bob = &system.users["bob"];
+        let message = system
+            .encrypt_message(&system.users["alice"], bob, "sending you the sword")
+            .expect("encrypt");
+        system.decrypt_message(bob, &message).expect("decrypt");
+
+        let receipt = system.create_receipt(bob, &message);
+        assert!(system.verify_receipt(&receipt, &message, &bob.keypair.public()));
+
+        // Only the reader's key vouches for it, and the read time can't be changed
+        assert!(!system.verify_receipt(&receipt, &message, &system.users["carol"].keypair.public()));
+        let mut backdated = receipt.clone();
+        backdated.read_at -= 1;
+        assert!(!system.verify_receipt(&backdated, &message, &bob.keypair.public()));
+    }
+
+    #[test]
+    fn receipt_for_altered_ciphertext_rejected() {
+        let system = system_with_users(&["alice", "bob"]);
+        let bob = &system.users["bob"];
+        let message = system
+            .encrypt_message(&system.users["alice"], bob, "sending you the sword")
+            .expect("encrypt");
+        let receipt = system.create_receipt(bob, &message);
+
+        let mut altered = message.clone();
+        altered.encrypted_data[0] ^= 0x01;
+        assert!(!system.verify_receipt(&receipt, &altered, &bob.keypair.public()));
+
+        // Nor can the receipt be pointed at the altered message
+        let mut repointed = receipt.clone();
+        repointed.message_digest = altered.message_id();
+        assert!(!system.verify_receipt(&repointed, &altered, &bob.keypair.public()));
+    }
+
+    #[test]
+    fn chat_signature_is_not_a_receipt() {
+        let system = system_with_users(&["alice", "bob"]);
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let message = system.encrypt_message(alice, bob, "sending you the sword").expect("encrypt");
+        let receipt = system.create_receipt(bob, &message);
+
+        // Bob's chat whose text is exactly what a receipt signs, less the context string
+        let mut body = receipt.message_digest.to_vec();
+        body.extend_from_slice(&receipt.read_at.to_be_bytes());
+        let chat = system.encrypt_bytes(bob, alice, &body).expect("encrypt");
+        for signature in [chat.signature, chat.envelope_signature] {
+            let forged = Receipt { signature, ..receipt.clone() };
+            assert!(!system.verify_receipt(&forged, &message, &bob.keypair.public()));
+        }
+    }
+}
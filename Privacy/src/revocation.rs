@@ -0,0 +1,176 @@
+use crate::error::{DecryptError, ImportError};
+use crate::message::push_field;
+use crate::signing::{MessageSignature, VerifyingKey};
+use crate::user::User;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use std::collections::HashMap;
+
+// Domain separation for revocation signatures
+const REVOCATION_CONTEXT: &[u8] = b"pgfi-revocation-v1";
+
+// Why a signing key was revoked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RevocationReason {
+    Compromised = 0,                     // The private key leaked
+    Superseded = 1,                      // Replaced by a newer key
+    Retired = 2,                         // No longer in use
+}
+
+impl RevocationReason {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Compromised),
+            1 => Some(Self::Superseded),
+            2 => Some(Self::Retired),
+            _ => None,
+        }
+    }
+}
+
+// Statement by a signing key that it must no longer be trusted, signed by that same key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevocationCert {
+    pub revoked_key: VerifyingKey,
+    pub reason: RevocationReason,
+    pub revoked_at: u64,                 // Unix millis when the cert was made
+    pub signature: MessageSignature,     // By revoked_key over everything above
+}
+
+fn revocation_signed_bytes(revoked_key: &VerifyingKey, reason: RevocationReason, revoked_at: u64) -> Vec<u8> {
+    let mut bytes = REVOCATION_CONTEXT.to_vec();
+    push_field(&mut bytes, revoked_key.as_bytes());
+    bytes.push(reason as u8);
+    bytes.extend_from_slice(&revoked_at.to_be_bytes());
+    bytes
+}
+
+impl RevocationCert {
+    // Only the holder of the private key can revoke it, so nobody else can cut a user off
+    pub fn verify(&self) -> bool {
+        self.revoked_key
+            .verify(&revocation_signed_bytes(&self.revoked_key, self.reason, self.revoked_at), &self.signature)
+            .is_ok()
+    }
+
+    // Base64 of: length-prefixed key | reason | revoked_at | signature, for sending to peers
+    pub fn to_base64(&self) -> String {
+        let mut bytes = Vec::new();
+        push_field(&mut bytes, self.revoked_key.as_bytes());
+        bytes.push(self.reason as u8);
+        bytes.extend_from_slice(&self.revoked_at.to_be_bytes());
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        BASE64.encode(bytes)
+    }
+
+    // Parse to_base64 output; the signature is checked when the cert is imported
+    pub fn from_base64(text: &str) -> Result<Self, ImportError> {
+        let bytes = BASE64.decode(text.trim()).map_err(|_| ImportError::InvalidRevocation)?;
+        let (len, rest) = bytes.split_first_chunk::<4>().ok_or(ImportError::InvalidRevocation)?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len + 1 + 8 {
+            return Err(ImportError::InvalidRevocation);
+        }
+        let (key, rest) = rest.split_at(len);
+        let revoked_key = VerifyingKey::from_bytes(key).ok_or(ImportError::InvalidRevocation)?;
+        let reason = RevocationReason::from_u8(rest[0]).ok_or(ImportError::InvalidRevocation)?;
+        let revoked_at = u64::from_be_bytes(rest[1..9].try_into().map_err(|_| ImportError::InvalidRevocation)?);
+        let signature = MessageSignature::from_bytes(revoked_key.algorithm(), &rest[9..]).ok_or(ImportError::InvalidRevocation)?;
+        Ok(Self {
+            revoked_key,
+            reason,
+            revoked_at,
+            signature,
+        })
+    }
+}
+
+impl User {
+    // Revoke this user's current signing key as of `revoked_at`, normally SignatureSystem::now_millis;
+    // hand the cert to peers once the key is no longer safe
+    pub fn generate_revocation(&self, reason: RevocationReason, revoked_at: u64) -> RevocationCert {
+        let revoked_key = self.keypair.public();
+        RevocationCert {
+            revoked_key,
+            reason,
+            revoked_at,
+            signature: self.keypair.sign(&revocation_signed_bytes(&revoked_key, reason, revoked_at)),
+        }
+    }
+}
+
+// Revocations we have accepted, keyed by the revoked public key
+#[derive(Default)]
+pub struct RevocationStore {
+    certs: HashMap<Vec<u8>, RevocationCert>,
+}
+
+impl RevocationStore {
+    // Accept a cert after checking it was signed by the key it revokes
+    pub fn import(&mut self, cert: RevocationCert) -> Result<(), ImportError> {
+        if !cert.verify() {
+            return Err(ImportError::InvalidRevocation);
+        }
+        self.certs.insert(cert.revoked_key.to_bytes(), cert);
+        Ok(())
+    }
+
+    pub fn is_revoked(&self, key: &VerifyingKey) -> bool {
+        self.certs.contains_key(key.as_bytes())
+    }
+
+    pub fn get(&self, key: &VerifyingKey) -> Option<&RevocationCert> {
+        self.certs.get(key.as_bytes())
+    }
+
+    // Refuse anything signed by a revoked key, whatever its timestamp claims
+    pub(crate) fn check(&self, sender: &VerifyingKey) -> Result<(), DecryptError> {
+        if self.is_revoked(sender) {
+            Err(DecryptError::Revoked)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::system_with_users;
+
+    #[test]
+    fn revoked_key_rejected_after_import() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+        let first = system.encrypt_message(alice, bob, "before the leak").expect("encrypt");
+        let second = system.encrypt_message(alice, bob, "after the leak").expect("encrypt");
+        assert_eq!(system.verify_signature_only(&first, &alice.keypair.public()), Ok(()));
+        assert_eq!(system.decrypt_message(bob, &first).expect("decrypt"), "before the leak");
+
+        let cert = RevocationCert::from_base64(&alice.generate_revocation(RevocationReason::Compromised, system.now_millis()).to_base64())
+            .expect("parse");
+        assert_eq!(cert.reason, RevocationReason::Compromised);
+        system.revocations.import(cert).expect("import");
+
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        assert!(system.revocations.is_revoked(&alice.keypair.public()));
+        assert_eq!(system.verify_signature_only(&first, &alice.keypair.public()), Err(DecryptError::Revoked));
+        assert_eq!(system.decrypt_message(bob, &second), Err(DecryptError::Revoked));
+
+        // Other senders are unaffected
+        let reply = system.encrypt_message(bob, alice, "stay safe").expect("encrypt");
+        assert_eq!(system.decrypt_message(alice, &reply).expect("decrypt"), "stay safe");
+    }
+
+    #[test]
+    fn cert_for_another_key_refused() {
+        let system = system_with_users(&["alice", "mallory"]);
+        let mut cert = system.users["mallory"].generate_revocation(RevocationReason::Retired, system.now_millis());
+        cert.revoked_key = system.users["alice"].keypair.public();
+
+        let mut store = RevocationStore::default();
+        assert_eq!(store.import(cert), Err(ImportError::InvalidRevocation));
+        assert!(!store.is_revoked(&system.users["alice"].keypair.public()));
+        assert_eq!(RevocationCert::from_base64("AAAA"), Err(ImportError::InvalidRevocation));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::clock::Clock;
+use crate::contact::RecipientKeys;
+use crate::error::{CryptoError, DecryptError, ServiceError};
+use crate::history::{MessageStore, StoredMessage};
+use crate::message::{ContentType, EncryptedMessage, MessageId, MultiRecipientMessage, NO_CONVERSATION};
+use crate::system::{Rewrap, SignatureSystem};
+use crate::user::User;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// What receive did with a message that arrived
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReceiveOutcome {
+    Stored,                              // Read and filed in history
+    Duplicate,                           // Already read once; not decrypted again
+}
+
+// What became of one inbox message in receive_all
+pub type Received = (MessageId, Result<ReceiveOutcome, DecryptError>);
+
+// What the messaging screen does, without the screen: who is signed in, sending to users and
+// contacts by name, and reading incoming messages into history. The GUI renders this and
+// forwards clicks to it, so the whole flow can run in tests without a window.
+pub struct MessagingService {
+    pub system: SignatureSystem,         // Users, contacts and the policies applied to them
+    pub current_user: Option<String>,    // Identity messages are sent as; incoming text is read by any user
+    pub history: MessageStore,
+    pub inbox: Vec<EncryptedMessage>,    // Received but not yet read, see receive_all
+    pub rewrapped: HashMap<MessageId, Rewrap>, // Unread messages moved off retired keys by message_id, see rewrap_history
+    pub message_lifetime: Option<u64>,   // How long read messages stay in history, None keeps them
+}
+
+impl Default for MessagingService {
+    fn default() -> Self {
+        Self::new(SignatureSystem::default())
+    }
+}
+
+impl MessagingService {
+    // History reads the system's clock, including any set on it later
+    pub fn new(system: SignatureSystem) -> Self {
+        let mut history = MessageStore::default();
+        history.follow_clock(system.clock());
+        Self {
+            system,
+            current_user: None,
+            history,
+            inbox: Vec::new(),
+            rewrapped: HashMap::new(),
+            message_lifetime: None,
+        }
+    }
+
+    // As new, with send times, freshness checks and history expiry all read from `clock`
+    pub fn with_clock(mut system: SignatureSystem, clock: Arc<dyn Clock>) -> Self {
+        system.set_clock(clock);
+        Self::new(system)
+    }
+
+    // Encrypt a text message from one of our users to a user or contact, looked up by name
+    pub fn send(&self, from: &str, to: &str, text: &str) -> Result<EncryptedMessage, ServiceError> {
+        let (sender, recipient) = self.parties(from, to)?;
+        Ok(self.system.encrypt_message(sender, recipient, text)?)
+    }
+
+    // As send, but the recipient can only read it once
+    pub fn send_burn_after_read(&self, from: &str, to: &str, text: &str) -> Result<EncryptedMessage, ServiceError> {
+        let (sender, recipient) = self.parties(from, to)?;
+        Ok(self.system.encrypt_burn_after_read(sender, recipient, text)?)
+    }
+
+    // As send, threaded under `parent`
+    pub fn send_reply(&self, from: &str, to: &str, text: &str, parent: &EncryptedMessage) -> Result<EncryptedMessage, ServiceError> {
+        let (sender, recipient) = self.parties(from, to)?;
+        Ok(self.system.encrypt_reply(sender, recipient, text, parent)?)
+    }
+
+    fn parties(&self, from: &str, to: &str) -> Result<(&User, &dyn RecipientKeys), ServiceError> {
+        // Locked users are missing rather than unknown
+        if self.system.is_locked() {
+            return Err(CryptoError::Locked.into());
+        }
+        let sender = self.system.users.get(from).ok_or_else(|| ServiceError::UnknownUser(from.to_string()))?;
+        let recipient = self.system.recipient(to).ok_or_else(|| ServiceError::UnknownRecipient(to.to_string()))?;
+        Ok((sender, recipient))
+    }
+
+    // Parse a wire-format text message, read it as whichever user it is for and file it in history
+    pub fn receive(&mut self, wire_bytes: &[u8]) -> Result<ReceiveOutcome, ServiceError> {
+        let message = EncryptedMessage::from_wire_limited(wire_bytes, self.system.policy.max_message_bytes)?;
+        self.receive_message(&message)
+    }
+
+    fn signed_in(&self) -> Result<&User, ServiceError> {
+        let username = self.current_user.as_deref().ok_or(ServiceError::NotSignedIn)?;
+        if self.system.is_locked() {
+            return Err(DecryptError::Locked.into());
+        }
+        self.system.users.get(username).ok_or_else(|| ServiceError::UnknownUser(username.to_string()))
+    }
+
+    fn expires_at(&self) -> Option<u64> {
+        self.message_lifetime.map(|lifetime| self.system.now_millis().saturating_add(lifetime))
+    }
+
+    // receive for a message that is already parsed, such as one imported as JSON. A message
+    // delivered twice, by a retry or over two paths, is recognised by its id and not read again.
+    pub fn receive_message(&mut self, message: &EncryptedMessage) -> Result<ReceiveOutcome, ServiceError> {
+        if self.system.is_locked() {
+            return Err(DecryptError::Locked.into());
+        }
+        if self.history.has_seen(&message.message_id()) {
+            // A second copy of a burned message still takes the first one out of history
+            if message.burn_after_read {
+                self.history.burn(&message.message_id());
+            }
+            return Ok(ReceiveOutcome::Duplicate);
+        }
+        let expires_at = self.expires_at();
+        let result = match self.rewrapped.get(&message.message_id()) {
+            Some(rewrap) => self
+                .system
+                .users
+                .get(&rewrap.recipient)
+                .ok_or(DecryptError::WrongRecipient)
+                .and_then(|user| self.system.decrypt_rewrapped(user, message, rewrap).map(|text| (user.username.clone(), text))),
+            None => self.system.decrypt_any(message).map(|(user, text)| (user.username.clone(), text.display())),
+        };
+        let (username, text) = match result {
+            Ok(read) => read,
+            Err(err) => {
+                // A second copy of a burned message also takes the first one out of history
+                if err == DecryptError::AlreadyRead {
+                    self.history.burn(&message.message_id());
+                }
+                return Err(err.into());
+            }
+        };
+        // Text that isn't clean UTF-8 is still kept, badged as such
+        self.history.mark_seen(message.message_id());
+        self.rewrapped.remove(&message.message_id());
+        self.history.add(stored(message, username, text, expires_at));
+        Ok(ReceiveOutcome::Stored)
+    }
+
+    // Move every inbox message sent to one of `username`'s retired keys onto their current key,
+    // after which the inbox no longer needs the keys in User::retired and they can be dropped.
+    // Messages for other users are left alone. Returns how many were moved.
+    pub fn rewrap_history(&mut self, username: &str) -> Result<usize, ServiceError> {
+        if self.system.is_locked() {
+            return Err(DecryptError::Locked.into());
+        }
+        let user = self.system.users.get(username).ok_or_else(|| ServiceError::UnknownUser(username.to_string()))?;
+        let mut moved = 0;
+        for message in &self.inbox {
+            let id = message.message_id();
+            let previous = self.rewrapped.get(&id).filter(|rewrap| rewrap.recipient == username);
+            match self.system.rewrap(user, message, previous) {
+                Ok(Some(rewrap)) => {
+                    self.rewrapped.insert(id, rewrap);
+                    moved += 1;
+                }
+                Ok(None) | Err(CryptoError::Forward(DecryptError::WrongRecipient)) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(moved)
+    }
+
+    // Try every inbox text message addressed to `as_user`, in inbox order. Each message gets its
+    // own outcome, so a corrupt one doesn't stop the rest being read.
+    pub fn decrypt_all(&self, as_user: &User) -> Vec<(MessageId, Result<String, DecryptError>)> {
+        self.inbox
+            .iter()
+            .filter(|message| self.can_read(message, as_user))
+            .map(|message| (message.message_id(), self.read(as_user, message)))
+            .collect()
+    }
+
+    // Whether decrypt_all and receive_all will try `message` as `user`: a text message wrapped to one
+    // of their keys, or moved to their current key by rewrap_history. Reads nothing, so the GUI's
+    // inbox badge counts with it too.
+    pub fn can_read(&self, message: &EncryptedMessage, user: &User) -> bool {
+        message.content_type == ContentType::Text && (self.system.can_decrypt(user, message) || self.rewrap_for(message, user).is_some())
+    }
+
+    fn rewrap_for(&self, message: &EncryptedMessage, user: &User) -> Option<&Rewrap> {
+        self.rewrapped.get(&message.message_id()).filter(|rewrap| rewrap.recipient == user.username)
+    }
+
+    fn read(&self, user: &User, message: &EncryptedMessage) -> Result<String, DecryptError> {
+        match self.rewrap_for(message, user) {
+            Some(rewrap) => self.system.decrypt_rewrapped(user, message, rewrap),
+            None => self.system.decrypt_message(user, message),
+        }
+    }
+
+    // decrypt_all, filing what was read in history. As with receive_message, a message already read
+    // is reported as a Duplicate rather than decrypted again. Every message tried leaves the inbox,
+    // since a second attempt would only be refused as a replay; messages for other users stay.
+    pub fn receive_all(&mut self, username: &str) -> Result<Vec<Received>, ServiceError> {
+        if self.system.is_locked() {
+            return Err(DecryptError::Locked.into());
+        }
+        let user = self.system.users.get(username).ok_or_else(|| ServiceError::UnknownUser(username.to_string()))?;
+        let expires_at = self.expires_at();
+        let (tried, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.inbox).into_iter().partition(|message| self.can_read(message, user));
+        self.inbox = rest;
+        let mut outcomes = Vec::with_capacity(tried.len());
+        for message in &tried {
+            let id = message.message_id();
+            let outcome = if self.history.has_seen(&id) {
+                // A second copy of a burned message still takes the first one out of history
+                if message.burn_after_read {
+                    self.history.burn(&id);
+                }
+                Ok(ReceiveOutcome::Duplicate)
+            } else {
+                self.read(&self.system.users[username], message).map(|text| {
+                    self.history.mark_seen(id);
+                    self.history.add(stored(message, username.to_string(), text, expires_at));
+                    ReceiveOutcome::Stored
+                })
+            };
+            self.rewrapped.remove(&id);
+            outcomes.push((id, outcome));
+        }
+        Ok(outcomes)
+    }
+
+    // Decrypt a file sent to the current user; files aren't kept in history
+    pub fn receive_bytes(&self, message: &EncryptedMessage) -> Result<Vec<u8>, ServiceError> {
+        Ok(self.system.decrypt_bytes(self.signed_in()?, message)?)
+    }
+
+    // Read a party message as the current user and file it in history. As with receive_message, a
+    // second delivery is recognised by its id; it is filed once even if several of our users are in the party.
+    pub fn receive_multi(&mut self, message: &MultiRecipientMessage) -> Result<ReceiveOutcome, ServiceError> {
+        let user = self.signed_in()?;
+        let id = message.message_id();
+        if self.history.has_seen(&id) {
+            return Ok(ReceiveOutcome::Duplicate);
+        }
+        let recipient = user.username.clone();
+        let body = self.system.decrypt_multi(user, message)?;
+        self.history.mark_seen(id);
+        self.history.add(StoredMessage {
+            sender: BASE64.encode(message.sender_public.as_bytes()),
+            recipient,
+            timestamp: message.timestamp,
+            body,
+            expires_at: self.expires_at(),
+            conversation_id: NO_CONVERSATION,
+            message_id: id,
+            read: false,
+        });
+        Ok(ReceiveOutcome::Stored)
+    }
+
+    // Every unexpired message in the order it was read
+    pub fn list_history(&self) -> Vec<&StoredMessage> {
+        self.history.search("", None, None, 0, usize::MAX)
+    }
+}
+
+fn stored(message: &EncryptedMessage, recipient: String, body: String, expires_at: Option<u64>) -> StoredMessage {
+    StoredMessage {
+        sender: BASE64.encode(message.sender_public.as_bytes()),
+        recipient,
+        timestamp: message.timestamp,
+        body,
+        expires_at,
+        conversation_id: message.conversation_id,
+        message_id: message.message_id(),
+        read: false,
+    }
+}
@@ -0,0 +1,382 @@
+use crate::contact::RecipientKeys;
+use crate::error::{CryptoError, DecryptError};
+use crate::keys::{DecryptionKey, EncryptionKey};
+use crate::user::User;
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm,
+    Key,
+    Nonce,
+};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use std::collections::HashMap;
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
+
+// Domain separation for each ratchet KDF
+const SESSION_CONTEXT: &[u8] = b"pgfi-session-v1";
+const ROOT_KDF_CONTEXT: &[u8] = b"pgfi-ratchet-root-v1";
+const CHAIN_KDF_CONTEXT: &[u8] = b"pgfi-ratchet-chain-v1";
+
+// Every message key encrypts exactly one message, so a fixed nonce is never reused
+const MESSAGE_NONCE: [u8; 12] = [0u8; 12];
+
+// Most message keys one incoming message may make us derive and hold for later
+const MAX_SKIP: u32 = 1000;
+
+type ChainKey = Zeroizing<[u8; 32]>;
+type MessageKey = Zeroizing<[u8; 32]>;
+
+// Our current ratchet secret, wiped when replaced
+struct RatchetSecret(StaticSecret);
+
+impl Clone for RatchetSecret {
+    fn clone(&self) -> Self {
+        Self(StaticSecret::from(self.0.to_bytes()))
+    }
+}
+
+impl Drop for RatchetSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+// One direction's symmetric ratchet
+#[derive(Clone)]
+struct Chain {
+    key: ChainKey,
+    counter: u32,                        // Messages already derived from this chain
+}
+
+impl Chain {
+    // Advance the chain, returning the key for the next message
+    fn step(&mut self) -> MessageKey {
+        let mut output = Zeroizing::new([0u8; 64]);
+        Hkdf::<Sha256>::from_prk(self.key.as_ref())
+            .expect("32 bytes is a valid HKDF-SHA256 PRK")
+            .expand(CHAIN_KDF_CONTEXT, output.as_mut())
+            .expect("64 bytes is a valid HKDF-SHA256 output length");
+        self.key.copy_from_slice(&output[..32]);
+        self.counter += 1;
+
+        let mut message_key = Zeroizing::new([0u8; 32]);
+        message_key.copy_from_slice(&output[32..]);
+        message_key
+    }
+}
+
+// Unencrypted part of a session message, authenticated as associated data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionHeader {
+    pub ratchet_public: [u8; 32],        // Sender's current ratchet public key
+    pub previous_chain_len: u32,         // Messages sent on the sender's previous chain
+    pub counter: u32,                    // Position in the current sending chain
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionMessage {
+    pub header: SessionHeader,
+    pub ciphertext: Vec<u8>,
+}
+
+impl SessionHeader {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = SESSION_CONTEXT.to_vec();
+        bytes.extend_from_slice(&self.ratchet_public);
+        bytes.extend_from_slice(&self.previous_chain_len.to_be_bytes());
+        bytes.extend_from_slice(&self.counter.to_be_bytes());
+        bytes
+    }
+}
+
+// Ongoing conversation with forward secrecy: every message uses a fresh key from a
+// hash chain, and each reply mixes a new X25519 exchange into the root key. Old keys
+// are deleted as soon as they are used, so a later compromise can't read earlier messages.
+// Both sides authenticate implicitly through their static X25519 keys; messages are not signed.
+// Until the responder first replies, the initiator's messages are only as safe as the responder's static key.
+#[derive(Clone)]
+pub struct Session {
+    root_key: Zeroizing<[u8; 32]>,
+    ratchet: RatchetSecret,
+    peer_ratchet: Option<X25519PublicKey>, // None until the initiator's first message arrives
+    sending: Option<Chain>,
+    receiving: Option<Chain>,
+    previous_sending_len: u32,
+    skipped: HashMap<([u8; 32], u32), MessageKey>, // Keys for messages that haven't arrived yet
+}
+
+// Both static X25519 keys a session is built from
+fn static_keys<'a>(me: &'a User, peer: &dyn RecipientKeys) -> Result<(&'a StaticSecret, X25519PublicKey), CryptoError> {
+    match (&me.decryption_key, peer.encryption_key()) {
+        (DecryptionKey::X25519(secret), EncryptionKey::X25519(public)) => Ok((secret, *public)),
+        _ => Err(CryptoError::InvalidKey),
+    }
+}
+
+// Shared secret between the two static keys, bound to who initiated
+fn initial_root_key(secret: &StaticSecret, peer: &X25519PublicKey, initiator: &X25519PublicKey, responder: &X25519PublicKey) -> Result<Zeroizing<[u8; 32]>, CryptoError> {
+    let shared = secret.diffie_hellman(peer);
+    if !shared.was_contributory() {
+        return Err(CryptoError::InvalidKey);
+    }
+    let mut info = SESSION_CONTEXT.to_vec();
+    info.extend_from_slice(initiator.as_bytes());
+    info.extend_from_slice(responder.as_bytes());
+
+    let mut root_key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, shared.as_bytes())
+        .expand(&info, root_key.as_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Ok(root_key)
+}
+
+// Mix a DH output into the root key, yielding the next root key and a new chain
+fn root_step(root_key: &mut Zeroizing<[u8; 32]>, secret: &StaticSecret, peer: &X25519PublicKey) -> Option<Chain> {
+    let shared = secret.diffie_hellman(peer);
+    if !shared.was_contributory() {
+        return None;
+    }
+    let mut output = Zeroizing::new([0u8; 64]);
+    Hkdf::<Sha256>::new(Some(root_key.as_ref()), shared.as_bytes())
+        .expand(ROOT_KDF_CONTEXT, output.as_mut())
+        .expect("64 bytes is a valid HKDF-SHA256 output length");
+    root_key.copy_from_slice(&output[..32]);
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&output[32..]);
+    Some(Chain { key, counter: 0 })
+}
+
+fn message_cipher(message_key: &MessageKey) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(message_key.as_ref()))
+}
+
+impl Session {
+    // Start a session with `peer`, who must accept it before replying
+    pub fn initiate(me: &User, peer: &dyn RecipientKeys) -> Result<Self, CryptoError> {
+        let (secret, peer_public) = static_keys(me, peer)?;
+        let mut root_key = initial_root_key(secret, &peer_public, &X25519PublicKey::from(secret), &peer_public)?;
+
+        // The peer's static key acts as their first ratchet key
+        let ratchet = RatchetSecret(StaticSecret::random_from_rng(OsRng));
+        let sending = root_step(&mut root_key, &ratchet.0, &peer_public).ok_or(CryptoError::InvalidKey)?;
+        Ok(Self {
+            root_key,
+            ratchet,
+            peer_ratchet: Some(peer_public),
+            sending: Some(sending),
+            receiving: None,
+            previous_sending_len: 0,
+            skipped: HashMap::new(),
+        })
+    }
+
+    // Answer a session started by `peer`; we can send once their first message is decrypted
+    pub fn accept(me: &User, peer: &dyn RecipientKeys) -> Result<Self, CryptoError> {
+        let (secret, peer_public) = static_keys(me, peer)?;
+        let root_key = initial_root_key(secret, &peer_public, &peer_public, &X25519PublicKey::from(secret))?;
+        Ok(Self {
+            root_key,
+            ratchet: RatchetSecret(StaticSecret::from(secret.to_bytes())),
+            peer_ratchet: None,
+            sending: None,
+            receiving: None,
+            previous_sending_len: 0,
+            skipped: HashMap::new(),
+        })
+    }
+
+    // Encrypt under the next key of the sending chain
+    pub fn encrypt(&mut self, message: &str) -> Result<SessionMessage, CryptoError> {
+        let sending = self.sending.as_mut().ok_or(CryptoError::SessionNotEstablished)?;
+        let header = SessionHeader {
+            ratchet_public: X25519PublicKey::from(&self.ratchet.0).to_bytes(),
+            previous_chain_len: self.previous_sending_len,
+            counter: sending.counter,
+        };
+        let message_key = sending.step();
+        let ciphertext = message_cipher(&message_key)
+            .encrypt(
+                Nonce::from_slice(&MESSAGE_NONCE),
+                Payload {
+                    msg: message.as_bytes(),
+                    aad: &header.to_bytes(),
+                },
+            )
+            .map_err(|_| CryptoError::Encryption)?;
+        Ok(SessionMessage { header, ciphertext })
+    }
+
+    // Decrypt the next message from the peer; the session is unchanged if this fails
+    pub fn decrypt(&mut self, message: &SessionMessage) -> Result<String, DecryptError> {
+        let mut next = self.clone();
+        let message_key = next.message_key(&message.header)?;
+        let data = message_cipher(&message_key)
+            .decrypt(
+                Nonce::from_slice(&MESSAGE_NONCE),
+                Payload {
+                    msg: &message.ciphertext,
+                    aad: &message.header.to_bytes(),
+                },
+            )
+            .map_err(|_| DecryptError::CorruptCiphertext)?;
+        let text = String::from_utf8(data).map_err(|_| DecryptError::MalformedUtf8)?;
+        *self = next;
+        Ok(text)
+    }
+
+    // Find or derive the key for an incoming header, ratcheting forward as needed
+    fn message_key(&mut self, header: &SessionHeader) -> Result<MessageKey, DecryptError> {
+        if let Some(message_key) = self.skipped.remove(&(header.ratchet_public, header.counter)) {
+            return Ok(message_key);
+        }
+
+        let peer_ratchet = X25519PublicKey::from(header.ratchet_public);
+        if self.peer_ratchet != Some(peer_ratchet) {
+            // The peer replied with a new ratchet key: finish the old chain, then step the root twice
+            self.skip_until(header.previous_chain_len)?;
+            self.previous_sending_len = self.sending.as_ref().map_or(0, |chain| chain.counter);
+            self.peer_ratchet = Some(peer_ratchet);
+            self.receiving = Some(root_step(&mut self.root_key, &self.ratchet.0, &peer_ratchet).ok_or(DecryptError::CorruptCiphertext)?);
+            self.ratchet = RatchetSecret(StaticSecret::random_from_rng(OsRng));
+            self.sending = Some(root_step(&mut self.root_key, &self.ratchet.0, &peer_ratchet).ok_or(DecryptError::CorruptCiphertext)?);
+        }
+
+        self.skip_until(header.counter)?;
+        let receiving = self.receiving.as_mut().ok_or(DecryptError::CorruptCiphertext)?;
+        if header.counter < receiving.counter {
+            // Its key was used and deleted already
+            return Err(DecryptError::NonceReused);
+        }
+        Ok(receiving.step())
+    }
+
+    // Derive and keep receiving keys up to `counter` for messages that arrive out of order
+    fn skip_until(&mut self, counter: u32) -> Result<(), DecryptError> {
+        let (Some(receiving), Some(peer_ratchet)) = (self.receiving.as_mut(), self.peer_ratchet) else {
+            return Ok(());
+        };
+        if counter > receiving.counter.saturating_add(MAX_SKIP) {
+            return Err(DecryptError::TooManySkipped);
+        }
+        while receiving.counter < counter {
+            let index = receiving.counter;
+            self.skipped.insert((peer_ratchet.to_bytes(), index), receiving.step());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyScheme;
+
+    fn pair() -> (User, User) {
+        let alice = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate");
+        let bob = User::generate("bob".to_string(), KeyScheme::X25519).expect("generate");
+        (alice, bob)
+    }
+
+    fn sessions(alice: &User, bob: &User) -> (Session, Session) {
+        let initiator = Session::initiate(alice, &bob.contact()).expect("initiate");
+        let responder = Session::accept(bob, &alice.contact()).expect("accept");
+        (initiator, responder)
+    }
+
+    #[test]
+    fn conversation_round_trip() {
+        let (alice, bob) = pair();
+        let (mut to_bob, mut to_alice) = sessions(&alice, &bob);
+        assert_eq!(to_alice.encrypt("too early").unwrap_err(), CryptoError::SessionNotEstablished);
+
+        for round in 0..3 {
+            for i in 0..3 {
+                let text = format!("alice {} {}", round, i);
+                assert_eq!(to_alice.decrypt(&to_bob.encrypt(&text).expect("encrypt")).expect("decrypt"), text);
+            }
+            let reply = to_alice.encrypt("ack").expect("encrypt");
+            assert_eq!(reply.header.counter, 0);
+            assert_eq!(to_bob.decrypt(&reply).expect("decrypt"), "ack");
+        }
+    }
+
+    #[test]
+    fn every_message_uses_a_new_key_and_replies_ratchet() {
+        let (alice, bob) = pair();
+        let (mut to_bob, mut to_alice) = sessions(&alice, &bob);
+        let first = to_bob.encrypt("same").expect("encrypt");
+        let second = to_bob.encrypt("same").expect("encrypt");
+        assert_ne!(first.ciphertext, second.ciphertext);
+        assert_eq!(first.header.ratchet_public, second.header.ratchet_public);
+
+        to_alice.decrypt(&first).expect("decrypt");
+        let reply = to_alice.encrypt("reply").expect("encrypt");
+        to_bob.decrypt(&reply).expect("decrypt");
+        let third = to_bob.encrypt("same").expect("encrypt");
+        assert_ne!(third.header.ratchet_public, first.header.ratchet_public);
+    }
+
+    #[test]
+    fn out_of_order_and_replayed_messages() {
+        let (alice, bob) = pair();
+        let (mut to_bob, mut to_alice) = sessions(&alice, &bob);
+        let messages: Vec<_> = (0..4).map(|i| to_bob.encrypt(&i.to_string()).expect("encrypt")).collect();
+
+        assert_eq!(to_alice.decrypt(&messages[2]).expect("decrypt"), "2");
+        assert_eq!(to_alice.decrypt(&messages[0]).expect("decrypt"), "0");
+        assert_eq!(to_alice.decrypt(&messages[3]).expect("decrypt"), "3");
+        assert_eq!(to_alice.decrypt(&messages[1]).expect("decrypt"), "1");
+        assert_eq!(to_alice.decrypt(&messages[1]), Err(DecryptError::NonceReused));
+
+        // A tampered message leaves the session as it was
+        let mut tampered = to_bob.encrypt("4").expect("encrypt");
+        let genuine = tampered.clone();
+        tampered.ciphertext[0] ^= 0x01;
+        assert_eq!(to_alice.decrypt(&tampered), Err(DecryptError::CorruptCiphertext));
+        assert_eq!(to_alice.decrypt(&genuine).expect("decrypt"), "4");
+
+        let mut far_ahead = to_bob.encrypt("5").expect("encrypt");
+        far_ahead.header.counter += MAX_SKIP + 1;
+        assert_eq!(to_alice.decrypt(&far_ahead), Err(DecryptError::TooManySkipped));
+    }
+
+    #[test]
+    fn later_compromise_cannot_read_earlier_messages() {
+        let (alice, bob) = pair();
+        let (mut to_bob, mut to_alice) = sessions(&alice, &bob);
+
+        // Record the traffic an eavesdropper would capture
+        let mut captured = Vec::new();
+        for i in 0..3 {
+            let message = to_bob.encrypt(&format!("before {}", i)).expect("encrypt");
+            to_alice.decrypt(&message).expect("decrypt");
+            captured.push(message);
+        }
+        let reply = to_alice.encrypt("reply").expect("encrypt");
+        to_bob.decrypt(&reply).expect("decrypt");
+        captured.push(reply);
+        let later = to_bob.encrypt("after").expect("encrypt");
+
+        // Steal both sides' entire state afterwards, static keys included
+        let mut stolen_alice = to_alice.clone();
+        let mut stolen_bob = to_bob.clone();
+        for message in &captured[..3] {
+            assert!(stolen_alice.decrypt(message).is_err());
+        }
+        assert!(stolen_bob.decrypt(&captured[3]).is_err());
+
+        // The stolen state still reads what comes next, which is what the ratchet heals over time
+        assert_eq!(stolen_alice.decrypt(&later).expect("decrypt"), "after");
+    }
+
+    #[test]
+    fn rsa_users_cannot_open_sessions() {
+        let alice = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate");
+        let bob = User::generate("bob".to_string(), KeyScheme::Rsa).expect("generate");
+        assert_eq!(Session::initiate(&alice, &bob.contact()).err(), Some(CryptoError::InvalidKey));
+    }
+}
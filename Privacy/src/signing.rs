@@ -0,0 +1,229 @@
+use crate::error::DecryptError;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer as _, Verifier};
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+// Signature algorithms a user can sign with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    #[default]
+    Ed25519,
+}
+
+// One signature algorithm. The enums below dispatch to an implementation per variant,
+// so adding an algorithm means a new impl and variant, not changes to every caller.
+pub trait SignatureScheme {
+    type SigningKey;
+    type VerifyingKey;
+    type Signature;
+
+    const ALGORITHM: SignatureAlgorithm;
+
+    fn sign(key: &Self::SigningKey, message: &[u8]) -> Self::Signature;
+    fn verify(key: &Self::VerifyingKey, message: &[u8], signature: &Self::Signature) -> bool;
+    fn public_bytes(key: &Self::VerifyingKey) -> Vec<u8>;
+}
+
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    type SigningKey = Keypair;
+    type VerifyingKey = PublicKey;
+    type Signature = Signature;
+
+    const ALGORITHM: SignatureAlgorithm = SignatureAlgorithm::Ed25519;
+
+    fn sign(key: &Keypair, message: &[u8]) -> Signature {
+        key.sign(message)
+    }
+
+    fn verify(key: &PublicKey, message: &[u8], signature: &Signature) -> bool {
+        key.verify(message, signature).is_ok()
+    }
+
+    fn public_bytes(key: &PublicKey) -> Vec<u8> {
+        key.as_bytes().to_vec()
+    }
+}
+
+// Whatever signs on a user's behalf. SigningKey holds the key in memory; a PKCS#11 token or other
+// HSM can implement this with the key never leaving the device, and every caller stays the same.
+pub trait Signer: Send + Sync {
+    fn sign(&self, message: &[u8]) -> MessageSignature;
+    fn public(&self) -> VerifyingKey;
+
+    // Raw private key for keystores and identity exports; None when it can't leave its hardware
+    fn secret_bytes(&self) -> Option<Zeroizing<Vec<u8>>> {
+        None
+    }
+}
+
+// Private half of a user's signing identity
+pub enum SigningKey {
+    Ed25519(Keypair),
+}
+
+// Public half, carried in messages so recipients can check the sender
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyingKey {
+    Ed25519(PublicKey),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageSignature {
+    Ed25519(Signature),
+}
+
+impl SigningKey {
+    // Fresh key drawn from `csprng`; recovery from a mnemonic relies on this consuming the same bytes every time
+    pub(crate) fn generate<R: RngCore + CryptoRng>(algorithm: SignatureAlgorithm, csprng: &mut R) -> Option<Self> {
+        match algorithm {
+            SignatureAlgorithm::Ed25519 => {
+                let mut secret = Zeroizing::new([0u8; 32]);
+                csprng.fill_bytes(secret.as_mut());
+                Self::from_secret_bytes(algorithm, secret.as_ref())
+            }
+        }
+    }
+
+    pub(crate) fn from_secret_bytes(algorithm: SignatureAlgorithm, bytes: &[u8]) -> Option<Self> {
+        match algorithm {
+            SignatureAlgorithm::Ed25519 => {
+                let secret = SecretKey::from_bytes(bytes).ok()?;
+                let public = PublicKey::from(&secret);
+                Some(Self::Ed25519(Keypair { secret, public }))
+            }
+        }
+    }
+
+    pub(crate) fn secret_bytes(&self) -> Zeroizing<Vec<u8>> {
+        match self {
+            Self::Ed25519(keypair) => Zeroizing::new(keypair.secret.to_bytes().to_vec()),
+        }
+    }
+
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        match self {
+            Self::Ed25519(_) => Ed25519::ALGORITHM,
+        }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> MessageSignature {
+        match self {
+            Self::Ed25519(keypair) => MessageSignature::Ed25519(Ed25519::sign(keypair, message)),
+        }
+    }
+
+    pub fn public(&self) -> VerifyingKey {
+        match self {
+            Self::Ed25519(keypair) => VerifyingKey::Ed25519(keypair.public),
+        }
+    }
+}
+
+impl Signer for SigningKey {
+    fn sign(&self, message: &[u8]) -> MessageSignature {
+        SigningKey::sign(self, message)
+    }
+
+    fn public(&self) -> VerifyingKey {
+        SigningKey::public(self)
+    }
+
+    fn secret_bytes(&self) -> Option<Zeroizing<Vec<u8>>> {
+        Some(SigningKey::secret_bytes(self))
+    }
+}
+
+impl VerifyingKey {
+    // Algorithms are told apart by key length: 32 bytes is Ed25519
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.len() {
+            32 => PublicKey::from_bytes(bytes).ok().map(Self::Ed25519),
+            _ => None,
+        }
+    }
+
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        match self {
+            Self::Ed25519(_) => Ed25519::ALGORITHM,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Ed25519(public) => public.as_bytes(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Ed25519(public) => Ed25519::public_bytes(public),
+        }
+    }
+
+    // A signature from another algorithm never verifies
+    pub fn verify(&self, message: &[u8], signature: &MessageSignature) -> Result<(), DecryptError> {
+        let valid = match (self, signature) {
+            (Self::Ed25519(public), MessageSignature::Ed25519(signature)) => Ed25519::verify(public, message, signature),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(DecryptError::InvalidSignature)
+        }
+    }
+}
+
+impl MessageSignature {
+    // Signatures of different algorithms can share a length, so the caller names the algorithm
+    pub fn from_bytes(algorithm: SignatureAlgorithm, bytes: &[u8]) -> Option<Self> {
+        match algorithm {
+            SignatureAlgorithm::Ed25519 => Signature::from_bytes(bytes).ok().map(Self::Ed25519),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Ed25519(signature) => signature.to_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn ed25519_through_trait_verifies() {
+        let signing = SigningKey::generate(SignatureAlgorithm::Ed25519, &mut OsRng).expect("generate");
+        let SigningKey::Ed25519(keypair) = &signing;
+
+        // The scheme itself and the enum wrapping it agree
+        let signature = Ed25519::sign(keypair, b"gg");
+        assert!(Ed25519::verify(&keypair.public, b"gg", &signature));
+        assert!(!Ed25519::verify(&keypair.public, b"gg wp", &signature));
+        assert_eq!(signing.sign(b"gg"), MessageSignature::Ed25519(signature));
+
+        let public = signing.public();
+        assert_eq!(public.algorithm(), SignatureAlgorithm::Ed25519);
+        assert_eq!(public.verify(b"gg", &signing.sign(b"gg")), Ok(()));
+        assert_eq!(public.verify(b"gg wp", &signing.sign(b"gg")), Err(DecryptError::InvalidSignature));
+    }
+
+    #[test]
+    fn keys_and_signatures_round_trip_through_bytes() {
+        let signing = SigningKey::generate(SignatureAlgorithm::Ed25519, &mut OsRng).expect("generate");
+        let public = VerifyingKey::from_bytes(&signing.public().to_bytes()).expect("public key");
+        assert_eq!(public, signing.public());
+        assert_eq!(VerifyingKey::from_bytes(&[0u8; 33]), None);
+
+        let restored = SigningKey::from_secret_bytes(signing.algorithm(), &signing.secret_bytes()).expect("secret key");
+        assert_eq!(restored.public(), public);
+
+        let signature = signing.sign(b"hello");
+        let parsed = MessageSignature::from_bytes(public.algorithm(), &signature.to_bytes()).expect("signature");
+        assert_eq!(public.verify(b"hello", &parsed), Ok(()));
+    }
+}
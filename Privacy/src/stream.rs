@@ -0,0 +1,409 @@
+use crate::contact::RecipientKeys;
+use crate::error::{CryptoError, DecryptError};
+use crate::keys::KeyExchange;
+use crate::signing::{MessageSignature, VerifyingKey};
+use crate::system::{SignatureSystem, SymmetricKey};
+use crate::user::User;
+use aes_gcm::{aead::Aead, Nonce};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use zeroize::Zeroizing;
+
+// Stream layout:
+//   MAGIC | VERSION | sender public key | timestamp | nonce prefix | key exchange length | key exchange
+//   then chunks of: last flag | ciphertext length | ciphertext
+//   then an Ed25519 signature over the SHA-256 of everything before it
+const STREAM_MAGIC: &[u8; 4] = b"PGST";
+const STREAM_VERSION: u8 = 2;
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+const SIGNATURE_LEN: usize = 64;
+
+// Plaintext bytes per chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+// Domain separation for stream signatures
+const STREAM_SIGNATURE_CONTEXT: &[u8] = b"pgfi-stream-v1";
+
+fn io_error(err: io::Error) -> CryptoError {
+    CryptoError::Io(err.to_string())
+}
+
+fn read_error(err: io::Error) -> DecryptError {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => DecryptError::Truncated,
+        _ => DecryptError::Io(err.to_string()),
+    }
+}
+
+// Called with (input bytes done, total) after every chunk
+pub type Progress<'a> = dyn FnMut(u64, u64) + 'a;
+
+// Progress reporting and cancellation for a running stream
+pub struct StreamControl<'a> {
+    pub total: u64,                      // Input length passed on as bytes_total, 0 if unknown
+    pub progress: &'a mut Progress<'a>,
+    pub cancel: &'a AtomicBool,          // Checked before every chunk; set it to stop the stream
+}
+
+impl StreamControl<'_> {
+    fn report(&mut self, done: u64) {
+        (self.progress)(done, self.total);
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+// Nonce for one chunk: random prefix | big-endian counter | last-chunk flag
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+// Bytes covered by the stream signature
+fn stream_signed_bytes(recipient: &str, digest: &[u8]) -> Vec<u8> {
+    let mut bytes = STREAM_SIGNATURE_CONTEXT.to_vec();
+    bytes.extend_from_slice(recipient.as_bytes());
+    bytes.extend_from_slice(digest);
+    bytes
+}
+
+// Fill `buf` from the reader, stopping early only at end of input
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+// Writer that hashes everything passing through it
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), CryptoError> {
+        self.hasher.update(bytes);
+        self.inner.write_all(bytes).map_err(io_error)
+    }
+}
+
+// Reader that hashes everything read through it; end of input mid-field means truncation
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    read: u64,                           // Bytes consumed so far, for progress reports
+}
+
+impl<R: Read> HashingReader<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), DecryptError> {
+        self.inner.read_exact(buf).map_err(read_error)?;
+        self.hasher.update(&*buf);
+        self.read += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecryptError> {
+        let mut bytes = [0u8; N];
+        self.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl SignatureSystem {
+    // Encrypt everything from `reader` into `writer` in CHUNK_SIZE pieces, without buffering it all
+    pub fn encrypt_stream<R: Read, W: Write>(
+        &self,
+        sender: &User,
+        recipient: &dyn RecipientKeys,
+        reader: R,
+        writer: W,
+    ) -> Result<(), CryptoError> {
+        let cancel = AtomicBool::new(false);
+        let control = StreamControl {
+            total: 0,
+            progress: &mut |_, _| {},
+            cancel: &cancel,
+        };
+        self.encrypt_stream_with(sender, recipient, reader, writer, control)
+    }
+
+    // encrypt_stream reporting plaintext bytes read. On cancel the final chunk and signature
+    // are never written and nothing is flushed, so the partial output can't decrypt.
+    pub fn encrypt_stream_with<R: Read, W: Write>(
+        &self,
+        sender: &User,
+        recipient: &dyn RecipientKeys,
+        mut reader: R,
+        writer: W,
+        mut control: StreamControl<'_>,
+    ) -> Result<(), CryptoError> {
+        let symmetric_key = SymmetricKey::generate(&mut OsRng);
+        let cipher = symmetric_key.cipher();
+        let key_exchange = recipient.encryption_key().wrap(&symmetric_key, &mut OsRng)?.to_bytes();
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+
+        let mut out = HashingWriter {
+            inner: writer,
+            hasher: Sha256::new(),
+        };
+        out.write_all(STREAM_MAGIC)?;
+        out.write_all(&[STREAM_VERSION])?;
+        out.write_all(sender.keypair.public().as_bytes())?;
+        out.write_all(&self.now_millis().to_be_bytes())?;
+        out.write_all(&prefix)?;
+        out.write_all(&(key_exchange.len() as u16).to_be_bytes())?;
+        out.write_all(&key_exchange)?;
+
+        // Read one chunk ahead so the final chunk can be flagged
+        let mut current = Zeroizing::new(vec![0u8; CHUNK_SIZE]);
+        let mut next = Zeroizing::new(vec![0u8; CHUNK_SIZE]);
+        let mut current_len = read_chunk(&mut reader, &mut current).map_err(io_error)?;
+        let mut done = current_len as u64;
+        let mut counter: u32 = 0;
+        loop {
+            if control.cancelled() {
+                return Err(CryptoError::Cancelled);
+            }
+            let next_len = if current_len == CHUNK_SIZE {
+                read_chunk(&mut reader, &mut next).map_err(io_error)?
+            } else {
+                0
+            };
+            let last = next_len == 0;
+
+            let nonce = chunk_nonce(&prefix, counter, last);
+            let ciphertext = cipher
+                .encrypt(Nonce::from_slice(&nonce), &current[..current_len])
+                .map_err(|_| CryptoError::Encryption)?;
+            out.write_all(&[last as u8])?;
+            out.write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+            out.write_all(&ciphertext)?;
+            control.report(done);
+
+            if last {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            current_len = next_len;
+            done += next_len as u64;
+            counter = counter.checked_add(1).ok_or(CryptoError::Encryption)?;
+        }
+
+        // Sign the whole stream for this recipient
+        let digest = out.hasher.finalize();
+        let signature = sender.keypair.sign(&stream_signed_bytes(&recipient.fingerprint(), &digest));
+        out.inner.write_all(&signature.to_bytes()).map_err(io_error)?;
+        out.inner.flush().map_err(io_error)
+    }
+
+    // Decrypt a stream from encrypt_stream into `writer`.
+    // Plaintext is written as chunks authenticate, so on any error the output must be discarded.
+    pub fn decrypt_stream<R: Read, W: Write>(&self, recipient: &User, reader: R, writer: W) -> Result<(), DecryptError> {
+        let cancel = AtomicBool::new(false);
+        let control = StreamControl {
+            total: 0,
+            progress: &mut |_, _| {},
+            cancel: &cancel,
+        };
+        self.decrypt_stream_with(recipient, reader, writer, control)
+    }
+
+    // decrypt_stream reporting encrypted bytes read. A cancelled stream is never flushed
+    // and its output must be discarded like any other failure.
+    pub fn decrypt_stream_with<R: Read, W: Write>(
+        &self,
+        recipient: &User,
+        reader: R,
+        mut writer: W,
+        mut control: StreamControl<'_>,
+    ) -> Result<(), DecryptError> {
+        let mut input = HashingReader {
+            inner: reader,
+            hasher: Sha256::new(),
+            read: 0,
+        };
+        if &input.read_array::<4>()? != STREAM_MAGIC {
+            return Err(DecryptError::CorruptCiphertext);
+        }
+        let [version] = input.read_array::<1>()?;
+        if version != STREAM_VERSION {
+            return Err(DecryptError::UnsupportedVersion(version));
+        }
+        let sender_public = VerifyingKey::from_bytes(&input.read_array::<32>()?).ok_or(DecryptError::CorruptCiphertext)?;
+        self.revocations.check(&sender_public)?;
+        let timestamp = u64::from_be_bytes(input.read_array::<8>()?);
+        self.check_timestamp(timestamp, self.now_millis())?;
+        let prefix = input.read_array::<NONCE_PREFIX_LEN>()?;
+        let exchange_len = u16::from_be_bytes(input.read_array::<2>()?) as usize;
+        let mut key_exchange = vec![0u8; exchange_len];
+        input.read_exact(&mut key_exchange)?;
+        let key_exchange = KeyExchange::from_bytes(&key_exchange).ok_or(DecryptError::CorruptCiphertext)?;
+
+        // Streams sent before a key rotation were wrapped to a retired key
+        let (fingerprint, symmetric_key) = recipient
+            .decryption_keys()
+            .find_map(|(fingerprint, decryption_key)| Some((fingerprint, decryption_key.unwrap(&key_exchange).ok()?)))
+            .ok_or(DecryptError::WrongRecipient)?;
+        let cipher = symmetric_key.cipher();
+
+        let mut ciphertext = vec![0u8; CHUNK_SIZE + TAG_LEN];
+        let mut counter: u32 = 0;
+        loop {
+            if control.cancelled() {
+                return Err(DecryptError::Cancelled);
+            }
+            let [flag] = input.read_array::<1>()?;
+            let last = match flag {
+                0 => false,
+                1 => true,
+                _ => return Err(DecryptError::CorruptCiphertext),
+            };
+            let len = u32::from_be_bytes(input.read_array::<4>()?) as usize;
+            if len > ciphertext.len() {
+                return Err(DecryptError::CorruptCiphertext);
+            }
+            input.read_exact(&mut ciphertext[..len])?;
+
+            // The flag is part of the nonce, so a chunk can't be relabelled as the last one
+            let nonce = chunk_nonce(&prefix, counter, last);
+            let plaintext = cipher
+                .decrypt(Nonce::from_slice(&nonce), &ciphertext[..len])
+                .map(Zeroizing::new)
+                .map_err(|_| DecryptError::CorruptCiphertext)?;
+            writer.write_all(&plaintext).map_err(read_error)?;
+            control.report(input.read);
+
+            if last {
+                break;
+            }
+            counter = counter.checked_add(1).ok_or(DecryptError::CorruptCiphertext)?;
+        }
+
+        // Verify the sender's signature over everything read so far
+        let digest = input.hasher.finalize();
+        let mut signature = [0u8; SIGNATURE_LEN];
+        input.inner.read_exact(&mut signature).map_err(|_| DecryptError::Truncated)?;
+        let signature = MessageSignature::from_bytes(sender_public.algorithm(), &signature).ok_or(DecryptError::InvalidSignature)?;
+        sender_public
+            .verify(&stream_signed_bytes(&fingerprint, &digest), &signature)
+            .map_err(|_| DecryptError::InvalidSignature)?;
+        writer.flush().map_err(read_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::system_with_users;
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        OsRng.fill_bytes(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn one_megabyte_stream_round_trip() {
+        let system = system_with_users(&["alice", "bob"]);
+        let replay = random_bytes(1024 * 1024);
+
+        let mut encrypted = Vec::new();
+        system
+            .encrypt_stream(&system.users["alice"], &system.users["bob"].contact(), replay.as_slice(), &mut encrypted)
+            .expect("encrypt");
+
+        let mut decrypted = Vec::new();
+        system
+            .decrypt_stream(&system.users["bob"], encrypted.as_slice(), &mut decrypted)
+            .expect("decrypt");
+        assert_eq!(decrypted, replay);
+    }
+
+    #[test]
+    fn dropped_trailing_chunk_rejected() {
+        let system = system_with_users(&["alice", "bob"]);
+        let replay = random_bytes(3 * CHUNK_SIZE);
+
+        let mut encrypted = Vec::new();
+        system
+            .encrypt_stream(&system.users["alice"], &system.users["bob"].contact(), replay.as_slice(), &mut encrypted)
+            .expect("encrypt");
+
+        // Cut the final chunk and the signature off the end
+        let final_chunk = 1 + 4 + CHUNK_SIZE + TAG_LEN;
+        encrypted.truncate(encrypted.len() - SIGNATURE_LEN - final_chunk);
+        let result = system.decrypt_stream(&system.users["bob"], encrypted.as_slice(), io::sink());
+        assert_eq!(result, Err(DecryptError::Truncated));
+    }
+
+    #[test]
+    fn stream_for_someone_else_rejected() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let mut encrypted = Vec::new();
+        system
+            .encrypt_stream(&system.users["alice"], &system.users["bob"].contact(), &b"replay"[..], &mut encrypted)
+            .expect("encrypt");
+
+        let result = system.decrypt_stream(&system.users["carol"], encrypted.as_slice(), io::sink());
+        assert_eq!(result, Err(DecryptError::WrongRecipient));
+    }
+    #[test]
+    fn cancelled_stream_stops_without_valid_output() {
+        let system = system_with_users(&["alice", "bob"]);
+        let replay = random_bytes(4 * CHUNK_SIZE);
+        let cancel = AtomicBool::new(false);
+        let mut reports = Vec::new();
+        let mut progress = |done: u64, total: u64| {
+            reports.push((done, total));
+            if done >= 2 * CHUNK_SIZE as u64 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        };
+
+        let mut encrypted = Vec::new();
+        let control = StreamControl {
+            total: replay.len() as u64,
+            progress: &mut progress,
+            cancel: &cancel,
+        };
+        let result = system.encrypt_stream_with(&system.users["alice"], &system.users["bob"].contact(), replay.as_slice(), &mut encrypted, control);
+        assert_eq!(result, Err(CryptoError::Cancelled));
+        assert_eq!(reports, [(CHUNK_SIZE as u64, replay.len() as u64), (2 * CHUNK_SIZE as u64, replay.len() as u64)]);
+
+        // What was written before the cancel has no final chunk or signature
+        let result = system.decrypt_stream(&system.users["bob"], encrypted.as_slice(), io::sink());
+        assert_eq!(result, Err(DecryptError::Truncated));
+
+        // Decryption stops the same way
+        let mut encrypted = Vec::new();
+        system
+            .encrypt_stream(&system.users["alice"], &system.users["bob"].contact(), replay.as_slice(), &mut encrypted)
+            .expect("encrypt");
+        let cancel = AtomicBool::new(true);
+        let control = StreamControl {
+            total: encrypted.len() as u64,
+            progress: &mut |_, _| {},
+            cancel: &cancel,
+        };
+        let mut decrypted = Vec::new();
+        let result = system.decrypt_stream_with(&system.users["bob"], encrypted.as_slice(), &mut decrypted, control);
+        assert_eq!(result, Err(DecryptError::Cancelled));
+        assert!(decrypted.is_empty());
+    }
+}
@@ -0,0 +1,2395 @@
+#[cfg(feature = "tracing")]
+use crate::audit::to_hex;
+use crate::audit::{AuditKey, AuditLog};
+use crate::burn::BurnedSet;
+use crate::clock::{Clock, SharedClock};
+use crate::contact::{contacts_bundle, import_public_contact, parse_contacts_bundle, Contact, RecipientKeys};
+use crate::ct::ct_eq;
+use crate::error::{CreateError, CryptoError, DecryptError, ImportError, KeystoreError};
+use crate::keys::{DecryptionKey, EncryptionKey, KeyConfig, KeyExchange, KeyScheme};
+use crate::message::{
+    batch_nonce, blinded_recipients, ciphertext_sign_bytes, push_field, AlgorithmSuite, AnonymousMessage, BatchEntry, BatchMessage, Cipher, CompressionAlgo, ContentType, DecryptedText, EncryptedMessage, MultiRecipientMessage, PaddingMode, SignMode, WrappedKey, DEFAULT_MAX_MESSAGE_BYTES, LEGACY_PKCS1_VERSION, MESSAGE_VERSION,
+};
+use crate::revocation::RevocationStore;
+use crate::tofu::{TofuStore, TrustPolicy};
+use crate::signing::{MessageSignature, VerifyingKey};
+use crate::user::{conversation_between, CreateThrottle, PendingUser, User};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, Payload},
+    Aes256Gcm,
+    Key,
+    Nonce,
+};
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::ChaCha20Poly1305;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use rand::{rngs::OsRng, CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// Domain separation for message signatures
+const SIGNATURE_CONTEXT: &[u8] = b"pgfi-message-v2";
+
+// Domain separation for AES-GCM associated data
+const AAD_CONTEXT: &[u8] = b"pgfi-aad-v1";
+
+// Domain separation for the key ids nonces are tracked under
+const KEY_ID_CONTEXT: &[u8] = b"pgfi-key-id-v1";
+
+// Default freshness window for incoming messages
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+// Largest plaintext a compressed message may expand to, so a small message can't inflate without bound
+const MAX_DECOMPRESSED_LEN: u64 = 64 * 1024 * 1024;
+
+// AES-256 message key, wiped from memory when dropped
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub(crate) struct SymmetricKey([u8; 32]);
+
+impl SymmetricKey {
+    pub(crate) fn generate<R: RngCore + CryptoRng>(csprng: &mut R) -> Self {
+        let mut key = Self([0u8; 32]);
+        csprng.fill_bytes(&mut key.0);
+        key
+    }
+
+    // Copy an unwrapped key, rejecting anything that isn't 32 bytes
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, DecryptError> {
+        if bytes.len() != 32 {
+            return Err(DecryptError::CorruptCiphertext);
+        }
+        let mut key = Self([0u8; 32]);
+        key.0.copy_from_slice(bytes);
+        Ok(key)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub(crate) fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0))
+    }
+
+    // Seal with whichever AEAD the message is tagged with; `nonce` must be 12 bytes
+    fn seal_with(&self, cipher: Cipher, nonce: &[u8], payload: Payload<'_, '_>) -> Result<Vec<u8>, aes_gcm::Error> {
+        match cipher {
+            Cipher::Gcm => self.cipher().encrypt(Nonce::from_slice(nonce), payload),
+            Cipher::GcmSiv => Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&self.0)).encrypt(Nonce::from_slice(nonce), payload),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(Key::<ChaCha20Poly1305>::from_slice(&self.0)).encrypt(Nonce::from_slice(nonce), payload),
+        }
+    }
+
+    fn open_with(&self, cipher: Cipher, nonce: &[u8], payload: Payload<'_, '_>) -> Result<Vec<u8>, aes_gcm::Error> {
+        match cipher {
+            Cipher::Gcm => self.cipher().decrypt(Nonce::from_slice(nonce), payload),
+            Cipher::GcmSiv => Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&self.0)).decrypt(Nonce::from_slice(nonce), payload),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(Key::<ChaCha20Poly1305>::from_slice(&self.0)).decrypt(Nonce::from_slice(nonce), payload),
+        }
+    }
+
+    // Digest naming this key within `scope` without revealing it
+    fn id(&self, scope: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_ID_CONTEXT);
+        hasher.update((scope.len() as u32).to_be_bytes());
+        hasher.update(scope);
+        hasher.update(self.0);
+        hasher.finalize().into()
+    }
+}
+
+// (key id, nonce) pairs already used, with the timestamp of the message that used them
+#[derive(Default)]
+struct NonceLog {
+    seen: HashMap<([u8; 32], [u8; 12]), u64>,
+}
+
+impl NonceLog {
+    // Remember a pair, returning false if it was seen before
+    fn record(&mut self, key_id: [u8; 32], nonce: &[u8], timestamp: u64) -> bool {
+        let Ok(nonce) = nonce.try_into() else {
+            return false;
+        };
+        self.seen.insert((key_id, nonce), timestamp).is_none()
+    }
+
+    // Forget pairs from messages the freshness policy would now reject anyway
+    fn prune(&mut self, oldest: u64) {
+        self.seen.retain(|_, timestamp| *timestamp >= oldest);
+    }
+}
+
+// Any cryptographically secure generator can supply keys and nonces
+pub trait SecureRng: RngCore + CryptoRng + Send {}
+
+impl<R: RngCore + CryptoRng + Send> SecureRng for R {}
+
+// OsRng unless the system was built around a seeded generator for test vectors
+struct SharedRng(Mutex<Box<dyn SecureRng>>);
+
+impl Default for SharedRng {
+    fn default() -> Self {
+        Self(Mutex::new(Box::new(OsRng)))
+    }
+}
+
+// When secret keys were last used, in Unix millis by the system's clock, for the idle lock.
+// None until the first check after creation or a clock change, which starts the timer.
+#[derive(Default)]
+struct LastActivity(Mutex<Option<u64>>);
+
+impl LastActivity {
+    fn slot(&self) -> MutexGuard<'_, Option<u64>> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// How a single-recipient payload is framed, beyond its bytes
+#[derive(Clone, Copy)]
+struct Framing {
+    content_type: ContentType,
+    compression: CompressionAlgo,
+    burn_after_read: bool,
+    in_reply_to: Option<[u8; 32]>,
+}
+
+impl Framing {
+    fn text() -> Self {
+        Self {
+            content_type: ContentType::Text,
+            compression: CompressionAlgo::None,
+            burn_after_read: false,
+            in_reply_to: None,
+        }
+    }
+
+    fn binary() -> Self {
+        Self { content_type: ContentType::Binary, ..Self::text() }
+    }
+}
+
+// Freshly encrypted payload whose symmetric key still needs wrapping
+struct SealedPayload {
+    symmetric_key: SymmetricKey,
+    nonce: Vec<u8>,
+    encrypted_data: Vec<u8>,
+}
+
+// A message key moved by SignatureSystem::rewrap from a retired key onto the recipient's current one.
+// The sender's signatures cover the original key exchange, so this sits beside the message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rewrap {
+    pub recipient: String,               // Username whose current key holds the message key
+    pub addressed_to: String,            // Fingerprint the sender encrypted to, which the ciphertext stays bound to
+    pub key_exchange: KeyExchange,       // Message key wrapped to the current encryption key
+}
+
+// Limits on how old or how far ahead an incoming message may be, and how large any message may be
+pub struct MessagePolicy {
+    pub max_age: Duration,
+    pub max_clock_skew: Duration,
+    pub max_message_bytes: usize,        // Largest plaintext encrypted, and field parsed by MessagingService
+}
+
+impl Default for MessagePolicy {
+    fn default() -> Self {
+        Self {
+            max_age: DEFAULT_MAX_AGE,
+            max_clock_skew: DEFAULT_MAX_CLOCK_SKEW,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+        }
+    }
+}
+
+// Context the ciphertext is bound to through AES-GCM, so it can't be replayed under another
+// sender, recipient set, send time or content type without failing authentication
+fn associated_data(sender: &VerifyingKey, recipients: &[String], timestamp: u64, content_type: ContentType) -> Vec<u8> {
+    let mut recipients = recipients.to_vec();
+    recipients.sort();
+
+    let mut bytes = AAD_CONTEXT.to_vec();
+    push_field(&mut bytes, sender.as_bytes());
+    bytes.extend_from_slice(&(recipients.len() as u32).to_be_bytes());
+    for fingerprint in &recipients {
+        push_field(&mut bytes, fingerprint.as_bytes());
+    }
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    bytes.push(content_type as u8);
+    bytes
+}
+
+// associated_data for a single-recipient message, plus the parent a reply names
+fn message_associated_data(
+    sender: &VerifyingKey,
+    recipients: &[String],
+    timestamp: u64,
+    content_type: ContentType,
+    in_reply_to: Option<&[u8; 32]>,
+) -> Vec<u8> {
+    let mut bytes = associated_data(sender, recipients, timestamp, content_type);
+    if let Some(parent) = in_reply_to {
+        bytes.extend_from_slice(parent);
+    }
+    bytes
+}
+
+// Associated data for an anonymous message: its own fields plus who it was sealed for
+fn anonymous_associated_data(message: &AnonymousMessage, recipient: &str) -> Vec<u8> {
+    let mut bytes = message.authenticated_bytes();
+    push_field(&mut bytes, recipient.as_bytes());
+    bytes
+}
+
+// Bytes covered by the sender's signature, built the same way on both sides: context, then the
+// sorted recipients, timestamp, content type and plaintext in that order, each length-prefixed so
+// no two different sets of fields can encode to the same bytes
+fn canonical_sign_bytes(recipients: &[String], timestamp: u64, content_type: ContentType, message: &[u8]) -> Vec<u8> {
+    let mut recipients = recipients.to_vec();
+    recipients.sort();
+
+    let mut bytes = SIGNATURE_CONTEXT.to_vec();
+    bytes.extend_from_slice(&(recipients.len() as u32).to_be_bytes());
+    for fingerprint in &recipients {
+        push_field(&mut bytes, fingerprint.as_bytes());
+    }
+    push_field(&mut bytes, &timestamp.to_be_bytes());
+    push_field(&mut bytes, &[content_type as u8]);
+    push_field(&mut bytes, message);
+    bytes
+}
+
+fn compress(compression: CompressionAlgo, data: &[u8]) -> Result<Cow<'_, [u8]>, CryptoError> {
+    match compression {
+        CompressionAlgo::None => Ok(Cow::Borrowed(data)),
+        CompressionAlgo::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).map_err(|err| CryptoError::Io(err.to_string()))?;
+            encoder.finish().map(Cow::Owned).map_err(|err| CryptoError::Io(err.to_string()))
+        }
+    }
+}
+
+fn pad(padding: PaddingMode, data: Cow<'_, [u8]>) -> Result<Cow<'_, [u8]>, CryptoError> {
+    if padding == PaddingMode::None {
+        return Ok(data);
+    }
+    let len = u32::try_from(data.len()).map_err(|_| CryptoError::Encryption)?;
+    let padded_len = padding.padded_len(data.len()).ok_or(CryptoError::Encryption)?;
+    let mut padded = Vec::with_capacity(padded_len);
+    padded.extend_from_slice(&len.to_be_bytes());
+    padded.extend_from_slice(&data);
+    padded.resize(padded_len, 0);
+    Ok(Cow::Owned(padded))
+}
+
+// Only ever run on authenticated plaintext
+fn unpad(padding: PaddingMode, mut data: Zeroizing<Vec<u8>>) -> Result<Zeroizing<Vec<u8>>, DecryptError> {
+    if padding == PaddingMode::None {
+        return Ok(data);
+    }
+    let (len, rest) = data.split_first_chunk::<4>().ok_or(DecryptError::BadPadding)?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if len > rest.len() {
+        return Err(DecryptError::BadPadding);
+    }
+    data.copy_within(4..4 + len, 0);
+    data[len..].zeroize();
+    data.truncate(len);
+    Ok(data)
+}
+
+// Only ever run on authenticated plaintext. Read a chunk at a time rather than with read_to_end,
+// so no buffer the plaintext outgrows is freed without being wiped.
+fn decompress(compression: CompressionAlgo, data: Zeroizing<Vec<u8>>) -> Result<Zeroizing<Vec<u8>>, DecryptError> {
+    match compression {
+        CompressionAlgo::None => Ok(data),
+        CompressionAlgo::Deflate => {
+            let mut decoder = DeflateDecoder::new(data.as_slice()).take(MAX_DECOMPRESSED_LEN + 1);
+            let mut decompressed = Zeroizing::new(Vec::new());
+            let mut chunk = Zeroizing::new([0u8; 8 * 1024]);
+            loop {
+                let read = decoder.read(chunk.as_mut_slice()).map_err(|_| DecryptError::Decompression)?;
+                if read == 0 {
+                    break;
+                }
+                if decompressed.capacity() - decompressed.len() < read {
+                    let mut grown = Zeroizing::new(Vec::with_capacity((decompressed.len() + read).max(decompressed.capacity() * 2)));
+                    grown.extend_from_slice(&decompressed);
+                    std::mem::swap(&mut decompressed, &mut grown);
+                }
+                decompressed.extend_from_slice(&chunk[..read]);
+            }
+            if decompressed.len() as u64 > MAX_DECOMPRESSED_LEN {
+                return Err(DecryptError::Decompression);
+            }
+            Ok(decompressed)
+        }
+    }
+}
+
+// Refuse payloads whose key was wrapped with anything but OAEP
+fn check_version(version: u8) -> Result<(), DecryptError> {
+    match version {
+        MESSAGE_VERSION => Ok(()),
+        LEGACY_PKCS1_VERSION => Err(DecryptError::LegacyPadding),
+        other => Err(DecryptError::UnsupportedVersion(other)),
+    }
+}
+
+// Payload checks applied before a message counts as received
+fn any_payload(_: &[u8]) -> Result<(), DecryptError> {
+    Ok(())
+}
+
+fn text_payload(data: &[u8]) -> Result<(), DecryptError> {
+    std::str::from_utf8(data)
+        .map(|_| ())
+        .map_err(|_| DecryptError::MalformedUtf8)
+}
+
+// Check the sender's outer signature before touching any other field
+fn verify_envelope(sender: &VerifyingKey, envelope: &[u8], signature: &MessageSignature) -> Result<(), DecryptError> {
+    sender
+        .verify(envelope, signature)
+        .map_err(|_| DecryptError::TamperedEnvelope)
+}
+
+// Check a SignMode::OverCiphertext signature, which needs nothing but the message
+fn verify_ciphertext_signature(message: &EncryptedMessage) -> Result<(), DecryptError> {
+    let signed = ciphertext_sign_bytes(message.timestamp, message.content_type, &message.nonce, &message.encrypted_data);
+    message
+        .sender_public
+        .verify(&signed, &message.signature)
+        .map_err(|_| DecryptError::InvalidSignature)
+}
+
+// Users and contacts known to this process plus the policy applied to incoming messages
+#[derive(Default)]
+pub struct SignatureSystem {
+    pub users: HashMap<String, User>,
+    pub contacts: HashMap<String, Contact>, // People we can message but hold no private keys for
+    pub policy: MessagePolicy,
+    pub key_scheme: KeyScheme,              // Encryption scheme for newly created users
+    pub key_config: KeyConfig,              // Key sizes for newly created users
+    pub cipher: Cipher,                     // AEAD for newly encrypted messages, batches included
+    pub padding: PaddingMode,               // Length hiding for newly encrypted single-recipient messages
+    pub sign_mode: SignMode,                // What the inner signature of new single-recipient messages covers
+    pub revocations: RevocationStore,       // Signing keys whose messages are refused
+    pub known_peers: TofuStore,             // Fingerprint first seen for each contact name
+    pub trust_policy: TrustPolicy,          // Which recipients encryption is allowed to
+    pub create_throttle: CreateThrottle,    // No cooldown by default; the GUI sets one
+    pub idle_timeout: Option<Duration>,     // Wipe secret keys after this long unused, None never does
+    nonces: Mutex<NonceLog>,                // Shared by encrypt and decrypt, which only borrow self
+    audit: Mutex<AuditLog>,                 // Every single-message decrypt attempt
+    audit_key: AuditKey,                    // Signs each audit entry
+    burned: Mutex<BurnedSet>,               // Burn-after-read messages already opened
+    rng: SharedRng,                         // Source for new users, message keys and nonces
+    clock: SharedClock,                     // Send times and freshness checks, SystemClock by default
+    last_activity: LastActivity,            // Last encrypt or decrypt, for idle_timeout
+    locked: Option<HashMap<String, Contact>>, // Public halves of the wiped users while locked
+}
+
+impl SignatureSystem {
+    // Draw every key and nonce from `rng`; a seeded generator makes output reproducible for test vectors
+    pub fn with_rng(rng: impl SecureRng + 'static) -> Self {
+        Self {
+            rng: SharedRng(Mutex::new(Box::new(rng))),
+            ..Self::default()
+        }
+    }
+
+    // Read the time from `clock` instead of the wall clock, so tests can move it by hand
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock.set(clock);
+        // Times from the old clock mean nothing against the new one
+        *self.last_activity.slot() = None;
+    }
+
+    // Handle to the clock slot, for history that must expire messages by the same time
+    pub(crate) fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    // Unix millis by the system's clock
+    pub fn now_millis(&self) -> u64 {
+        self.clock.now_millis()
+    }
+
+    // Create a new user with keypair
+    pub fn create_user(&mut self, username: String) -> Result<(), CreateError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("create_user", username = %username, scheme = ?self.key_scheme).entered();
+        let result = self.generate_user(username.clone());
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(user) => tracing::info!(fingerprint = %user.fingerprint(), "created user"),
+            Err(CreateError::Busy) => tracing::warn!("user creation throttled"),
+            Err(err) => tracing::error!(error = ?err, "user creation failed"),
+        }
+        self.users.insert(username, result?);
+        Ok(())
+    }
+
+    fn generate_user(&mut self, username: String) -> Result<User, CreateError> {
+        self.check_username_free(&username)?;
+        let _ticket = self.create_throttle.begin()?;
+        Ok(User::generate_with_rng(username, self.key_scheme, self.key_config, &mut *self.rng())?)
+    }
+
+    // Create a user whose keys can be recovered from the same phrase later
+    pub fn create_user_from_mnemonic(&mut self, username: String, mnemonic: &str) -> Result<(), CreateError> {
+        self.check_username_free(&username)?;
+        let _ticket = self.create_throttle.begin()?;
+        let user = User::from_mnemonic_with_config(username.clone(), mnemonic, self.key_scheme, self.key_config)?;
+        self.users.insert(username, user);
+        Ok(())
+    }
+
+    // Creating a user must never replace one we already hold keys for
+    fn check_username_free(&self, username: &str) -> Result<(), CreateError> {
+        if self.users.contains_key(username) {
+            return Err(CreateError::DuplicateUsername);
+        }
+        Ok(())
+    }
+
+    // Start generating a user on a background thread; the throttle stays busy until it finishes
+    pub fn spawn_user(&mut self, username: String) -> Result<PendingUser, CreateError> {
+        self.check_username_free(&username)?;
+        let ticket = self.create_throttle.begin()?;
+        let (name, scheme, config) = (username.clone(), self.key_scheme, self.key_config);
+        Ok(PendingUser::run(username, move || {
+            let _ticket = ticket;
+            User::generate_with_config(name, scheme, config)
+        }))
+    }
+
+    // Start recovering a user from a phrase on a background thread
+    pub fn spawn_user_from_mnemonic(&mut self, username: String, phrase: String) -> Result<PendingUser, CreateError> {
+        self.check_username_free(&username)?;
+        let ticket = self.create_throttle.begin()?;
+        let (name, scheme, config) = (username.clone(), self.key_scheme, self.key_config);
+        let phrase = Zeroizing::new(phrase);
+        Ok(PendingUser::run(username, move || {
+            let _ticket = ticket;
+            User::from_mnemonic_with_config(name, &phrase, scheme, config)
+        }))
+    }
+
+    // Import a public key bundle into the address book under a local name.
+    // Keys that differ from the ones first seen under that name are refused with KeyChanged.
+    pub fn add_contact(&mut self, name: String, pem: &str) -> Result<(), ImportError> {
+        let contact = import_public_contact(pem)?;
+        self.known_peers.check(&name, &contact.fingerprint())?;
+        self.contacts.insert(name, contact);
+        Ok(())
+    }
+
+    // Import a bundle whose changed keys the user has explicitly confirmed
+    pub fn accept_contact(&mut self, name: String, pem: &str) -> Result<(), ImportError> {
+        let contact = import_public_contact(pem)?;
+        self.known_peers.accept(&name, &contact.fingerprint());
+        self.contacts.insert(name, contact);
+        Ok(())
+    }
+
+    // Every contact with its name, as JSON, so a whole guild can be handed over at once
+    pub fn export_contacts_bundle(&self) -> String {
+        contacts_bundle(&self.contacts)
+    }
+
+    // Add each contact in a bundle from export_contacts_bundle under its name, as add_contact does.
+    // Every entry gets its own result, and a bad one doesn't stop the rest from importing.
+    pub fn import_contacts_bundle(&mut self, bundle: &str) -> Vec<Result<Contact, ImportError>> {
+        parse_contacts_bundle(bundle)
+            .into_iter()
+            .map(|entry| {
+                let (name, contact) = entry?;
+                self.known_peers.check(&name, &contact.fingerprint())?;
+                self.contacts.insert(name, contact.clone());
+                Ok(contact)
+            })
+            .collect()
+    }
+
+    // Refuse a recipient the trust policy doesn't allow. Our own users always pass: we hold their
+    // private keys, so there is nothing to compare.
+    pub fn check_trust(&self, recipient: &dyn RecipientKeys) -> Result<(), CryptoError> {
+        if self.trust_policy == TrustPolicy::AllowAll {
+            return Ok(());
+        }
+        let fingerprint = recipient.fingerprint();
+        if self.known_peers.is_fingerprint_verified(&fingerprint) || self.users.values().any(|user| ct_eq(user.fingerprint().as_bytes(), fingerprint.as_bytes())) {
+            return Ok(());
+        }
+        Err(CryptoError::UntrustedRecipient)
+    }
+
+    // Refuse a message bigger than the policy allows, before spending memory on it
+    fn check_size(&self, len: usize) -> Result<(), CryptoError> {
+        let limit = self.policy.max_message_bytes;
+        if len > limit {
+            return Err(CryptoError::TooLarge(limit));
+        }
+        Ok(())
+    }
+
+    // Refuse secret-key work once the idle lock has engaged or is due, otherwise count it as activity
+    fn check_unlocked(&self) -> bool {
+        if self.is_locked() {
+            return false;
+        }
+        let now = self.now_millis();
+        let mut last = self.last_activity.slot();
+        let idle = now.saturating_sub(last.unwrap_or(now));
+        if self.idle_timeout.is_some_and(|timeout| idle >= timeout.as_millis() as u64) {
+            return false;
+        }
+        *last = Some(now);
+        true
+    }
+
+    // Wipe every user's secret keys if none has been used for idle_timeout, returning whether it did.
+    // The GUI calls this every frame; the wiped users stay listed in locked_users until unlock.
+    pub fn lock_if_idle(&mut self) -> bool {
+        let Some(timeout) = self.idle_timeout else {
+            return false;
+        };
+        let now = self.now_millis();
+        let idle = now.saturating_sub(*self.last_activity.slot().get_or_insert(now));
+        if self.is_locked() || idle < timeout.as_millis() as u64 {
+            return false;
+        }
+        self.lock();
+        true
+    }
+
+    // Wipe every user's secret keys now. Users not saved to a keystore are gone for good.
+    pub fn lock(&mut self) {
+        let users = std::mem::take(&mut self.users);
+        let public = users.iter().map(|(name, user)| (name.clone(), user.contact())).collect();
+        // Dropping a User zeroizes its keys
+        drop(users);
+        self.locked = Some(public);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.is_some()
+    }
+
+    // Names and public keys of the users the lock wiped, for display until unlock
+    pub fn locked_users(&self) -> Option<&HashMap<String, Contact>> {
+        self.locked.as_ref()
+    }
+
+    // Reload the wiped users from their keystore once the passphrase is entered again
+    pub fn unlock(&mut self, keystore: &Path, passphrase: &str) -> Result<(), KeystoreError> {
+        let users = crate::keystore::load(keystore, passphrase)?;
+        self.users.extend(users);
+        self.locked = None;
+        *self.last_activity.slot() = Some(self.now_millis());
+        Ok(())
+    }
+
+    // Owned user or imported contact with this name, users first
+    pub fn recipient(&self, name: &str) -> Option<&dyn RecipientKeys> {
+        match self.users.get(name) {
+            Some(user) => Some(user),
+            None => self.contacts.get(name).map(|contact| contact as &dyn RecipientKeys),
+        }
+    }
+
+    // Generator shared by everything this system encrypts, still usable after a panic like the nonce log
+    fn rng(&self) -> MutexGuard<'_, Box<dyn SecureRng>> {
+        self.rng.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Burn-after-read messages this system has opened; save it and restore it on start
+    pub fn burned(&self) -> MutexGuard<'_, BurnedSet> {
+        self.burned.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Who tried to read which message, and how it went
+    pub fn audit_log(&self) -> MutexGuard<'_, AuditLog> {
+        self.audit.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Public half of the key audit entries are signed with, for AuditLog::verify
+    pub fn audit_key(&self) -> VerifyingKey {
+        self.audit_key.public()
+    }
+
+    // Nonce log, still usable if another thread panicked while holding it
+    fn nonce_log(&self) -> MutexGuard<'_, NonceLog> {
+        let mut log = self.nonces.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        log.prune(self.now_millis().saturating_sub(self.policy.max_age.as_millis() as u64));
+        log
+    }
+
+    // Encrypt a message under a fresh symmetric key, authenticating `aad` alongside it
+    fn seal<R: RngCore + CryptoRng>(&self, data: &[u8], aad: &[u8], csprng: &mut R) -> Result<SealedPayload, CryptoError> {
+        // Generate a random symmetric key
+        let symmetric_key = SymmetricKey::generate(csprng);
+        let (nonce, encrypted_data) = self.seal_under(&symmetric_key, data, aad, csprng)?;
+
+        Ok(SealedPayload {
+            symmetric_key,
+            nonce,
+            encrypted_data,
+        })
+    }
+
+    // Encrypt under an existing key with a nonce never used with it before, returning (nonce, ciphertext)
+    fn seal_under<R: RngCore + CryptoRng>(
+        &self,
+        symmetric_key: &SymmetricKey,
+        data: &[u8],
+        aad: &[u8],
+        csprng: &mut R,
+    ) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        // A repeated nonce under one key breaks GCM, so draw again if it ever happens.
+        // GCM-SIV survives a repeat, but there is no reason to allow one.
+        let key_id = symmetric_key.id(&[]);
+        let mut nonce = Aes256Gcm::generate_nonce(&mut *csprng);
+        while !self.nonce_log().record(key_id, &nonce, self.now_millis()) {
+            nonce = Aes256Gcm::generate_nonce(&mut *csprng);
+        }
+
+        // Encrypt the message with the configured AEAD
+        let encrypted_data = symmetric_key
+            .seal_with(self.cipher, &nonce, Payload { msg: data, aad })
+            .map_err(|_| CryptoError::Encryption)?;
+        Ok((nonce.to_vec(), encrypted_data))
+    }
+
+    // Encrypt and sign a text message
+    pub fn encrypt_message(&self, sender: &User, recipient: &dyn RecipientKeys, message: &str) -> Result<EncryptedMessage, CryptoError> {
+        self.encrypt_at(sender, recipient, message.as_bytes(), Framing::text(), self.now_millis())
+    }
+
+    // Encrypt a text message the recipient can decrypt only once, on any device sharing their burned set
+    pub fn encrypt_burn_after_read(&self, sender: &User, recipient: &dyn RecipientKeys, message: &str) -> Result<EncryptedMessage, CryptoError> {
+        let framing = Framing { burn_after_read: true, ..Framing::text() };
+        self.encrypt_at(sender, recipient, message.as_bytes(), framing, self.now_millis())
+    }
+
+    // Encrypt a text message answering `parent`, whose hash it carries in in_reply_to
+    pub fn encrypt_reply(&self, sender: &User, recipient: &dyn RecipientKeys, message: &str, parent: &EncryptedMessage) -> Result<EncryptedMessage, CryptoError> {
+        let framing = Framing { in_reply_to: Some(parent.message_id()), ..Framing::text() };
+        self.encrypt_at(sender, recipient, message.as_bytes(), framing, self.now_millis())
+    }
+
+    // Encrypt a text message compressed first. Only for text from a single trust context;
+    // see CompressionAlgo for why mixing in attacker-supplied text leaks secrets.
+    pub fn encrypt_message_compressed(
+        &self,
+        sender: &User,
+        recipient: &dyn RecipientKeys,
+        message: &str,
+        compression: CompressionAlgo,
+    ) -> Result<EncryptedMessage, CryptoError> {
+        self.encrypt_at(sender, recipient, message.as_bytes(), Framing { compression, ..Framing::text() }, self.now_millis())
+    }
+
+    // Encrypt and sign arbitrary binary data such as a file attachment
+    pub fn encrypt_bytes(&self, sender: &User, recipient: &dyn RecipientKeys, data: &[u8]) -> Result<EncryptedMessage, CryptoError> {
+        self.encrypt_at(sender, recipient, data, Framing::binary(), self.now_millis())
+    }
+
+    // Encrypt and sign a payload stamped with the given send time
+    fn encrypt_at(
+        &self,
+        sender: &User,
+        recipient: &dyn RecipientKeys,
+        data: &[u8],
+        framing: Framing,
+        timestamp: u64,
+    ) -> Result<EncryptedMessage, CryptoError> {
+        // Fingerprints and sizes only; the payload and keys never reach a log
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!(
+            "encrypt_message",
+            sender = %sender.fingerprint(),
+            recipient = %recipient.fingerprint(),
+            size = data.len(),
+            content_type = ?framing.content_type,
+        )
+        .entered();
+        let result = self.encrypt_untraced(sender, recipient, data, framing, timestamp);
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(message) => tracing::info!(message = %to_hex(&message.message_id()), ciphertext_size = message.encrypted_data.len(), "encrypted message"),
+            Err(err) => tracing::error!(error = ?err, "encrypt failed"),
+        }
+        result
+    }
+
+    fn encrypt_untraced(
+        &self,
+        sender: &User,
+        recipient: &dyn RecipientKeys,
+        data: &[u8],
+        framing: Framing,
+        timestamp: u64,
+    ) -> Result<EncryptedMessage, CryptoError> {
+        if !self.check_unlocked() {
+            return Err(CryptoError::Locked);
+        }
+        self.check_trust(recipient)?;
+        self.check_size(data.len())?;
+        let Framing { content_type, compression, burn_after_read, in_reply_to } = framing;
+        let addressed_to = [recipient.fingerprint()];
+        let aad = message_associated_data(&sender.keypair.public(), &addressed_to, timestamp, content_type, in_reply_to.as_ref());
+        let mut rng = self.rng();
+        let padded = Zeroizing::new(pad(self.padding, compress(compression, data)?)?.into_owned());
+        // Padding can grow the payload past what a receiver will parse
+        self.check_size(padded.len())?;
+        let sealed = self.seal(&padded, &aad, &mut *rng)?;
+
+        // Sign the original payload with its timestamp, type and intended recipient, or just what a relay sees
+        let signature = match self.sign_mode {
+            SignMode::OverPlaintext => sender.keypair.sign(&canonical_sign_bytes(&addressed_to, timestamp, content_type, data)),
+            SignMode::OverCiphertext => {
+                sender.keypair.sign(&ciphertext_sign_bytes(timestamp, content_type, &sealed.nonce, &sealed.encrypted_data))
+            }
+        };
+        let (key_exchange, recipient_key_id) = if recipient.delivers_to_identity() {
+            let identity = recipient.verifying_key();
+            let key_id = EncryptionKey::from_ed25519(&identity).ok_or(CryptoError::InvalidKey)?.key_id();
+            (EncryptionKey::wrap_hpke(&identity, &sealed.symmetric_key, &mut *rng)?, key_id)
+        } else {
+            let encryption_key = recipient.encryption_key();
+            (encryption_key.wrap(&sealed.symmetric_key, &mut *rng)?, encryption_key.key_id())
+        };
+
+        let mut message = EncryptedMessage {
+            version: MESSAGE_VERSION,
+            suite: AlgorithmSuite::new(&key_exchange, self.cipher, &sender.keypair.public()),
+            key_exchange,
+            encrypted_data: sealed.encrypted_data,
+            signature,
+            sender_public: sender.keypair.public(),
+            nonce: sealed.nonce,
+            timestamp,
+            content_type,
+            compression,
+            padding: self.padding,
+            burn_after_read,
+            conversation_id: conversation_between(&sender.keypair.public(), &recipient.verifying_key()),
+            sign_mode: self.sign_mode,
+            in_reply_to,
+            recipient_key_id,
+            envelope_signature: signature,   // Placeholder, envelope_bytes doesn't cover it
+        };
+
+        // Sign every transmitted field so nothing can be altered in transit
+        message.envelope_signature = sender.keypair.sign(&message.envelope_bytes());
+        Ok(message)
+    }
+
+    // Forward a received message to a new recipient, signed by `as_user`. The plaintext only lives in a
+    // buffer that is wiped once re-encrypted, never in a String. Reading the original counts as receiving
+    // it, so it is audited, burned if burn-after-read, and can't be forwarded twice.
+    pub fn reencrypt(&self, as_user: &User, message: &EncryptedMessage, new_recipient: &dyn RecipientKeys) -> Result<EncryptedMessage, CryptoError> {
+        let mut plaintext = Zeroizing::new(Vec::new());
+        self.reencrypt_through(as_user, message, new_recipient, &mut plaintext)
+    }
+
+    // reencrypt with the intermediate buffer supplied by the caller, so tests can see it wiped
+    fn reencrypt_through(
+        &self,
+        as_user: &User,
+        message: &EncryptedMessage,
+        new_recipient: &dyn RecipientKeys,
+        plaintext: &mut Zeroizing<Vec<u8>>,
+    ) -> Result<EncryptedMessage, CryptoError> {
+        let check = match message.content_type {
+            ContentType::Text => text_payload,
+            ContentType::Binary => any_payload,
+        };
+        **plaintext = self.decrypt_checked(as_user, message, None, check).map_err(CryptoError::Forward)?;
+        let framing = Framing {
+            content_type: message.content_type,
+            compression: CompressionAlgo::None,
+            burn_after_read: message.burn_after_read,
+            in_reply_to: None,
+        };
+        let forwarded = self.encrypt_at(as_user, new_recipient, plaintext, framing, self.now_millis());
+        plaintext.zeroize();
+        forwarded
+    }
+
+    // Encrypt and sign a message once for several recipients
+    pub fn encrypt_message_multi(&self, sender: &User, recipients: &[&dyn RecipientKeys], message: &str) -> Result<MultiRecipientMessage, CryptoError> {
+        if !self.check_unlocked() {
+            return Err(CryptoError::Locked);
+        }
+        for recipient in recipients {
+            self.check_trust(*recipient)?;
+        }
+        self.check_size(message.len())?;
+        let timestamp = self.now_millis();
+        let mut rng = self.rng();
+        let mut recipient_salt = [0u8; 16];
+        rng.fill_bytes(&mut recipient_salt);
+        let key_ids: HashMap<_, _> = recipients
+            .iter()
+            .map(|recipient| (recipient.encryption_key().blinded_key_id(&recipient_salt), recipient.encryption_key()))
+            .collect();
+        let addressed_to = blinded_recipients(key_ids.keys());
+        let aad = associated_data(&sender.keypair.public(), &addressed_to, timestamp, ContentType::Text);
+        let sealed = self.seal(message.as_bytes(), &aad, &mut *rng)?;
+
+        // Wrap the same symmetric key once per recipient, filed under their blinded key id
+        let mut wrapped_keys = HashMap::with_capacity(key_ids.len());
+        for (key_id, encryption_key) in key_ids {
+            let key_exchange = encryption_key.wrap(&sealed.symmetric_key, &mut *rng)?;
+            wrapped_keys.insert(key_id, WrappedKey { key_exchange });
+        }
+
+        // Sign the original message with its timestamp and the full, blinded recipient set
+        let signature = sender.keypair.sign(&canonical_sign_bytes(&addressed_to, timestamp, ContentType::Text, message.as_bytes()));
+
+        let mut message = MultiRecipientMessage {
+            version: MESSAGE_VERSION,
+            encrypted_data: sealed.encrypted_data,
+            signature,
+            sender_public: sender.keypair.public(),
+            recipient_salt,
+            wrapped_keys,
+            nonce: sealed.nonce,
+            timestamp,
+            content_type: ContentType::Text,
+            cipher: self.cipher,
+            envelope_signature: signature,   // Placeholder, envelope_bytes doesn't cover it
+        };
+        message.envelope_signature = sender.keypair.sign(&message.envelope_bytes());
+        Ok(message)
+    }
+
+    // Encrypt many text messages to one recipient, wrapping a single key for all of them.
+    // Key wrapping dominates the cost of small messages, RSA especially, so this does it once.
+    pub fn encrypt_batch(&self, sender: &User, recipient: &dyn RecipientKeys, messages: &[&str]) -> Result<BatchMessage, CryptoError> {
+        if !self.check_unlocked() {
+            return Err(CryptoError::Locked);
+        }
+        self.check_trust(recipient)?;
+        for message in messages {
+            self.check_size(message.len())?;
+        }
+        let timestamp = self.now_millis();
+        let addressed_to = [recipient.fingerprint()];
+        let aad = associated_data(&sender.keypair.public(), &addressed_to, timestamp, ContentType::Text);
+        let mut rng = self.rng();
+        let symmetric_key = SymmetricKey::generate(&mut *rng);
+        let mut nonce_base = [0u8; 12];
+        rng.fill_bytes(&mut nonce_base);
+
+        // The key is fresh for this batch, so counter nonces can't collide with anything sealed before
+        let mut entries = Vec::with_capacity(messages.len());
+        for (index, message) in messages.iter().enumerate() {
+            let nonce = batch_nonce(&nonce_base, index as u64);
+            let encrypted_data = symmetric_key
+                .seal_with(self.cipher, &nonce, Payload { msg: message.as_bytes(), aad: &aad })
+                .map_err(|_| CryptoError::Encryption)?;
+            entries.push(BatchEntry {
+                encrypted_data,
+                signature: sender.keypair.sign(&canonical_sign_bytes(&addressed_to, timestamp, ContentType::Text, message.as_bytes())),
+            });
+        }
+
+        let mut batch = BatchMessage {
+            version: MESSAGE_VERSION,
+            sender_public: sender.keypair.public(),
+            key_exchange: recipient.encryption_key().wrap(&symmetric_key, &mut *rng)?,
+            recipient_key_id: recipient.encryption_key().key_id(),
+            entries,
+            timestamp,
+            cipher: self.cipher,
+            nonce_base,
+            envelope_signature: sender.keypair.sign(&[]),   // Placeholder, envelope_bytes doesn't cover it
+        };
+        batch.envelope_signature = sender.keypair.sign(&batch.envelope_bytes());
+        Ok(batch)
+    }
+
+    // Encrypt a text message that carries no sender identity; see AnonymousMessage for what that gives up
+    pub fn encrypt_anonymous(&self, recipient: &dyn RecipientKeys, message: &str) -> Result<AnonymousMessage, CryptoError> {
+        self.check_trust(recipient)?;
+        self.check_size(message.len())?;
+        let mut rng = self.rng();
+        let symmetric_key = SymmetricKey::generate(&mut *rng);
+        let mut anonymous = AnonymousMessage {
+            version: MESSAGE_VERSION,
+            encrypted_data: Vec::new(),
+            key_exchange: recipient.encryption_key().wrap(&symmetric_key, &mut *rng)?,
+            nonce: Vec::new(),
+            timestamp: self.now_millis(),
+            content_type: ContentType::Text,
+            cipher: self.cipher,
+        };
+
+        // The tag covers the recipient too, so the message can't be passed off as meant for someone else
+        let aad = anonymous_associated_data(&anonymous, &recipient.fingerprint());
+        let (nonce, encrypted_data) = self.seal_under(&symmetric_key, message.as_bytes(), &aad, &mut *rng)?;
+        anonymous.nonce = nonce;
+        anonymous.encrypted_data = encrypted_data;
+        Ok(anonymous)
+    }
+
+    // Decrypt an anonymous message. Success means it is intact and meant for us, not who sent it.
+    pub fn decrypt_anonymous(&self, recipient: &User, message: &AnonymousMessage) -> Result<String, DecryptError> {
+        check_version(message.version)?;
+        if message.nonce.len() != 12 {
+            return Err(DecryptError::CorruptCiphertext);
+        }
+
+        for (fingerprint, decryption_key) in recipient.decryption_keys() {
+            let symmetric_key = match self.unwrap_fresh(decryption_key, &message.key_exchange, message.timestamp) {
+                Err(DecryptError::WrongRecipient) => continue,
+                result => result?,
+            };
+            let aad = anonymous_associated_data(message, &fingerprint);
+            let data = symmetric_key
+                .open_with(message.cipher, &message.nonce, Payload { msg: &message.encrypted_data, aad: &aad })
+                .map_err(|_| DecryptError::CorruptCiphertext)?;
+            text_payload(&data)?;
+            if !self.nonce_log().record(symmetric_key.id(fingerprint.as_bytes()), &message.nonce, message.timestamp) {
+                return Err(DecryptError::NonceReused);
+            }
+            return String::from_utf8(data).map_err(|_| DecryptError::MalformedUtf8);
+        }
+        Err(DecryptError::WrongRecipient)
+    }
+
+    // Decrypt every message of a batch, unwrapping the shared key once.
+    // Anything wrong with the batch as a whole is reported against every entry.
+    pub fn decrypt_batch(&self, recipient: &User, batch: &BatchMessage) -> Vec<Result<String, DecryptError>> {
+        let opened = check_version(batch.version)
+            .and_then(|()| self.revocations.check(&batch.sender_public))
+            .and_then(|()| verify_envelope(&batch.sender_public, &batch.envelope_bytes(), &batch.envelope_signature))
+            .and_then(|()| {
+                recipient
+                    .decryption_keys()
+                    .find_map(|(fingerprint, decryption_key)| match self.unwrap_fresh(decryption_key, &batch.key_exchange, batch.timestamp) {
+                        Err(DecryptError::WrongRecipient) => None,
+                        result => Some(result.map(|symmetric_key| (fingerprint, symmetric_key))),
+                    })
+                    .unwrap_or(Err(DecryptError::WrongRecipient))
+            });
+        let (fingerprint, symmetric_key) = match opened {
+            Ok(opened) => opened,
+            Err(err) => return vec![Err(err); batch.entries.len()],
+        };
+
+        (0..batch.entries.len())
+            .map(|index| {
+                let message = batch.entry(index).ok_or(DecryptError::CorruptCiphertext)?;
+                let data = self.open(&symmetric_key, &message, std::slice::from_ref(&fingerprint), &fingerprint, text_payload)?;
+                String::from_utf8(data).map_err(|_| DecryptError::MalformedUtf8)
+            })
+            .collect()
+    }
+
+    // Decrypt and verify a text message
+    pub fn decrypt_message(&self, recipient: &User, message: &EncryptedMessage) -> Result<String, DecryptError> {
+        let data = self.decrypt_checked(recipient, message, None, text_payload)?;
+        String::from_utf8(data).map_err(|_| DecryptError::MalformedUtf8)
+    }
+
+    // Move the key of an unread message sent to one of `recipient`'s retired keys onto their
+    // current key, so the retired key can be dropped. The message isn't read, so it can still be
+    // received once with decrypt_rewrapped. `previous` is an earlier rewrap of the same message,
+    // from before another rotation. None when the key is already with the current key. Stored
+    // messages are moved however old they are; only the signatures are checked.
+    pub fn rewrap(&self, recipient: &User, message: &EncryptedMessage, previous: Option<&Rewrap>) -> Result<Option<Rewrap>, CryptoError> {
+        // Identity-key messages follow the signing key, which is never retired
+        if matches!(message.key_exchange, KeyExchange::Hpke { .. }) {
+            return Ok(None);
+        }
+        check_version(message.version).map_err(CryptoError::Forward)?;
+        message.suite.check().map_err(CryptoError::Forward)?;
+        verify_envelope(&message.sender_public, &message.envelope_bytes(), &message.envelope_signature).map_err(CryptoError::Forward)?;
+
+        let current = recipient.fingerprint();
+        let key_exchange = previous.map_or(&message.key_exchange, |previous| &previous.key_exchange);
+        for (fingerprint, decryption_key) in recipient.decryption_keys() {
+            match self.unwrap_stored(decryption_key, key_exchange) {
+                Err(DecryptError::WrongRecipient) => continue,
+                Err(err) => return Err(CryptoError::Forward(err)),
+                Ok(_) if fingerprint == current => return Ok(None),
+                Ok(symmetric_key) => {
+                    let key_exchange = recipient.encryption_key.wrap(&symmetric_key, &mut *self.rng())?;
+                    return Ok(Some(Rewrap {
+                        recipient: recipient.username.clone(),
+                        addressed_to: previous.map_or(fingerprint, |previous| previous.addressed_to.clone()),
+                        key_exchange,
+                    }));
+                }
+            }
+        }
+        Err(CryptoError::Forward(DecryptError::WrongRecipient))
+    }
+
+    // decrypt_message for a message whose key rewrap moved. Every check but freshness is the same,
+    // since the message was already stored, and it counts as the same message for replay and burn-after-read.
+    pub fn decrypt_rewrapped(&self, recipient: &User, message: &EncryptedMessage, rewrap: &Rewrap) -> Result<String, DecryptError> {
+        let data = self.decrypt_checked(recipient, message, Some(rewrap), text_payload)?;
+        String::from_utf8(data).map_err(|_| DecryptError::MalformedUtf8)
+    }
+
+    // Decrypt and verify a text message, leaving the caller to decide what to do if it isn't valid UTF-8
+    pub fn decrypt_message_lossy(&self, recipient: &User, message: &EncryptedMessage) -> Result<DecryptedText, DecryptError> {
+        let bytes = self.decrypt_checked(recipient, message, None, any_payload)?;
+        Ok(DecryptedText { bytes })
+    }
+
+    // Decrypt a text message as whichever of our users holds the key named by its recipient key id,
+    // current or retired, returning that user too. Only that user's attempt is audited; a message
+    // for none of them is refused without one.
+    pub fn decrypt_any(&self, message: &EncryptedMessage) -> Result<(&User, DecryptedText), DecryptError> {
+        let user = self
+            .users
+            .values()
+            .find(|user| user.holds_key(&message.recipient_key_id))
+            .ok_or(DecryptError::WrongRecipient)?;
+        self.decrypt_message_lossy(user, message).map(|text| (user, text))
+    }
+
+    // Whether the message key is wrapped to one of `recipient`'s keys, going by its recipient key id,
+    // for inbox indicators. Nothing is unwrapped, logged, burned or recorded against replays, so a
+    // true can still fail to decrypt. See MessagingService::can_read for rewrapped messages.
+    pub fn can_decrypt(&self, recipient: &User, message: &EncryptedMessage) -> bool {
+        recipient.holds_key(&message.recipient_key_id)
+    }
+
+    // can_decrypt for a party message: whether one of `recipient`'s keys has a wrapped key in it
+    pub fn can_decrypt_multi(&self, recipient: &User, message: &MultiRecipientMessage) -> bool {
+        recipient.decryption_keys().any(|(_, decryption_key)| message.wraps_for(&decryption_key.encryption_key()))
+    }
+
+    // Decrypt and verify a payload without assuming it is text
+    pub fn decrypt_bytes(&self, recipient: &User, message: &EncryptedMessage) -> Result<Vec<u8>, DecryptError> {
+        self.decrypt_checked(recipient, message, None, any_payload)
+    }
+
+    // Confirm who sent a message without being able to read it. The envelope signature covers the
+    // ciphertext and every other transmitted field, so no private key or plaintext is needed.
+    // Under SignMode::OverCiphertext the inner signature is checked too.
+    pub fn verify_signature_only(&self, message: &EncryptedMessage, claimed_sender: &VerifyingKey) -> Result<(), DecryptError> {
+        check_version(message.version)?;
+        self.revocations.check(claimed_sender)?;
+        if message.sender_public != *claimed_sender {
+            return Err(DecryptError::InvalidSignature);
+        }
+        verify_envelope(claimed_sender, &message.envelope_bytes(), &message.envelope_signature)?;
+        match message.sign_mode {
+            SignMode::OverPlaintext => Ok(()),
+            SignMode::OverCiphertext => verify_ciphertext_signature(message),
+        }
+    }
+
+    // Decrypt with every key the recipient holds; `check` must pass before the message counts as received.
+    // The attempt goes in the audit log whatever the outcome.
+    fn decrypt_checked(
+        &self,
+        recipient: &User,
+        message: &EncryptedMessage,
+        rewrap: Option<&Rewrap>,
+        check: fn(&[u8]) -> Result<(), DecryptError>,
+    ) -> Result<Vec<u8>, DecryptError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!(
+            "decrypt_message",
+            recipient = %recipient.fingerprint(),
+            message = %to_hex(&message.message_id()),
+            ciphertext_size = message.encrypted_data.len(),
+        )
+        .entered();
+        let result = self.decrypt_unaudited(recipient, message, rewrap, check);
+        self.audit_log().record(&self.audit_key, self.now_millis(), &recipient.username, &recipient.encryption_key.key_id(), message, &result);
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(plaintext) => tracing::info!(size = plaintext.len(), "decrypted message"),
+            Err(err) => tracing::error!(error = ?err, "decrypt failed"),
+        }
+        result
+    }
+
+    fn decrypt_unaudited(
+        &self,
+        recipient: &User,
+        message: &EncryptedMessage,
+        rewrap: Option<&Rewrap>,
+        check: fn(&[u8]) -> Result<(), DecryptError>,
+    ) -> Result<Vec<u8>, DecryptError> {
+        check_version(message.version)?;
+        // Decline algorithms this build lacks before doing any work with them
+        message.suite.check()?;
+        self.revocations.check(&message.sender_public)?;
+        verify_envelope(&message.sender_public, &message.envelope_bytes(), &message.envelope_signature)?;
+
+        // Messages sent before a key rotation were wrapped to a retired key, and ones to the
+        // bare identity key are read with the signing key's X25519 form
+        let identity_key = match message.key_exchange {
+            KeyExchange::Hpke { .. } => recipient.identity_decryption_key(),
+            _ => None,
+        };
+        let identity_key = identity_key.iter().map(|(fingerprint, decryption_key)| (fingerprint.clone(), decryption_key));
+        // A rewrapped key opens with the current key but stays bound to the fingerprint the sender used
+        let key_exchange = rewrap.map_or(&message.key_exchange, |rewrap| &rewrap.key_exchange);
+        for (fingerprint, decryption_key) in recipient.decryption_keys().chain(identity_key) {
+            let unwrapped = match rewrap {
+                Some(_) => self.unwrap_stored(decryption_key, key_exchange),
+                None => self.unwrap_fresh(decryption_key, key_exchange, message.timestamp),
+            };
+            match unwrapped {
+                Err(DecryptError::WrongRecipient) => continue,
+                Err(err) => return Err(err),
+                Ok(symmetric_key) => {
+                    let fingerprint = rewrap.map_or(fingerprint, |rewrap| rewrap.addressed_to.clone());
+                    // Checked before opening so a burned message's plaintext is never produced again
+                    let burn_id = symmetric_key.id(fingerprint.as_bytes());
+                    if message.burn_after_read && self.burned().contains(&burn_id) {
+                        return Err(DecryptError::AlreadyRead);
+                    }
+                    let data = self.open(&symmetric_key, message, std::slice::from_ref(&fingerprint), &fingerprint, check)?;
+                    if message.burn_after_read {
+                        self.burned().insert(burn_id);
+                    }
+                    return Ok(data);
+                }
+            }
+        }
+        Err(DecryptError::WrongRecipient)
+    }
+
+    fn unwrap_fresh(&self, decryption_key: &DecryptionKey, key_exchange: &KeyExchange, timestamp: u64) -> Result<SymmetricKey, DecryptError> {
+        if !self.check_unlocked() {
+            return Err(DecryptError::Locked);
+        }
+        // Reject stale or implausibly future messages before doing any public-key work
+        self.check_timestamp(timestamp, self.now_millis())?;
+
+        // Recover the symmetric key with the recipient's private key; failure means it was sent to another key
+        decryption_key.unwrap(key_exchange)
+    }
+
+    // unwrap_fresh for a message already in our store, which may be older than the freshness policy allows
+    fn unwrap_stored(&self, decryption_key: &DecryptionKey, key_exchange: &KeyExchange) -> Result<SymmetricKey, DecryptError> {
+        if !self.check_unlocked() {
+            return Err(DecryptError::Locked);
+        }
+        decryption_key.unwrap(key_exchange)
+    }
+
+    // Decrypt a message whose envelope has been verified, checking it was signed for exactly `addressed_to`.
+    // Each recipient key accepts a given key and nonce once, so replays are refused.
+    fn open(
+        &self,
+        symmetric_key: &SymmetricKey,
+        message: &EncryptedMessage,
+        addressed_to: &[String],
+        fingerprint: &str,
+        check: fn(&[u8]) -> Result<(), DecryptError>,
+    ) -> Result<Vec<u8>, DecryptError> {
+        if message.nonce.len() != 12 {
+            return Err(DecryptError::CorruptCiphertext);
+        }
+        if message.sign_mode == SignMode::OverCiphertext {
+            verify_ciphertext_signature(message)?;
+        }
+
+        // Decrypt the message; a different sender, recipient set, time, type or parent fails authentication here
+        let aad = message_associated_data(
+            &message.sender_public,
+            addressed_to,
+            message.timestamp,
+            message.content_type,
+            message.in_reply_to.as_ref(),
+        );
+        let decrypted_data = symmetric_key
+            .open_with(message.suite.aead()?, &message.nonce, Payload { msg: &message.encrypted_data, aad: &aad })
+            .map_err(|_| DecryptError::CorruptCiphertext)?;
+        // Wiped on every way out but the one that hands the plaintext to the caller
+        let mut decrypted_data = decompress(message.compression, unpad(message.padding, Zeroizing::new(decrypted_data))?)?;
+
+        // Verify the signature over the original payload
+        if message.sign_mode == SignMode::OverPlaintext {
+            message
+                .sender_public
+                .verify(
+                    &Zeroizing::new(canonical_sign_bytes(addressed_to, message.timestamp, message.content_type, &decrypted_data)),
+                    &message.signature,
+                )
+                .map_err(|_| DecryptError::InvalidSignature)?;
+        }
+
+        // Only authenticated, usable messages are logged, so a forged copy can't block the real one
+        check(&decrypted_data)?;
+        if !self.nonce_log().record(symmetric_key.id(fingerprint.as_bytes()), &message.nonce, message.timestamp) {
+            return Err(DecryptError::NonceReused);
+        }
+        // Moves the buffer out without copying; it becomes the caller's String as it is
+        Ok(std::mem::take(&mut *decrypted_data))
+    }
+
+    // Check a message timestamp against the freshness policy
+    pub(crate) fn check_timestamp(&self, timestamp: u64, now: u64) -> Result<(), DecryptError> {
+        let max_age = self.policy.max_age.as_millis() as u64;
+        let max_clock_skew = self.policy.max_clock_skew.as_millis() as u64;
+        if timestamp > now.saturating_add(max_clock_skew) {
+            return Err(DecryptError::FutureTimestamp);
+        }
+        if now.saturating_sub(timestamp) > max_age {
+            return Err(DecryptError::Expired);
+        }
+        Ok(())
+    }
+
+    // Decrypt a multi-recipient message using the wrapped key for this recipient
+    pub fn decrypt_multi(&self, recipient: &User, message: &MultiRecipientMessage) -> Result<String, DecryptError> {
+        check_version(message.version)?;
+        self.revocations.check(&message.sender_public)?;
+        verify_envelope(&message.sender_public, &message.envelope_bytes(), &message.envelope_signature)?;
+
+        // One HMAC and scan of the key ids per key we hold, and no public-key work at all if none is addressed
+        let (fingerprint, single, decryption_key) = recipient
+            .decryption_keys()
+            .find_map(|(fingerprint, decryption_key)| {
+                let single = message.for_recipient(&decryption_key.encryption_key())?;
+                Some((fingerprint, single, decryption_key))
+            })
+            .ok_or(DecryptError::WrongRecipient)?;
+        let addressed_to = message.recipients();
+        let symmetric_key = self.unwrap_fresh(decryption_key, &single.key_exchange, single.timestamp)?;
+        let data = self.open(&symmetric_key, &single, &addressed_to, &fingerprint, text_payload)?;
+        String::from_utf8(data).map_err(|_| DecryptError::MalformedUtf8)
+    }
+}
+
+// A system holding fresh users with these names, for unit tests across the crate
+#[cfg(test)]
+pub(crate) fn system_with_users(names: &[&str]) -> SignatureSystem {
+    let mut system = SignatureSystem::default();
+    for name in names {
+        system.create_user(name.to_string()).expect("create user");
+    }
+    system
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::contact::IdentityRecipient;
+    use crate::message::SuiteComponent;
+    use crate::signing::{SignatureAlgorithm, Signer, SigningKey};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    // Re-sign the envelope as the sender would, to exercise the checks behind it
+    fn reseal(message: &mut EncryptedMessage, sender: &User) {
+        message.envelope_signature = sender.keypair.sign(&message.envelope_bytes());
+    }
+
+    fn wrapped_key(message: &mut EncryptedMessage) -> &mut Vec<u8> {
+        match &mut message.key_exchange {
+            KeyExchange::Rsa { wrapped_key }
+            | KeyExchange::X25519 { wrapped_key, .. }
+            | KeyExchange::Hybrid { wrapped_key, .. }
+            | KeyExchange::Hpke { wrapped_key, .. } => wrapped_key,
+        }
+    }
+
+    #[test]
+    fn unsupported_rsa_size_rejected_by_create_user() {
+        let mut system = SignatureSystem {
+            key_scheme: KeyScheme::Rsa,
+            key_config: KeyConfig { rsa_bits: 1024 },
+            ..SignatureSystem::default()
+        };
+        assert_eq!(
+            system.create_user("alice".to_string()),
+            Err(CreateError::Crypto(CryptoError::UnsupportedKeySize(1024)))
+        );
+        assert!(system.users.is_empty());
+    }
+
+    #[test]
+    fn second_create_during_cooldown_rejected() {
+        let mut system = SignatureSystem {
+            create_throttle: CreateThrottle::new(Duration::from_secs(60)),
+            ..SignatureSystem::default()
+        };
+        system.create_user("alice".to_string()).expect("create user");
+        assert_eq!(system.create_user("bob".to_string()), Err(CreateError::Busy));
+        assert!(matches!(system.spawn_user("bob".to_string()), Err(CreateError::Busy)));
+        assert!(!system.users.contains_key("bob"));
+
+        // Without a cooldown, only a creation still running blocks the next
+        let mut throttle = CreateThrottle::default();
+        let ticket = throttle.begin().expect("first");
+        assert!(matches!(throttle.begin(), Err(CreateError::Busy)));
+        drop(ticket);
+        assert!(throttle.begin().is_ok());
+    }
+
+    #[test]
+    fn duplicate_username_keeps_first_user() {
+        let mut system = system_with_users(&["alice"]);
+        let fingerprint = system.users["alice"].fingerprint();
+
+        assert_eq!(system.create_user("alice".to_string()), Err(CreateError::DuplicateUsername));
+        let phrase = crate::generate_mnemonic();
+        assert_eq!(system.create_user_from_mnemonic("alice".to_string(), &phrase), Err(CreateError::DuplicateUsername));
+        assert!(matches!(system.spawn_user("alice".to_string()), Err(CreateError::DuplicateUsername)));
+        assert_eq!(system.users.len(), 1);
+        assert_eq!(system.users["alice"].fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn oaep_round_trip() {
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let encrypted = system.encrypt_message(alice, &bob.contact(), "gg, rematch?").expect("encrypt");
+        assert_eq!(encrypted.version, MESSAGE_VERSION);
+        assert_eq!(system.decrypt_message(bob, &encrypted).expect("decrypt"), "gg, rematch?");
+    }
+
+    #[test]
+    fn users_created_with_either_scheme() {
+        let x25519_users: Vec<User> = (0..2)
+            .map(|i| User::generate(format!("x25519-{}", i), KeyScheme::X25519).expect("generate"))
+            .collect();
+        let rsa_users: Vec<User> = (0..2)
+            .map(|i| User::generate(format!("rsa-{}", i), KeyScheme::Rsa).expect("generate"))
+            .collect();
+
+        // How much faster X25519 is lives in the create_user bench, where timing is the point
+        for (users, scheme) in [(&x25519_users, KeyScheme::X25519), (&rsa_users, KeyScheme::Rsa)] {
+            for user in users {
+                assert_eq!(user.encryption_key.scheme(), scheme);
+                assert_eq!(user.decryption_key.scheme(), scheme);
+            }
+        }
+        assert!(matches!(x25519_users[0].encryption_key, EncryptionKey::X25519(_)));
+        assert!(matches!(rsa_users[0].encryption_key, EncryptionKey::Rsa(_)));
+
+        // Both schemes still interoperate in either direction
+        let system = SignatureSystem::default();
+        for (sender, recipient) in [(&rsa_users[0], &x25519_users[0]), (&x25519_users[1], &rsa_users[1])] {
+            let encrypted = system.encrypt_message(sender, recipient, "gl hf").expect("encrypt");
+            assert_eq!(encrypted.key_exchange.scheme(), recipient.encryption_key.scheme());
+            assert_eq!(system.decrypt_message(recipient, &encrypted).expect("decrypt"), "gl hf");
+        }
+    }
+
+    #[test]
+    fn legacy_pkcs1_message_rejected() {
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let mut encrypted = system.encrypt_message(alice, &bob.contact(), "hello").expect("encrypt");
+        encrypted.version = LEGACY_PKCS1_VERSION;
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::LegacyPadding));
+    }
+
+    #[test]
+    fn corrupted_symmetric_key_fails_decryption() {
+        for scheme in [KeyScheme::X25519, KeyScheme::Rsa] {
+            let mut system = SignatureSystem {
+                key_scheme: scheme,
+                ..SignatureSystem::default()
+            };
+            system.create_user("alice".to_string()).expect("create user");
+            system.create_user("bob".to_string()).expect("create user");
+            let alice = &system.users["alice"];
+            let bob = &system.users["bob"];
+
+            let mut encrypted = system.encrypt_message(alice, &bob.contact(), "hello").expect("encrypt");
+            wrapped_key(&mut encrypted)[0] ^= 0xff;
+            assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::TamperedEnvelope));
+            reseal(&mut encrypted, alice);
+            assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::WrongRecipient));
+
+            wrapped_key(&mut encrypted).truncate(16);
+            reseal(&mut encrypted, alice);
+            assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::WrongRecipient));
+        }
+    }
+
+    #[test]
+    fn multi_recipient_round_trip() {
+        let system = system_with_users(&["alice", "bob", "carol", "dave", "eve"]);
+        let recipients = [&system.users["bob"], &system.users["carol"], &system.users["dave"]];
+
+        let encrypted = system
+            .encrypt_message_multi(&system.users["alice"], &recipients.map(|user| user as &dyn RecipientKeys), "party up at the gate")
+            .expect("encrypt");
+        assert_eq!(encrypted.wrapped_keys.len(), 3);
+
+        for recipient in recipients {
+            assert_eq!(
+                system.decrypt_multi(recipient, &encrypted).expect("decrypt"),
+                "party up at the gate"
+            );
+        }
+        assert_eq!(
+            system.decrypt_multi(&system.users["eve"], &encrypted),
+            Err(DecryptError::WrongRecipient)
+        );
+    }
+
+    #[test]
+    fn timestamp_window_boundaries() {
+        let system = SignatureSystem::default();
+        let now = 1_700_000_000_000;
+        let max_age = DEFAULT_MAX_AGE.as_millis() as u64;
+        let skew = DEFAULT_MAX_CLOCK_SKEW.as_millis() as u64;
+
+        assert_eq!(system.check_timestamp(now, now), Ok(()));
+        assert_eq!(system.check_timestamp(now - max_age, now), Ok(()));
+        assert_eq!(system.check_timestamp(now - max_age - 1, now), Err(DecryptError::Expired));
+        assert_eq!(system.check_timestamp(now + skew, now), Ok(()));
+        assert_eq!(system.check_timestamp(now + skew + 1, now), Err(DecryptError::FutureTimestamp));
+    }
+
+    #[test]
+    fn stale_and_future_messages_rejected() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        system.policy.max_age = Duration::from_secs(60);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+        let now = system.now_millis();
+
+        let stale = system
+            .encrypt_at(alice, &bob.contact(), b"old news", Framing::text(), now - 61_000)
+            .expect("encrypt");
+        assert_eq!(system.decrypt_message(bob, &stale), Err(DecryptError::Expired));
+
+        let recent = system
+            .encrypt_at(alice, &bob.contact(), b"fresh", Framing::text(), now - 30_000)
+            .expect("encrypt");
+        assert_eq!(system.decrypt_message(bob, &recent).expect("decrypt"), "fresh");
+
+        let future = system
+            .encrypt_at(alice, &bob.contact(), b"from tomorrow", Framing::text(), now + 10 * 60_000)
+            .expect("encrypt");
+        assert_eq!(system.decrypt_message(bob, &future), Err(DecryptError::FutureTimestamp));
+    }
+
+    #[test]
+    fn restamped_message_fails_signature() {
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let mut encrypted = system.encrypt_message(alice, &bob.contact(), "hello").expect("encrypt");
+        encrypted.timestamp -= 1;
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::TamperedEnvelope));
+        // The timestamp is associated data, so AES-GCM rejects it before the signature is checked
+        reseal(&mut encrypted, alice);
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::CorruptCiphertext));
+    }
+
+    #[test]
+    fn forwarded_message_fails_verification() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+        let carol = &system.users["carol"];
+
+        // Bob unwraps the key Alice sent him and re-wraps it to Carol
+        let mut forwarded = system.encrypt_message(alice, &bob.contact(), "only for bob").expect("encrypt");
+        let symmetric_key = bob.decryption_key.unwrap(&forwarded.key_exchange).expect("unwrap");
+        forwarded.key_exchange = carol.encryption_key.wrap(&symmetric_key, &mut OsRng).expect("rewrap");
+
+        assert_eq!(system.decrypt_message(carol, &forwarded), Err(DecryptError::TamperedEnvelope));
+        reseal(&mut forwarded, alice);
+        assert_eq!(system.decrypt_message(carol, &forwarded), Err(DecryptError::CorruptCiphertext));
+    }
+
+    #[test]
+    fn fifty_recipient_message_selected_by_blinded_key_id() {
+        let names: Vec<String> = (0..51).map(|i| format!("player{}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let mut system = system_with_users(&names);
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        system.set_clock(clock.clone());
+        let recipients: Vec<&dyn RecipientKeys> = names[1..].iter().map(|name| &system.users[*name] as &dyn RecipientKeys).collect();
+        let encrypted = system.encrypt_message_multi(&system.users["player0"], &recipients, "raid at dawn").expect("encrypt");
+        assert_eq!(encrypted.wrapped_keys.len(), 50);
+
+        let last = &system.users["player50"];
+        assert!(encrypted.wrapped_keys.contains_key(&last.encryption_key.blinded_key_id(&encrypted.recipient_salt)));
+        assert_eq!(system.decrypt_multi(last, &encrypted).expect("decrypt"), "raid at dawn");
+
+        // Once the message is stale, anyone who reaches the unwrap step gets Expired.
+        // The sender's own key id isn't there, so they are turned away before that.
+        clock.advance(system.policy.max_age + Duration::from_millis(1));
+        assert_eq!(system.decrypt_multi(&system.users["player0"], &encrypted), Err(DecryptError::WrongRecipient));
+        assert_eq!(system.decrypt_multi(&system.users["player1"], &encrypted), Err(DecryptError::Expired));
+    }
+
+    #[test]
+    fn recipient_set_not_enumerable_from_message() {
+        let system = system_with_users(&["alice", "bob", "carol", "eve"]);
+        let (bob, carol) = (&system.users["bob"], &system.users["carol"]);
+        let recipients = [bob as &dyn RecipientKeys, carol];
+        let first = system.encrypt_message_multi(&system.users["alice"], &recipients, "for both of you").expect("encrypt");
+        let second = system.encrypt_message_multi(&system.users["alice"], &recipients, "for both of you").expect("encrypt");
+
+        // Each recipient finds their own slot
+        for recipient in [bob, carol] {
+            assert!(first.for_recipient(&recipient.encryption_key).is_some());
+            assert_eq!(system.decrypt_multi(recipient, &first).expect("decrypt"), "for both of you");
+        }
+        assert!(first.for_recipient(&system.users["eve"].encryption_key).is_none());
+
+        // Nothing in the message is a recipient's fingerprint or key id
+        let envelope = first.envelope_bytes();
+        for recipient in [bob, carol] {
+            for needle in [recipient.fingerprint().into_bytes(), recipient.encryption_key.key_id().to_vec()] {
+                assert!(!envelope.windows(needle.len()).any(|window| window == needle.as_slice()));
+            }
+        }
+
+        // Nor can two messages be linked by their recipients
+        assert_ne!(first.recipient_salt, second.recipient_salt);
+        assert!(first.wrapped_keys.keys().all(|key_id| !second.wrapped_keys.contains_key(key_id)));
+    }
+
+    #[test]
+    fn stripped_recipient_set_fails_verification() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let bob = &system.users["bob"];
+        let carol = &system.users["carol"];
+
+        let mut encrypted = system
+            .encrypt_message_multi(&system.users["alice"], &[bob as &dyn RecipientKeys, carol], "for both of you")
+            .expect("encrypt");
+        encrypted.wrapped_keys.remove(&carol.encryption_key.blinded_key_id(&encrypted.recipient_salt));
+        assert_eq!(system.decrypt_multi(bob, &encrypted), Err(DecryptError::TamperedEnvelope));
+        encrypted.envelope_signature = system.users["alice"].keypair.sign(&encrypted.envelope_bytes());
+        assert_eq!(system.decrypt_multi(bob, &encrypted), Err(DecryptError::CorruptCiphertext));
+    }
+
+    #[test]
+    fn wrong_recipient_fails_decryption() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let encrypted = system
+            .encrypt_message(&system.users["alice"], &system.users["bob"].contact(), "hello")
+            .expect("encrypt");
+        assert_eq!(
+            system.decrypt_message(&system.users["carol"], &encrypted),
+            Err(DecryptError::WrongRecipient)
+        );
+    }
+
+    #[test]
+    fn reencrypted_message_reaches_new_recipient() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let (alice, bob, carol) = (&system.users["alice"], &system.users["bob"], &system.users["carol"]);
+        let original = system.encrypt_message(alice, &bob.contact(), "pass it on").expect("encrypt");
+
+        let mut plaintext = Zeroizing::new(Vec::new());
+        let forwarded = system
+            .reencrypt_through(bob, &original, &carol.contact(), &mut plaintext)
+            .expect("reencrypt");
+        assert!(plaintext.is_empty() && plaintext.capacity() > 0);
+
+        assert_eq!(forwarded.sender_public, bob.keypair.public());
+        assert_eq!(system.decrypt_message(carol, &forwarded).expect("decrypt"), "pass it on");
+        assert!(system.verify_signature_only(&forwarded, &bob.keypair.public()).is_ok());
+        assert_eq!(system.decrypt_message(bob, &forwarded), Err(DecryptError::WrongRecipient));
+
+        // Only the addressee can forward, and only once
+        assert_eq!(
+            system.reencrypt(carol, &original, &alice.contact()).err(),
+            Some(CryptoError::Forward(DecryptError::WrongRecipient))
+        );
+        assert_eq!(
+            system.reencrypt(bob, &original, &carol.contact()).err(),
+            Some(CryptoError::Forward(DecryptError::NonceReused))
+        );
+    }
+
+    #[test]
+    fn replayed_message_rejected() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let encrypted = system.encrypt_message(alice, &bob.contact(), "hello").expect("encrypt");
+        assert_eq!(system.decrypt_message(bob, &encrypted).expect("decrypt"), "hello");
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::NonceReused));
+
+        // The same nonce under a shared key is fine once per recipient
+        let recipients = [bob as &dyn RecipientKeys, &system.users["carol"]];
+        let party = system.encrypt_message_multi(alice, &recipients, "gm").expect("encrypt");
+        assert_eq!(system.decrypt_multi(bob, &party).expect("decrypt"), "gm");
+        assert_eq!(system.decrypt_multi(&system.users["carol"], &party).expect("decrypt"), "gm");
+        assert_eq!(system.decrypt_multi(bob, &party), Err(DecryptError::NonceReused));
+    }
+
+    #[test]
+    fn hybrid_message_round_trip() {
+        let mut system = SignatureSystem {
+            key_scheme: KeyScheme::Hybrid,
+            ..SignatureSystem::default()
+        };
+        system.create_user("alice".to_string()).expect("create user");
+        system.create_user("bob".to_string()).expect("create user");
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let encrypted = system.encrypt_message(alice, &bob.contact(), "quantum-safe hello").expect("encrypt");
+        assert!(matches!(encrypted.key_exchange, KeyExchange::Hybrid { .. }));
+        let json = EncryptedMessage::from_json(&encrypted.to_json().expect("json")).expect("json");
+        assert_eq!(json.key_exchange, encrypted.key_exchange);
+        let received = EncryptedMessage::from_base64(&encrypted.to_base64()).expect("wire");
+        assert_eq!(received.key_exchange, encrypted.key_exchange);
+        assert_eq!(system.decrypt_message(bob, &received).expect("decrypt"), "quantum-safe hello");
+
+        // RSA still unwraps its secret, but the ML-KEM one no longer matches so the derived key is wrong
+        let mut corrupted = encrypted;
+        let KeyExchange::Hybrid { kem_ciphertext, .. } = &mut corrupted.key_exchange else {
+            panic!("expected a hybrid key exchange");
+        };
+        kem_ciphertext[0] ^= 0xff;
+        reseal(&mut corrupted, alice);
+        assert_eq!(system.decrypt_message(bob, &corrupted), Err(DecryptError::WrongRecipient));
+    }
+
+    #[test]
+    fn seeded_rng_gives_identical_ciphertexts() {
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha20Rng;
+
+        for scheme in [KeyScheme::X25519, KeyScheme::Rsa] {
+            let encrypt = |seed: u64| {
+                let mut system = SignatureSystem::with_rng(ChaCha20Rng::seed_from_u64(seed));
+                system.key_scheme = scheme;
+                system.create_user("alice".to_string()).expect("create user");
+                system.create_user("bob".to_string()).expect("create user");
+                let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+                system
+                    .encrypt_at(alice, bob, b"test vector", Framing::text(), 1_700_000_000_000)
+                    .expect("encrypt")
+                    .to_base64()
+            };
+            assert_eq!(encrypt(7), encrypt(7));
+            assert_ne!(encrypt(7), encrypt(8));
+        }
+    }
+
+    // Byte-for-byte wire form of one fixed message. A layout change without a MESSAGE_VERSION bump fails
+    // here; after a deliberate bump, run with REGENERATE_WIRE_FIXTURE=1 to write the next version's file.
+    #[test]
+    fn wire_format_matches_golden_fixture() {
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha20Rng;
+
+        let mut system = SignatureSystem::with_rng(ChaCha20Rng::seed_from_u64(81));
+        system.padding = PaddingMode::PowerOfTwo;
+        system.policy.max_age = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+        system.create_user("alice".to_string()).expect("create user");
+        system.create_user("bob".to_string()).expect("create user");
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let framing = Framing { compression: CompressionAlgo::Deflate, in_reply_to: Some([0x81; 32]), ..Framing::text() };
+        let wire = system
+            .encrypt_at(alice, bob, b"golden golden golden", framing, 1_700_000_000_000)
+            .expect("encrypt")
+            .to_wire();
+
+        let path = format!("{}/tests/fixtures/wire-v{}.bin", env!("CARGO_MANIFEST_DIR"), MESSAGE_VERSION);
+        if std::env::var_os("REGENERATE_WIRE_FIXTURE").is_some() {
+            std::fs::write(&path, &wire).expect("write fixture");
+        }
+        let golden = std::fs::read(&path)
+            .unwrap_or_else(|err| panic!("{}: {}; a new MESSAGE_VERSION needs REGENERATE_WIRE_FIXTURE=1", path, err));
+        assert!(wire == golden, "wire format changed without a MESSAGE_VERSION bump");
+
+        // The committed bytes still parse and decrypt
+        let message = EncryptedMessage::from_wire(&golden).expect("parse fixture");
+        assert_eq!(system.decrypt_message(bob, &message).as_deref(), Ok("golden golden golden"));
+    }
+
+    #[test]
+    fn weak_rsa_contact_refused() {
+        use rsa::pkcs1::EncodeRsaPublicKey;
+        use rsa::pkcs8::der::pem::LineEnding;
+        use rsa::RsaPrivateKey;
+
+        let mut system = system_with_users(&["alice"]);
+        let bundle = system.users["alice"].export_public_pem();
+        let end = "-----END PUBLIC KEY-----\n";
+        let signing_block = bundle[..bundle.find(end).expect("ed25519 block") + end.len()].to_string();
+
+        for (name, bits) in [("weak", 1024), ("strong", 2048)] {
+            let private = RsaPrivateKey::new(&mut OsRng, bits).expect("generate");
+            let pem = signing_block.clone() + &private.to_public_key().to_pkcs1_pem(LineEnding::LF).expect("encode");
+            system.add_contact(name.to_string(), &pem).expect("import");
+        }
+
+        let alice = &system.users["alice"];
+        assert_eq!(system.contacts["weak"].encryption.weak_rsa_bits(), Some(1024));
+        assert_eq!(
+            system.encrypt_message(alice, &system.contacts["weak"], "hello").err(),
+            Some(CryptoError::WeakRecipientKey(1024))
+        );
+        assert_eq!(system.contacts["strong"].encryption.weak_rsa_bits(), None);
+        assert!(system.encrypt_message(alice, &system.contacts["strong"], "hello").is_ok());
+    }
+
+    #[test]
+    fn signature_verifies_without_recipient_key() {
+        let sender_side = system_with_users(&["alice", "bob", "mallory"]);
+        let alice = &sender_side.users["alice"];
+        let mallory = &sender_side.users["mallory"];
+        let message = sender_side
+            .encrypt_message(alice, &sender_side.users["bob"].contact(), "raid at nine")
+            .expect("encrypt");
+
+        // A bystander holding none of bob's keys
+        let bystander = SignatureSystem::default();
+        let alice_public = alice.keypair.public();
+        assert_eq!(bystander.verify_signature_only(&message, &alice_public), Ok(()));
+        assert_eq!(
+            bystander.verify_signature_only(&message, &mallory.keypair.public()),
+            Err(DecryptError::InvalidSignature)
+        );
+
+        // Mallory re-signs an altered ciphertext while still claiming to be alice
+        let mut forged = message.clone();
+        forged.encrypted_data[0] ^= 0x01;
+        reseal(&mut forged, mallory);
+        assert_eq!(bystander.verify_signature_only(&forged, &alice_public), Err(DecryptError::TamperedEnvelope));
+
+        let mut altered = message.clone();
+        altered.encrypted_data[0] ^= 0x01;
+        assert_eq!(bystander.verify_signature_only(&altered, &alice_public), Err(DecryptError::TamperedEnvelope));
+    }
+
+    // Stands in for an HSM: signs with a key the rest of the code can't read, counting each use
+    struct CountingSigner {
+        key: SigningKey,
+        uses: Arc<AtomicUsize>,
+    }
+
+    impl Signer for CountingSigner {
+        fn sign(&self, message: &[u8]) -> MessageSignature {
+            self.uses.fetch_add(1, Ordering::SeqCst);
+            self.key.sign(message)
+        }
+
+        fn public(&self) -> VerifyingKey {
+            self.key.public()
+        }
+    }
+
+    #[test]
+    fn external_signer_signs_messages() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        let uses = Arc::new(AtomicUsize::new(0));
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519, &mut OsRng).expect("generate");
+        let public = key.public();
+        system.users.get_mut("alice").expect("alice").keypair = Box::new(CountingSigner { key, uses: uses.clone() });
+
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let message = system.encrypt_message(alice, bob, "gg").expect("encrypt");
+        assert_eq!(message.sender_public, public);
+        assert_eq!(uses.load(Ordering::SeqCst), 2); // Inner and envelope signatures
+        assert_eq!(system.decrypt_message(bob, &message).as_deref(), Ok("gg"));
+        assert_eq!(SignatureSystem::default().verify_signature_only(&message, &public), Ok(()));
+
+        // The key can't be written out, so neither can the user
+        let dir = tempfile::tempdir().expect("tempdir");
+        assert!(matches!(
+            crate::keystore::save(&dir.path().join("users.keystore"), "hunter2", &system.users),
+            Err(KeystoreError::NotExportable)
+        ));
+    }
+
+    #[test]
+    fn reply_names_parent_under_authentication() {
+        let system = system_with_users(&["alice", "bob"]);
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let question = system.encrypt_message(alice, bob, "lost my sword").expect("encrypt");
+        let reply = system.encrypt_reply(bob, alice, "check the forge", &question).expect("encrypt");
+        assert_eq!(reply.in_reply_to, Some(question.message_id()));
+
+        let parsed = EncryptedMessage::from_wire(&reply.to_wire()).expect("parse");
+        assert_eq!(parsed.in_reply_to, reply.in_reply_to);
+        let parsed = EncryptedMessage::from_json(&reply.to_json().expect("json")).expect("parse");
+        assert_eq!(parsed.in_reply_to, reply.in_reply_to);
+
+        // Re-pointing the reply fails even with the envelope signed again to match
+        let mut moved = reply.clone();
+        moved.in_reply_to.as_mut().expect("parent")[0] ^= 0x01;
+        reseal(&mut moved, bob);
+        assert_eq!(system.decrypt_message(alice, &moved), Err(DecryptError::CorruptCiphertext));
+        let mut detached = reply.clone();
+        detached.in_reply_to = None;
+        reseal(&mut detached, bob);
+        assert_eq!(system.decrypt_message(alice, &detached), Err(DecryptError::CorruptCiphertext));
+
+        assert_eq!(system.decrypt_message(alice, &parsed).as_deref(), Ok("check the forge"));
+    }
+
+    #[test]
+    fn each_sign_mode_verifies_for_recipient_and_relay() {
+        for sign_mode in [SignMode::OverPlaintext, SignMode::OverCiphertext] {
+            let mut system = system_with_users(&["alice", "bob"]);
+            system.sign_mode = sign_mode;
+            let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+            let sent = system.encrypt_message(alice, bob, "raid at nine").expect("encrypt");
+            let message = EncryptedMessage::from_wire(&sent.to_wire()).expect("parse");
+            assert_eq!(message.sign_mode, sign_mode);
+
+            let relay = SignatureSystem::default();
+            assert_eq!(relay.verify_signature_only(&message, &alice.keypair.public()), Ok(()));
+            assert_eq!(system.decrypt_message(bob, &message).as_deref(), Ok("raid at nine"));
+        }
+
+        // A relay holding no keys catches a bad ciphertext signature even inside a valid envelope
+        let mut system = system_with_users(&["alice", "bob"]);
+        system.sign_mode = SignMode::OverCiphertext;
+        let alice = &system.users["alice"];
+        let mut message = system.encrypt_message(alice, &system.users["bob"], "raid at nine").expect("encrypt");
+        message.signature = alice.keypair.sign(b"something else");
+        reseal(&mut message, alice);
+        let relay = SignatureSystem::default();
+        assert_eq!(relay.verify_signature_only(&message, &alice.keypair.public()), Err(DecryptError::InvalidSignature));
+        assert_eq!(system.decrypt_message(&system.users["bob"], &message), Err(DecryptError::InvalidSignature));
+
+        // Nor can the tag be flipped to make a plaintext signature pass as a ciphertext one
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let mut message = system.encrypt_message(alice, &system.users["bob"], "raid at nine").expect("encrypt");
+        message.sign_mode = SignMode::OverCiphertext;
+        reseal(&mut message, alice);
+        assert_eq!(relay.verify_signature_only(&message, &alice.keypair.public()), Err(DecryptError::InvalidSignature));
+    }
+
+    #[test]
+    fn batch_wraps_one_key_for_many_messages() {
+        let mut system = SignatureSystem {
+            key_scheme: KeyScheme::Rsa,
+            ..SignatureSystem::default()
+        };
+        for name in ["alice", "bob", "carol"] {
+            system.create_user(name.to_string()).expect("create user");
+        }
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let messages: Vec<String> = (0..100).map(|i| format!("move {}", i)).collect();
+        let messages: Vec<&str> = messages.iter().map(String::as_str).collect();
+        let batch = system.encrypt_batch(alice, bob, &messages).expect("encrypt");
+
+        // One RSA wrap for the whole batch, where the per-message path does one per message
+        assert_eq!(batch.entries.len(), 100);
+        assert!(matches!(batch.key_exchange, KeyExchange::Rsa { .. }));
+        let nonces: std::collections::HashSet<_> = (0..100).map(|index| batch.entry(index).expect("entry").nonce).collect();
+        assert_eq!(nonces.len(), 100);
+
+        let decrypted = system.decrypt_batch(bob, &batch);
+        assert_eq!(decrypted.len(), 100);
+        for (result, expected) in decrypted.into_iter().zip(&messages) {
+            assert_eq!(result.expect("decrypt"), *expected);
+        }
+
+        // Batch-wide failures show up on every entry
+        assert!(system
+            .decrypt_batch(&system.users["carol"], &batch)
+            .into_iter()
+            .all(|result| result == Err(DecryptError::WrongRecipient)));
+        assert!(system.decrypt_batch(bob, &batch).into_iter().all(|result| result == Err(DecryptError::NonceReused)));
+        let mut reordered = batch.clone();
+        reordered.entries.swap(0, 1);
+        assert!(system
+            .decrypt_batch(bob, &reordered)
+            .into_iter()
+            .all(|result| result == Err(DecryptError::TamperedEnvelope)));
+    }
+
+    #[test]
+    fn batch_nonces_are_unique_counters() {
+        // A million-entry batch would take minutes to sign, so check its nonces directly
+        let mut base = [0u8; 12];
+        OsRng.fill_bytes(&mut base);
+        let nonces: std::collections::HashSet<_> = (0..1_000_000).map(|index| batch_nonce(&base, index)).collect();
+        assert_eq!(nonces.len(), 1_000_000);
+
+        let system = system_with_users(&["alice", "bob"]);
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let messages: Vec<String> = (0..1000).map(|i| format!("tick {}", i)).collect();
+        let messages: Vec<&str> = messages.iter().map(String::as_str).collect();
+        let batch = system.encrypt_batch(alice, bob, &messages).expect("encrypt");
+
+        let decrypted = system.decrypt_batch(bob, &batch);
+        for index in [0, 1, 255, 256, 777, 999] {
+            assert_eq!(batch.entry(index).expect("entry").nonce, batch_nonce(&batch.nonce_base, index as u64));
+            assert_eq!(decrypted[index].as_deref(), Ok(messages[index]));
+        }
+        assert!(decrypted.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn anonymous_message_authenticates_without_sender() {
+        let system = system_with_users(&["bob", "carol"]);
+        let bob = &system.users["bob"];
+        let report = system.encrypt_anonymous(bob, "player 42 is using an aimbot").expect("encrypt");
+
+        // No sender goes in, and the one-off key exchange doesn't even link two reports together
+        let second = system.encrypt_anonymous(bob, "player 42 is using an aimbot").expect("encrypt");
+        let (KeyExchange::X25519 { ephemeral_public: first_key, .. }, KeyExchange::X25519 { ephemeral_public: second_key, .. }) =
+            (&report.key_exchange, &second.key_exchange)
+        else {
+            panic!("expected X25519 key exchanges");
+        };
+        assert_ne!(first_key, second_key);
+
+        let mut restamped = report.clone();
+        restamped.timestamp -= 1;
+        assert_eq!(system.decrypt_anonymous(bob, &restamped), Err(DecryptError::CorruptCiphertext));
+        assert_eq!(system.decrypt_anonymous(&system.users["carol"], &report), Err(DecryptError::WrongRecipient));
+
+        assert_eq!(system.decrypt_anonymous(bob, &report).expect("decrypt"), "player 42 is using an aimbot");
+        assert_eq!(system.decrypt_anonymous(bob, &report), Err(DecryptError::NonceReused));
+    }
+
+    #[test]
+    fn gcm_siv_messages_decrypt() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        system.cipher = Cipher::GcmSiv;
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let encrypted = system.encrypt_message(alice, bob, "misuse resistant").expect("encrypt");
+        assert_eq!(encrypted.suite.aead(), Ok(Cipher::GcmSiv));
+        let batch = system.encrypt_batch(alice, bob, &["one", "two"]).expect("encrypt");
+        assert_eq!(batch.cipher, Cipher::GcmSiv);
+
+        // The tag chooses the cipher on the way in, whatever the reader's own setting
+        let reader = SignatureSystem::default();
+        assert_eq!(reader.decrypt_message(bob, &encrypted).expect("decrypt"), "misuse resistant");
+        let batch: Vec<_> = reader.decrypt_batch(bob, &batch).into_iter().map(|result| result.expect("decrypt")).collect();
+        assert_eq!(batch, ["one", "two"]);
+
+        // Relabelling the ciphertext as plain GCM fails authentication
+        let mut relabelled = system.encrypt_message(alice, bob, "misuse resistant").expect("encrypt");
+        relabelled.suite.cipher = Cipher::Gcm as u8;
+        reseal(&mut relabelled, alice);
+        assert_eq!(reader.decrypt_message(bob, &relabelled), Err(DecryptError::CorruptCiphertext));
+    }
+
+    #[test]
+    fn chacha20_messages_decrypt() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        system.cipher = Cipher::ChaCha20Poly1305;
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let encrypted = system.encrypt_message(alice, bob, "no AES hardware").expect("encrypt");
+        assert_eq!(encrypted.suite.aead(), Ok(Cipher::ChaCha20Poly1305));
+        let encrypted = EncryptedMessage::from_wire(&encrypted.to_wire()).expect("from_wire");
+        let reader = SignatureSystem::default();
+        assert_eq!(reader.decrypt_message(bob, &encrypted).expect("decrypt"), "no AES hardware");
+
+        // The tag is covered by the envelope signature, so it can't be swapped in transit
+        let mut relabelled = system.encrypt_message(alice, bob, "no AES hardware").expect("encrypt");
+        relabelled.suite.cipher = Cipher::Gcm as u8;
+        assert_eq!(reader.decrypt_message(bob, &relabelled), Err(DecryptError::TamperedEnvelope));
+
+        // Even a sender who re-signs the relabelled message can't get it read as AES-GCM
+        reseal(&mut relabelled, alice);
+        assert_eq!(reader.decrypt_message(bob, &relabelled), Err(DecryptError::CorruptCiphertext));
+    }
+
+    #[test]
+    fn unknown_suite_component_named_in_error() {
+        let system = system_with_users(&["alice", "bob"]);
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+
+        // A future cipher id still parses, and decrypting names the cipher as the problem
+        let mut future = system.encrypt_message(alice, bob, "from a newer client").expect("encrypt");
+        future.suite.cipher = 0x7f;
+        reseal(&mut future, alice);
+        let future = EncryptedMessage::from_wire(&future.to_wire()).expect("from_wire");
+        assert_eq!(system.decrypt_message(bob, &future), Err(DecryptError::UnsupportedSuite(SuiteComponent::Cipher)));
+        assert_eq!(
+            DecryptError::UnsupportedSuite(SuiteComponent::Cipher).to_string(),
+            "Message uses an unsupported cipher algorithm"
+        );
+
+        let mut future = system.encrypt_message(alice, bob, "from a newer client").expect("encrypt");
+        future.suite.hash = 9;
+        assert_eq!(system.decrypt_message(bob, &future), Err(DecryptError::UnsupportedSuite(SuiteComponent::Hash)));
+    }
+
+    #[test]
+    fn flipped_ciphertext_byte_reported_as_corrupt() {
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let mut encrypted = system.encrypt_message(alice, &bob.contact(), "hello").expect("encrypt");
+        encrypted.encrypted_data[0] ^= 0x01;
+        reseal(&mut encrypted, alice);
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::CorruptCiphertext));
+    }
+
+    #[test]
+    fn binary_payload_read_as_text_reported_as_malformed() {
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let encrypted = system.encrypt_bytes(alice, &bob.contact(), &[0xff, 0xfe, 0x00]).expect("encrypt");
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::MalformedUtf8));
+        assert_eq!(system.decrypt_bytes(bob, &encrypted).expect("decrypt"), vec![0xff, 0xfe, 0x00]);
+    }
+
+    #[test]
+    fn invalid_utf8_text_surfaced_with_badge() {
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+        let payload = b"gg \xff\xfe wp";
+
+        let encrypted = system
+            .encrypt_at(alice, bob, payload, Framing::text(), system.now_millis())
+            .expect("encrypt");
+        let text = system.decrypt_message_lossy(bob, &encrypted).expect("decrypt");
+        assert_eq!(text.bytes, payload);
+        assert!(!text.is_valid_utf8());
+        assert_eq!(text.lossy(), "gg \u{fffd}\u{fffd} wp");
+        assert_eq!(text.display(), format!("{} gg \u{fffd}\u{fffd} wp", crate::message::INVALID_TEXT_BADGE));
+
+        // Clean text carries no badge
+        let encrypted = system.encrypt_message(alice, bob, "gg wp").expect("encrypt");
+        assert_eq!(system.decrypt_message_lossy(bob, &encrypted).expect("decrypt").display(), "gg wp");
+    }
+
+    #[test]
+    fn canonical_sign_bytes_separate_colliding_fields() {
+        // Run together without lengths, these recipient sets are the same bytes
+        let split_late = ["ab".to_string(), "c".to_string()];
+        let split_early = ["a".to_string(), "bc".to_string()];
+        assert_eq!(split_late.concat(), split_early.concat());
+        assert_ne!(
+            canonical_sign_bytes(&split_late, 7, ContentType::Text, b"gg"),
+            canonical_sign_bytes(&split_early, 7, ContentType::Text, b"gg")
+        );
+
+        // A signature over one never verifies the other
+        let system = system_with_users(&["alice"]);
+        let alice = &system.users["alice"];
+        let signature = alice.keypair.sign(&canonical_sign_bytes(&split_late, 7, ContentType::Text, b"gg"));
+        assert!(alice
+            .keypair
+            .public()
+            .verify(&canonical_sign_bytes(&split_early, 7, ContentType::Text, b"gg"), &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn compressed_log_is_smaller_and_round_trips() {
+        let system = system_with_users(&["alice", "bob"]);
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let log: String = (0..2000).map(|tick| format!("tick {} player=alice pos=(10,20) hp=100\n", tick % 10)).collect();
+
+        let plain = system.encrypt_message(alice, bob, &log).expect("encrypt");
+        let compressed = system.encrypt_message_compressed(alice, bob, &log, CompressionAlgo::Deflate).expect("encrypt");
+        assert_eq!(plain.compression, CompressionAlgo::None);
+        assert_eq!(compressed.compression, CompressionAlgo::Deflate);
+        assert!(compressed.encrypted_data.len() * 20 < plain.encrypted_data.len());
+
+        let restored = EncryptedMessage::from_wire(&compressed.to_wire()).expect("from_wire");
+        assert_eq!(system.decrypt_message(bob, &restored).expect("decrypt"), log);
+
+        // The tag is signed, so it can't be stripped to hand the reader raw DEFLATE
+        let mut stripped = compressed.clone();
+        stripped.compression = CompressionAlgo::None;
+        assert_eq!(system.decrypt_message(bob, &stripped), Err(DecryptError::TamperedEnvelope));
+    }
+
+    #[test]
+    fn bucketed_padding_hides_length() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        system.padding = PaddingMode::Bucket(std::num::NonZeroU32::new(256).expect("non-zero"));
+        let (alice, bob) = (&system.users["alice"], &system.users["bob"]);
+        let long = "a much longer answer that explains exactly where to meet and when ".repeat(3);
+
+        let short_message = system.encrypt_message(alice, bob, "yes").expect("encrypt");
+        let long_message = system.encrypt_message(alice, bob, &long).expect("encrypt");
+        assert_eq!(short_message.encrypted_data.len(), long_message.encrypted_data.len());
+
+        let restored = EncryptedMessage::from_wire(&short_message.to_wire()).expect("from_wire");
+        assert_eq!(restored.padding, system.padding);
+        assert_eq!(system.decrypt_message(bob, &restored).expect("decrypt"), "yes");
+        assert_eq!(system.decrypt_message(bob, &long_message).expect("decrypt"), long);
+        assert_eq!(PaddingMode::PowerOfTwo.padded_len(3), Some(8));
+    }
+
+    #[test]
+    fn binary_blob_round_trip() {
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        // Random bytes are almost never valid UTF-8
+        let mut replay = vec![0u8; 4096];
+        rand::RngCore::fill_bytes(&mut OsRng, &mut replay);
+
+        let encrypted = system.encrypt_bytes(alice, &bob.contact(), &replay).expect("encrypt");
+        assert_eq!(encrypted.content_type, ContentType::Binary);
+        assert_eq!(system.decrypt_bytes(bob, &encrypted).expect("decrypt"), replay);
+    }
+
+    #[test]
+    fn relabelled_content_type_fails_signature() {
+        let system = system_with_users(&["alice", "bob"]);
+        let alice = &system.users["alice"];
+        let bob = &system.users["bob"];
+
+        let mut encrypted = system.encrypt_message(alice, &bob.contact(), "hello").expect("encrypt");
+        assert_eq!(encrypted.content_type, ContentType::Text);
+        encrypted.content_type = ContentType::Binary;
+        assert_eq!(system.decrypt_bytes(bob, &encrypted), Err(DecryptError::TamperedEnvelope));
+        reseal(&mut encrypted, alice);
+        assert_eq!(system.decrypt_bytes(bob, &encrypted), Err(DecryptError::CorruptCiphertext));
+    }
+
+    #[test]
+    fn claimed_sender_bound_into_ciphertext() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let bob = &system.users["bob"];
+        let carol = &system.users["carol"];
+
+        // Carol re-signs Alice's message as her own; the ciphertext still names Alice
+        let mut encrypted = system.encrypt_message(&system.users["alice"], &bob.contact(), "hello").expect("encrypt");
+        encrypted.sender_public = carol.keypair.public();
+        reseal(&mut encrypted, carol);
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::CorruptCiphertext));
+    }
+
+    #[test]
+    fn message_to_retired_key_still_decrypts() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        let encrypted = system
+            .encrypt_message(&system.users["alice"], &system.users["bob"].contact(), "sent before rotation")
+            .expect("encrypt");
+
+        let now = system.now_millis();
+        system.users.get_mut("bob").expect("bob").rotate_keys(now).expect("rotate");
+        let bob = &system.users["bob"];
+        assert_eq!(system.decrypt_message(bob, &encrypted).expect("decrypt"), "sent before rotation");
+
+        // New messages use the fresh key
+        let fresh = system
+            .encrypt_message(&system.users["alice"], &bob.contact(), "after rotation")
+            .expect("encrypt");
+        assert_eq!(system.decrypt_message(bob, &fresh).expect("decrypt"), "after rotation");
+    }
+
+    #[test]
+    fn rewrapped_message_reads_with_only_current_key() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        let encrypted = system
+            .encrypt_message(&system.users["alice"], &system.users["bob"].contact(), "sent before rotation")
+            .expect("encrypt");
+        assert_eq!(system.rewrap(&system.users["bob"], &encrypted, None), Ok(None));
+
+        let now = system.now_millis();
+        system.users.get_mut("bob").expect("bob").rotate_keys(now).expect("rotate");
+        let rewrap = system.rewrap(&system.users["bob"], &encrypted, None).expect("rewrap").expect("moved");
+        system.users.get_mut("bob").expect("bob").retired.clear();
+
+        let bob = &system.users["bob"];
+        assert_eq!(system.decrypt_message(bob, &encrypted), Err(DecryptError::WrongRecipient));
+        assert_eq!(system.decrypt_rewrapped(bob, &encrypted, &rewrap).expect("decrypt"), "sent before rotation");
+        // Still one message as far as replay protection goes
+        assert_eq!(system.decrypt_rewrapped(bob, &encrypted, &rewrap), Err(DecryptError::NonceReused));
+    }
+
+    #[test]
+    fn message_older_than_max_age_still_rewrapped() {
+        let mut system = system_with_users(&["alice", "bob"]);
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        system.set_clock(clock.clone());
+        let encrypted = system
+            .encrypt_message(&system.users["alice"], &system.users["bob"].contact(), "from last season")
+            .expect("encrypt");
+
+        clock.advance(system.policy.max_age + Duration::from_secs(1));
+        assert_eq!(system.decrypt_message(&system.users["bob"], &encrypted), Err(DecryptError::Expired));
+        let now = system.now_millis();
+        system.users.get_mut("bob").expect("bob").rotate_keys(now).expect("rotate");
+        let rewrap = system.rewrap(&system.users["bob"], &encrypted, None).expect("rewrap").expect("moved");
+        system.users.get_mut("bob").expect("bob").retired.clear();
+
+        let bob = &system.users["bob"];
+        assert_eq!(system.decrypt_rewrapped(bob, &encrypted, &rewrap).expect("decrypt"), "from last season");
+    }
+
+    #[test]
+    fn can_decrypt_leaves_no_trace() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let encrypted = system
+            .encrypt_burn_after_read(&system.users["alice"], &system.users["bob"], "for bob only")
+            .expect("encrypt");
+
+        assert!(system.can_decrypt(&system.users["bob"], &encrypted));
+        assert!(!system.can_decrypt(&system.users["carol"], &encrypted));
+        assert!(!system.can_decrypt(&system.users["alice"], &encrypted));
+        assert!(system.audit_log().entries().is_empty());
+
+        // Not burned or logged as received, so the real read still works
+        assert_eq!(system.decrypt_message(&system.users["bob"], &encrypted).expect("decrypt"), "for bob only");
+        assert_eq!(system.audit_log().entries().len(), 1);
+
+        let party = system
+            .encrypt_message_multi(&system.users["alice"], &[&system.users["bob"] as &dyn RecipientKeys, &system.users["carol"]], "for the party")
+            .expect("encrypt");
+        assert!(system.can_decrypt_multi(&system.users["bob"], &party));
+        assert!(system.can_decrypt_multi(&system.users["carol"], &party));
+        assert!(!system.can_decrypt_multi(&system.users["alice"], &party));
+        assert_eq!(system.decrypt_multi(&system.users["carol"], &party).as_deref(), Ok("for the party"));
+    }
+
+    #[test]
+    fn key_material_zeroized_on_drop() {
+        fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+        assert_zeroize_on_drop::<SymmetricKey>();
+        assert_zeroize_on_drop::<Zeroizing<Vec<u8>>>();
+        assert_zeroize_on_drop::<DecryptionKey>();
+
+        // Wiping doesn't get in the way of an ordinary round trip
+        let system = system_with_users(&["alice", "bob"]);
+        let encrypted = system
+            .encrypt_message(&system.users["alice"], &system.users["bob"].contact(), "still works")
+            .expect("encrypt");
+        assert_eq!(system.decrypt_message(&system.users["bob"], &encrypted).expect("decrypt"), "still works");
+    }
+
+    #[test]
+    fn contact_only_recipient_round_trip() {
+        // Bob's private keys live in his own process; Alice only has his PEM bundle
+        let bob_side = system_with_users(&["bob"]);
+        let mut system = system_with_users(&["alice"]);
+        system
+            .add_contact("bob".to_string(), &bob_side.users["bob"].export_public_pem())
+            .expect("add contact");
+
+        let recipient = system.recipient("bob").expect("recipient");
+        assert_eq!(recipient.fingerprint(), bob_side.users["bob"].fingerprint());
+        let encrypted = system
+            .encrypt_message(&system.users["alice"], recipient, "add me as a friend")
+            .expect("encrypt");
+        assert_eq!(
+            bob_side.decrypt_message(&bob_side.users["bob"], &encrypted).expect("decrypt"),
+            "add me as a friend"
+        );
+        assert!(system.recipient("mallory").is_none());
+    }
+
+    #[test]
+    fn identity_key_alone_receives_messages() {
+        // Alice has only Bob's Ed25519 key, not his encryption key
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let recipient = IdentityRecipient::new(system.users["bob"].keypair.public()).expect("identity key");
+        let encrypted = system.encrypt_message(&system.users["alice"], &recipient, "one key is enough").expect("encrypt");
+        assert!(matches!(encrypted.key_exchange, KeyExchange::Hpke { .. }));
+
+        let parsed = EncryptedMessage::from_wire(&encrypted.to_wire()).expect("parse");
+        assert_eq!(system.decrypt_message(&system.users["carol"], &parsed), Err(DecryptError::WrongRecipient));
+        assert_eq!(system.decrypt_message(&system.users["bob"], &parsed).expect("decrypt"), "one key is enough");
+    }
+
+    #[test]
+    fn flipped_nonce_bit_caught_by_envelope() {
+        let system = system_with_users(&["alice", "bob"]);
+        let mut encrypted = system
+            .encrypt_message(&system.users["alice"], &system.users["bob"].contact(), "hello")
+            .expect("encrypt");
+
+        encrypted.nonce[0] ^= 0x01;
+        assert_eq!(
+            system.decrypt_message(&system.users["bob"], &encrypted),
+            Err(DecryptError::TamperedEnvelope)
+        );
+    }
+
+    #[test]
+    fn idle_lock_needs_passphrase_again() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("users.keystore");
+        let mut system = system_with_users(&["alice", "bob"]);
+        crate::keystore::save(&path, "hunter2", &system.users).expect("save");
+        system.idle_timeout = Some(Duration::from_secs(5 * 60));
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        system.set_clock(clock.clone());
+
+        // Used once, then left alone for longer than the timeout
+        system.encrypt_message(&system.users["alice"], &system.users["bob"], "hello").expect("encrypt");
+        clock.advance(Duration::from_secs(4 * 60));
+        assert!(!system.lock_if_idle());
+        clock.advance(Duration::from_secs(2 * 60));
+        assert_eq!(
+            system.encrypt_message(&system.users["alice"], &system.users["bob"], "hello").err(),
+            Some(CryptoError::Locked)
+        );
+        assert!(system.lock_if_idle());
+        assert!(system.users.is_empty());
+        assert!(system.locked_users().expect("locked").contains_key("alice"));
+
+        assert!(matches!(system.unlock(&path, "wrong"), Err(KeystoreError::BadPassphrase)));
+        assert!(system.is_locked());
+        system.unlock(&path, "hunter2").expect("unlock");
+        assert!(!system.is_locked());
+        let encrypted = system
+            .encrypt_message(&system.users["alice"], &system.users["bob"], "hello")
+            .expect("encrypt after unlock");
+        assert_eq!(system.decrypt_message(&system.users["bob"], &encrypted).as_deref(), Ok("hello"));
+        assert!(!system.lock_if_idle());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[traced_test]
+    #[test]
+    fn failed_decrypt_logs_error_without_plaintext() {
+        let system = system_with_users(&["alice", "bob", "carol"]);
+        let encrypted = system
+            .encrypt_message(&system.users["alice"], &system.users["bob"], "meet at the north gate")
+            .expect("encrypt");
+        assert_eq!(system.decrypt_message(&system.users["carol"], &encrypted), Err(DecryptError::WrongRecipient));
+
+        logs_assert(|lines: &[&str]| {
+            match lines.iter().filter(|line| line.contains(" ERROR ") && line.contains("decrypt failed")).count() {
+                1 => Ok(()),
+                count => Err(format!("expected one error-level decrypt event, found {}", count)),
+            }
+        });
+        assert!(logs_contain("WrongRecipient"));
+        assert!(logs_contain(&system.users["carol"].fingerprint()));
+        assert!(!logs_contain("north gate"));
+    }
+}
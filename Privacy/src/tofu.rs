@@ -0,0 +1,125 @@
+use crate::ct::ct_eq;
+use crate::error::TofuWarning;
+use std::collections::HashMap;
+
+// Which recipients SignatureSystem will encrypt to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrustPolicy {
+    #[default]
+    AllowAll,                            // Any key, including one only trusted on first use
+    RequireVerified,                     // Our own users, or contacts whose safety number was compared
+}
+
+// Trust on first use: the fingerprint first seen for each peer name. Anything later that
+// claims the same name with other keys is flagged until the user explicitly accepts it.
+#[derive(Clone, Debug, Default)]
+pub struct TofuStore {
+    seen: HashMap<String, String>,       // Username -> fingerprint
+    verified: HashMap<String, String>,   // Username -> fingerprint whose safety number the user compared
+}
+
+impl TofuStore {
+    // Trust and record a name seen for the first time; refuse a fingerprint that differs from the recorded one.
+    // Call this for imports and for incoming messages whose transport names the sender.
+    pub fn check(&mut self, username: &str, fingerprint: &str) -> Result<(), TofuWarning> {
+        match self.seen.get(username) {
+            Some(known) if !ct_eq(known.as_bytes(), fingerprint.as_bytes()) => Err(TofuWarning::KeyChanged {
+                username: username.to_string(),
+                old: known.clone(),
+                new: fingerprint.to_string(),
+            }),
+            Some(_) => Ok(()),
+            None => {
+                self.seen.insert(username.to_string(), fingerprint.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    // Replace the recorded fingerprint once the user has confirmed the change out of band
+    pub fn accept(&mut self, username: &str, fingerprint: &str) {
+        self.seen.insert(username.to_string(), fingerprint.to_string());
+    }
+
+    pub fn fingerprint(&self, username: &str) -> Option<&str> {
+        self.seen.get(username).map(String::as_str)
+    }
+
+    // Record whether the user compared safety numbers with this peer. The mark belongs to the
+    // fingerprint it was made for, so it lapses by itself once the peer's keys change.
+    pub fn set_verified(&mut self, username: &str, fingerprint: &str, verified: bool) {
+        if verified {
+            self.verified.insert(username.to_string(), fingerprint.to_string());
+        } else {
+            self.verified.remove(username);
+        }
+    }
+
+    pub fn is_verified(&self, username: &str, fingerprint: &str) -> bool {
+        self.verified.get(username).is_some_and(|known| ct_eq(known.as_bytes(), fingerprint.as_bytes()))
+    }
+
+    // Whether any peer was verified with exactly these keys, for callers that only hold the keys
+    pub fn is_fingerprint_verified(&self, fingerprint: &str) -> bool {
+        self.verified.values().any(|known| ct_eq(known.as_bytes(), fingerprint.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{CryptoError, ImportError};
+    use crate::user::User;
+    use crate::{KeyScheme, SignatureSystem};
+
+    #[test]
+    fn changed_contact_key_needs_confirmation() {
+        let mut system = SignatureSystem::default();
+        let first = User::generate("bob".to_string(), KeyScheme::X25519).expect("generate");
+        let second = User::generate("bob".to_string(), KeyScheme::X25519).expect("generate");
+
+        system.add_contact("bob".to_string(), &first.export_public_pem()).expect("first import");
+        system.add_contact("bob".to_string(), &first.export_public_pem()).expect("same keys again");
+        assert_eq!(system.known_peers.fingerprint("bob"), Some(first.fingerprint().as_str()));
+
+        let warning = TofuWarning::KeyChanged {
+            username: "bob".to_string(),
+            old: first.fingerprint(),
+            new: second.fingerprint(),
+        };
+        assert_eq!(system.add_contact("bob".to_string(), &second.export_public_pem()), Err(ImportError::KeyChanged(warning)));
+        assert_eq!(system.contacts["bob"].fingerprint(), first.fingerprint());
+
+        system.accept_contact("bob".to_string(), &second.export_public_pem()).expect("confirmed import");
+        assert_eq!(system.contacts["bob"].fingerprint(), second.fingerprint());
+        assert_eq!(system.add_contact("bob".to_string(), &second.export_public_pem()), Ok(()));
+    }
+
+    #[test]
+    fn verified_mark_lapses_when_keys_change() {
+        let mut store = TofuStore::default();
+        store.set_verified("bob", "aa:bb", true);
+        assert!(store.is_verified("bob", "aa:bb"));
+        assert!(!store.is_verified("bob", "cc:dd"));
+        store.set_verified("bob", "aa:bb", false);
+        assert!(!store.is_verified("bob", "aa:bb"));
+    }
+
+    #[test]
+    fn strict_policy_needs_verified_recipient() {
+        let mut system = SignatureSystem::default();
+        system.trust_policy = TrustPolicy::RequireVerified;
+        system.create_user("alice".to_string()).expect("create alice");
+        let bob = User::generate("bob".to_string(), KeyScheme::X25519).expect("generate");
+        system.add_contact("bob".to_string(), &bob.export_public_pem()).expect("import");
+        let alice = &system.users["alice"];
+
+        assert_eq!(system.encrypt_message(alice, &system.contacts["bob"], "hi").err(), Some(CryptoError::UntrustedRecipient));
+        assert!(system.encrypt_message(alice, alice, "note to self").is_ok());
+
+        system.known_peers.set_verified("bob", &bob.fingerprint(), true);
+        let alice = &system.users["alice"];
+        let encrypted = system.encrypt_message(alice, &system.contacts["bob"], "hi").expect("verified recipient");
+        assert_eq!(system.decrypt_message(&bob, &encrypted).expect("decrypt"), "hi");
+    }
+}
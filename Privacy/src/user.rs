@@ -0,0 +1,368 @@
+use crate::error::{CreateError, CryptoError};
+use crate::keys::{DecryptionKey, EncryptionKey, KeyConfig, KeyId, KeyScheme, MIN_RSA_BITS};
+use crate::mnemonic::seeded_rng;
+use crate::signing::{SignatureAlgorithm, Signer, SigningKey, VerifyingKey};
+use rand::{rngs::OsRng, CryptoRng, RngCore};
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use web_time::Instant;
+use zeroize::Zeroizing;
+
+// Structure to hold user information
+pub struct User {
+    pub username: String,
+    pub keypair: Box<dyn Signer>,        // For signatures, a SigningKey unless it lives in an HSM
+    pub decryption_key: DecryptionKey,   // For encryption
+    pub encryption_key: EncryptionKey,   // Public half of decryption_key
+    pub retired: Vec<RetiredKey>,        // Previous encryption keys, newest last
+}
+
+// An encryption key replaced by `User::rotate_keys`, kept to read older messages
+pub struct RetiredKey {
+    pub fingerprint: String,             // Fingerprint the user had while this key was current
+    pub decryption_key: DecryptionKey,
+    pub retired_at: u64,                 // Unix millis
+}
+
+// Refuses to start creating a user while another is being created or within `cooldown` of the
+// last start, so a held-down button can't queue up unbounded key generation
+#[derive(Default)]
+pub struct CreateThrottle {
+    pub cooldown: Duration,
+    last_started: Option<Instant>,
+    in_flight: Arc<AtomicBool>,
+}
+
+impl CreateThrottle {
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn begin(&mut self) -> Result<CreateTicket, CreateError> {
+        let cooling_down = self.last_started.is_some_and(|started| started.elapsed() < self.cooldown);
+        if cooling_down || self.in_flight.load(Ordering::Acquire) {
+            return Err(CreateError::Busy);
+        }
+        self.in_flight.store(true, Ordering::Release);
+        self.last_started = Some(Instant::now());
+        Ok(CreateTicket(Arc::clone(&self.in_flight)))
+    }
+}
+
+// Held for as long as one creation runs; dropping it lets the next one start
+pub(crate) struct CreateTicket(Arc<AtomicBool>);
+
+impl Drop for CreateTicket {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+// A user whose keys are still being generated on a background thread
+pub struct PendingUser {
+    pub username: String,
+    receiver: Receiver<Result<User, CryptoError>>,
+}
+
+impl PendingUser {
+    // Start generating fresh keys without blocking the caller
+    pub fn spawn(username: String, scheme: KeyScheme, config: KeyConfig) -> Self {
+        let name = username.clone();
+        Self::run(username, move || User::generate_with_config(name, scheme, config))
+    }
+
+    // Start recovering keys from a phrase without blocking the caller
+    pub fn spawn_from_mnemonic(username: String, phrase: String, scheme: KeyScheme, config: KeyConfig) -> Self {
+        let name = username.clone();
+        let phrase = Zeroizing::new(phrase);
+        Self::run(username, move || User::from_mnemonic_with_config(name, &phrase, scheme, config))
+    }
+
+    pub(crate) fn run(username: String, generate: impl FnOnce() -> Result<User, CryptoError> + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // The receiver may have been dropped if the caller gave up waiting
+            let _ = sender.send(generate());
+        });
+        Self { username, receiver }
+    }
+
+    // None while keys are still generating; the result is handed out exactly once
+    pub fn poll(&self) -> Option<Result<User, CryptoError>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(CryptoError::KeyGeneration)),
+        }
+    }
+}
+
+// Generate a signing key and an encryption key for the chosen scheme
+fn generate_keys<R: RngCore + CryptoRng>(csprng: &mut R, scheme: KeyScheme, config: KeyConfig) -> Result<(SigningKey, DecryptionKey), CryptoError> {
+    let keypair = SigningKey::generate(SignatureAlgorithm::Ed25519, csprng).ok_or(CryptoError::KeyGeneration)?;
+    let decryption_key = DecryptionKey::generate(scheme, config, csprng)?;
+    Ok((keypair, decryption_key))
+}
+
+impl User {
+    // Generate a fresh Ed25519 keypair and encryption key for a new user
+    pub fn generate(username: String, scheme: KeyScheme) -> Result<Self, CryptoError> {
+        Self::generate_with_config(username, scheme, KeyConfig::default())
+    }
+
+    pub fn generate_with_config(username: String, scheme: KeyScheme, config: KeyConfig) -> Result<Self, CryptoError> {
+        Self::generate_with_rng(username, scheme, config, &mut OsRng)
+    }
+
+    // Keys drawn from a caller-supplied generator, e.g. a seeded one for reproducible test vectors
+    pub fn generate_with_rng<R: RngCore + CryptoRng>(username: String, scheme: KeyScheme, config: KeyConfig, csprng: &mut R) -> Result<Self, CryptoError> {
+        Ok(Self::with_keys(username, generate_keys(csprng, scheme, config)?))
+    }
+
+    // Recreate the same keys every time from a BIP39 recovery phrase and the same scheme
+    pub fn from_mnemonic(username: String, phrase: &str, scheme: KeyScheme) -> Result<Self, CryptoError> {
+        Self::from_mnemonic_with_config(username, phrase, scheme, KeyConfig::default())
+    }
+
+    // Recovery also needs the same key config; RSA keys of another size are different keys
+    pub fn from_mnemonic_with_config(username: String, phrase: &str, scheme: KeyScheme, config: KeyConfig) -> Result<Self, CryptoError> {
+        Ok(Self::with_keys(username, generate_keys(&mut seeded_rng(phrase)?, scheme, config)?))
+    }
+
+    // Adopt keys made elsewhere, e.g. when migrating from another system; the public halves are
+    // derived here. An RSA key that fails its consistency check or is under MIN_RSA_BITS is refused.
+    pub fn from_keys(username: String, ed25519_secret: [u8; 32], rsa_private: RsaPrivateKey) -> Result<Self, CryptoError> {
+        let ed25519_secret = Zeroizing::new(ed25519_secret);
+        let keypair = SigningKey::from_secret_bytes(SignatureAlgorithm::Ed25519, ed25519_secret.as_ref()).ok_or(CryptoError::InvalidKey)?;
+        rsa_private.validate().map_err(|_| CryptoError::InvalidKey)?;
+        let bits = rsa_private.size() * 8;
+        if bits < MIN_RSA_BITS {
+            return Err(CryptoError::UnsupportedKeySize(bits));
+        }
+        Ok(Self::with_keys(username, (keypair, DecryptionKey::Rsa(Box::new(rsa_private)))))
+    }
+
+    fn with_keys(username: String, (keypair, decryption_key): (SigningKey, DecryptionKey)) -> Self {
+        let encryption_key = decryption_key.encryption_key();
+
+        Self {
+            username,
+            keypair: Box::new(keypair),
+            decryption_key,
+            encryption_key,
+            retired: Vec::new(),
+        }
+    }
+
+    // Replace both keys under the same scheme, keeping the old decryption key so earlier messages stay readable.
+    // `now` is when the old key is retired, normally SignatureSystem::now_millis.
+    // The new signing key is a software one even if the old one was in an HSM.
+    pub fn rotate_keys(&mut self, now: u64) -> Result<(), CryptoError> {
+        let (keypair, decryption_key) = generate_keys(&mut OsRng, self.decryption_key.scheme(), self.decryption_key.config())?;
+        let fingerprint = self.fingerprint();
+
+        self.keypair = Box::new(keypair);
+        self.encryption_key = decryption_key.encryption_key();
+        let old_key = std::mem::replace(&mut self.decryption_key, decryption_key);
+        self.retired.push(RetiredKey {
+            fingerprint,
+            decryption_key: old_key,
+            retired_at: now,
+        });
+        Ok(())
+    }
+
+    // Current key first, then retired keys from newest to oldest
+    pub(crate) fn decryption_keys(&self) -> impl Iterator<Item = (String, &DecryptionKey)> {
+        std::iter::once((self.fingerprint(), &self.decryption_key)).chain(
+            self.retired
+                .iter()
+                .rev()
+                .map(|retired| (retired.fingerprint.clone(), &retired.decryption_key)),
+        )
+    }
+
+    // Whether one of this user's decryption keys, retired ones and the identity key included, has this key id
+    pub fn holds_key(&self, key_id: &KeyId) -> bool {
+        self.decryption_keys().any(|(_, decryption_key)| decryption_key.encryption_key().key_id() == *key_id)
+            || EncryptionKey::from_ed25519(&self.keypair.public()).is_some_and(|identity| identity.key_id() == *key_id)
+    }
+
+    // The signing key as an X25519 decryption key, with the fingerprint messages to it are
+    // addressed under, for reading KeyExchange::Hpke. None when the signer keeps its secret.
+    pub(crate) fn identity_decryption_key(&self) -> Option<(String, DecryptionKey)> {
+        let decryption_key = DecryptionKey::from_ed25519_secret(&self.keypair.secret_bytes()?)?;
+        Some((key_fingerprint(&self.keypair.public(), &decryption_key.encryption_key()), decryption_key))
+    }
+
+    // Stable identifier for this user's public keys, for out-of-band verification
+    pub fn fingerprint(&self) -> String {
+        key_fingerprint(&self.keypair.public(), &self.encryption_key)
+    }
+}
+
+// Names the conversation between two identities. Both sides derive it independently: it hashes
+// the two Ed25519 keys in sorted order, so it's the same whichever party computes it.
+pub type ConversationId = [u8; 32];
+
+// Domain separation for conversation ids
+const CONVERSATION_CONTEXT: &[u8] = b"pgfi-conversation-v1";
+
+pub fn conversation_id(a: &User, b_public: &VerifyingKey) -> ConversationId {
+    conversation_between(&a.keypair.public(), b_public)
+}
+
+pub(crate) fn conversation_between(a: &VerifyingKey, b: &VerifyingKey) -> ConversationId {
+    let (a, b) = (a.to_bytes(), b.to_bytes());
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher = Sha256::new();
+    hasher.update(CONVERSATION_CONTEXT);
+    for key in [first, second] {
+        hasher.update((key.len() as u32).to_be_bytes());
+        hasher.update(key);
+    }
+    hasher.finalize().into()
+}
+
+// SHA-256 over both public keys, first 16 bytes as colon-separated hex
+pub fn key_fingerprint(signing: &VerifyingKey, encryption: &EncryptionKey) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(signing.as_bytes());
+    hasher.update(encryption.fingerprint_bytes());
+    hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn fingerprints_are_stable_and_distinct() {
+        let alice = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate");
+        let bob = User::generate("bob".to_string(), KeyScheme::X25519).expect("generate");
+
+        assert_eq!(alice.fingerprint(), alice.fingerprint());
+        assert_ne!(alice.fingerprint(), bob.fingerprint());
+
+        // 16 bytes rendered as colon-separated hex
+        let fingerprint = alice.fingerprint();
+        assert_eq!(fingerprint.len(), 16 * 3 - 1);
+        assert_eq!(fingerprint.split(':').count(), 16);
+    }
+
+    #[test]
+    fn conversation_id_same_in_both_directions() {
+        let alice = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate");
+        let bob = User::generate("bob".to_string(), KeyScheme::Rsa).expect("generate");
+        let carol = User::generate("carol".to_string(), KeyScheme::X25519).expect("generate");
+
+        let id = conversation_id(&alice, &bob.keypair.public());
+        assert_eq!(conversation_id(&bob, &alice.keypair.public()), id);
+        assert_ne!(conversation_id(&alice, &carol.keypair.public()), id);
+
+        // Messages either way are stamped with it
+        let system = crate::SignatureSystem::default();
+        let to_bob = system.encrypt_message(&alice, &bob.contact(), "gg").expect("encrypt");
+        let to_alice = system.encrypt_message(&bob, &alice, "gg").expect("encrypt");
+        assert_eq!(to_bob.conversation_id, id);
+        assert_eq!(to_alice.conversation_id, id);
+    }
+
+    #[test]
+    fn imported_keys_derive_expected_public_halves() {
+        // RFC 8032 section 7.1, test 1
+        let seed = hex_bytes("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let expected = hex_bytes("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let rsa = crate::keystore::import_rsa_private_pkcs8_pem(include_str!("../tests/fixtures/openssl-rsa-pkcs8.pem"), "openssl-fixture").expect("rsa");
+        let rsa_public = rsa.to_public_key();
+
+        let alice = User::from_keys("alice".to_string(), seed, rsa).expect("from_keys");
+        assert_eq!(alice.keypair.public().as_bytes(), &expected[..]);
+        assert_eq!(alice.encryption_key, EncryptionKey::Rsa(rsa_public));
+
+        // The adopted keys work like generated ones
+        let system = crate::SignatureSystem::default();
+        let bob = User::generate("bob".to_string(), KeyScheme::X25519).expect("generate");
+        let message = system.encrypt_message(&bob, &alice, "welcome over").expect("encrypt");
+        assert_eq!(system.decrypt_message(&alice, &message).expect("decrypt"), "welcome over");
+
+        let weak = RsaPrivateKey::new(&mut OsRng, 1024).expect("rsa");
+        assert_eq!(User::from_keys("mallory".to_string(), seed, weak).err(), Some(CryptoError::UnsupportedKeySize(1024)));
+    }
+
+    fn hex_bytes(hex: &str) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).expect("ascii"), 16).expect("hex");
+        }
+        bytes
+    }
+
+    #[test]
+    fn mnemonic_reproduces_same_keys() {
+        for scheme in [KeyScheme::X25519, KeyScheme::Rsa] {
+            let phrase = crate::mnemonic::generate_mnemonic();
+            let first = User::from_mnemonic("alice".to_string(), &phrase, scheme).expect("derive");
+            let second = User::from_mnemonic("alice".to_string(), &phrase, scheme).expect("derive");
+            assert_eq!(first.keypair.public(), second.keypair.public());
+            assert_eq!(first.encryption_key, second.encryption_key);
+
+            let other = User::from_mnemonic("alice".to_string(), &crate::mnemonic::generate_mnemonic(), scheme).expect("derive");
+            assert_ne!(other.keypair.public(), first.keypair.public());
+            assert_ne!(other.encryption_key, first.encryption_key);
+        }
+    }
+
+    #[test]
+    fn background_generation_yields_usable_keys() {
+        let phrase = crate::mnemonic::generate_mnemonic();
+        let pending = [
+            PendingUser::spawn("alice".to_string(), KeyScheme::Rsa, KeyConfig::default()),
+            PendingUser::spawn_from_mnemonic("bob".to_string(), phrase.clone(), KeyScheme::X25519, KeyConfig::default()),
+        ];
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let mut users = Vec::new();
+        for pending in &pending {
+            let user = loop {
+                if let Some(result) = pending.poll() {
+                    break result.expect("generate");
+                }
+                assert!(Instant::now() < deadline, "key generation never finished");
+                thread::sleep(Duration::from_millis(10));
+            };
+            assert_eq!(user.username, pending.username);
+            assert_eq!(user.encryption_key, user.decryption_key.encryption_key());
+            users.push(user);
+        }
+
+        let recovered = User::from_mnemonic("bob".to_string(), &phrase, KeyScheme::X25519).expect("derive");
+        assert_eq!(users[1].fingerprint(), recovered.fingerprint());
+    }
+
+    #[test]
+    fn rotation_retires_previous_key() {
+        let mut alice = User::generate("alice".to_string(), KeyScheme::X25519).expect("generate");
+        let original = alice.fingerprint();
+
+        alice.rotate_keys(1_000).expect("rotate");
+        assert_ne!(alice.fingerprint(), original);
+        assert_eq!(alice.retired.len(), 1);
+        assert_eq!(alice.retired[0].retired_at, 1_000);
+        assert_eq!(alice.retired[0].fingerprint, original);
+    }
+}
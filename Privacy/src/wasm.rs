@@ -0,0 +1,79 @@
+use crate::contact::import_public_contact;
+use crate::keystore::import_identity;
+use crate::message::EncryptedMessage;
+use crate::system::SignatureSystem;
+use wasm_bindgen::prelude::*;
+
+// Browser front end: users live inside the messenger and are named by username,
+// while public keys and messages cross into JavaScript as base64 text
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Messenger {
+    system: SignatureSystem,
+}
+
+#[wasm_bindgen]
+impl Messenger {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Create an X25519 user and return its compact public key bundle for sharing
+    #[wasm_bindgen(js_name = createUser)]
+    pub fn create_user(&mut self, username: String) -> Result<String, JsError> {
+        self.system.create_user(username.clone())?;
+        self.public_key(&username)
+    }
+
+    #[wasm_bindgen(js_name = createUserFromMnemonic)]
+    pub fn create_user_from_mnemonic(&mut self, username: String, phrase: &str) -> Result<String, JsError> {
+        self.system.create_user_from_mnemonic(username.clone(), phrase)?;
+        self.public_key(&username)
+    }
+
+    // Compact public key bundle, as User::export_public_compact
+    #[wasm_bindgen(js_name = publicKey)]
+    pub fn public_key(&self, username: &str) -> Result<String, JsError> {
+        Ok(self.user(username)?.export_public_compact())
+    }
+
+    // Passphrase-protected secret keys, for keeping in browser storage
+    #[wasm_bindgen(js_name = exportIdentity)]
+    pub fn export_identity(&self, username: &str, passphrase: &str) -> Result<String, JsError> {
+        Ok(self.user(username)?.export_identity(passphrase))
+    }
+
+    // Restore an exported identity, returning its username
+    #[wasm_bindgen(js_name = importIdentity)]
+    pub fn import_identity(&mut self, blob: &str, passphrase: &str) -> Result<String, JsError> {
+        let user = import_identity(blob, passphrase)?;
+        let username = user.username.clone();
+        self.system.users.insert(username.clone(), user);
+        Ok(username)
+    }
+
+    // Encrypt to a recipient's public key bundle; returns the base64 wire message
+    #[wasm_bindgen(js_name = encryptMessage)]
+    pub fn encrypt_message(&self, sender: &str, recipient_public: &str, message: &str) -> Result<String, JsError> {
+        let recipient = import_public_contact(recipient_public)?;
+        let encrypted = self.system.encrypt_message(self.user(sender)?, &recipient, message)?;
+        Ok(encrypted.to_base64())
+    }
+
+    // Decrypt and verify a base64 wire message as one of this messenger's users
+    #[wasm_bindgen(js_name = decryptMessage)]
+    pub fn decrypt_message(&self, recipient: &str, message_base64: &str) -> Result<String, JsError> {
+        let message = EncryptedMessage::from_base64(message_base64)?;
+        Ok(self.system.decrypt_message(self.user(recipient)?, &message)?)
+    }
+}
+
+impl Messenger {
+    fn user(&self, username: &str) -> Result<&crate::user::User, JsError> {
+        self.system
+            .users
+            .get(username)
+            .ok_or_else(|| JsError::new(&format!("no user named {}", username)))
+    }
+}
@@ -0,0 +1,47 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn privacy_cli(keystore: &Path, args: &[&str], stdin: Option<&[u8]>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_privacy-cli"))
+        .arg("--keystore")
+        .arg(keystore)
+        .args(args)
+        .env("PRIVACY_PASSPHRASE", "correct horse")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn privacy-cli");
+    if let Some(input) = stdin {
+        child.stdin.take().expect("stdin").write_all(input).expect("write stdin");
+    }
+    drop(child.stdin.take());
+    child.wait_with_output().expect("wait for privacy-cli")
+}
+
+#[test]
+fn encrypt_output_pipes_into_decrypt() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let keystore = dir.path().join("keystore.bin");
+
+    for name in ["alice", "bob"] {
+        let output = privacy_cli(&keystore, &["gen-user", name], None);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    let encrypted = privacy_cli(
+        &keystore,
+        &["encrypt", "--from", "alice", "--to", "bob", "--message", "server restart at 04:00"],
+        None,
+    );
+    assert!(encrypted.status.success(), "{}", String::from_utf8_lossy(&encrypted.stderr));
+
+    let decrypted = privacy_cli(&keystore, &["decrypt", "--as", "bob"], Some(&encrypted.stdout));
+    assert!(decrypted.status.success(), "{}", String::from_utf8_lossy(&decrypted.stderr));
+    assert_eq!(String::from_utf8_lossy(&decrypted.stdout).trim_end(), "server restart at 04:00");
+
+    // Alice holds the wrong private key for a message addressed to Bob
+    let misdirected = privacy_cli(&keystore, &["decrypt", "--as", "alice"], Some(&encrypted.stdout));
+    assert!(!misdirected.status.success());
+}
@@ -0,0 +1,295 @@
+use digital_signature_system::{import_public_contact, DecryptError, EncryptedMessage, ManualClock, MessagingService, ReceiveOutcome, Received, RecipientKeys, ServiceError, SignatureSystem, WireError};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn system_with_users(names: &[&str]) -> SignatureSystem {
+    let mut system = SignatureSystem::default();
+    for name in names {
+        system.create_user(name.to_string()).expect("create user");
+    }
+    system
+}
+
+// receive_all's per-message outcomes, without the message ids
+fn outcomes(received: Result<Vec<Received>, ServiceError>) -> Vec<Result<ReceiveOutcome, DecryptError>> {
+    received.expect("receive_all").into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+#[test]
+fn message_survives_json_between_two_processes() {
+    let sender_side = system_with_users(&["alice"]);
+    let receiver_side = system_with_users(&["bob"]);
+    let alice = &sender_side.users["alice"];
+    let bob = &receiver_side.users["bob"];
+
+    // Bob shares only his public keys; the message travels as JSON
+    let contact = import_public_contact(&bob.export_public_pem()).expect("import");
+    let json = sender_side
+        .encrypt_message(alice, &contact, "gg, rematch?")
+        .expect("encrypt")
+        .to_json()
+        .expect("to_json");
+
+    let received = EncryptedMessage::from_json(&json).expect("from_json");
+    assert_eq!(received.sender_public, alice.keypair.public());
+    assert_eq!(receiver_side.decrypt_message(bob, &received).expect("decrypt"), "gg, rematch?");
+}
+
+#[test]
+fn party_message_readable_only_by_recipients() {
+    let system = system_with_users(&["alice", "bob", "carol", "eve"]);
+    let recipients = [&system.users["bob"], &system.users["carol"]];
+
+    let encrypted = system
+        .encrypt_message_multi(&system.users["alice"], &recipients.map(|user| user as &dyn RecipientKeys), "raid at dusk")
+        .expect("encrypt");
+    for recipient in recipients {
+        assert_eq!(system.decrypt_multi(recipient, &encrypted).expect("decrypt"), "raid at dusk");
+    }
+    assert_eq!(
+        system.decrypt_multi(&system.users["eve"], &encrypted),
+        Err(DecryptError::WrongRecipient)
+    );
+}
+
+#[test]
+fn party_message_delivered_twice_filed_once() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob", "carol"]));
+    let recipients = [&service.system.users["bob"], &service.system.users["carol"]];
+    let party = service
+        .system
+        .encrypt_message_multi(&service.system.users["alice"], &recipients.map(|user| user as &dyn RecipientKeys), "raid at dusk")
+        .expect("encrypt");
+    service.current_user = Some("bob".to_string());
+
+    assert_eq!(service.receive_multi(&party), Ok(ReceiveOutcome::Stored));
+    assert_eq!(service.receive_multi(&party), Ok(ReceiveOutcome::Duplicate));
+    let history = service.list_history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].message_id, party.message_id());
+}
+
+#[test]
+fn party_messages_marked_read_one_at_a_time() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    service.current_user = Some("bob".to_string());
+    for text in ["first", "second"] {
+        let system = &service.system;
+        let party = system.encrypt_message_multi(&system.users["alice"], &[&system.users["bob"] as &dyn RecipientKeys], text).expect("encrypt");
+        service.receive_multi(&party).expect("receive");
+    }
+    assert_eq!(service.history.unread_count(), 2);
+
+    let id = service.list_history()[0].message_id;
+    assert!(service.history.mark_read(&id));
+    assert_eq!(service.history.unread_count(), 1);
+    assert!(service.list_history()[0].read);
+    assert!(!service.list_history()[1].read);
+}
+
+#[test]
+fn service_send_receive_history() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    let wire = service.send("alice", "bob", "meet at the portal").expect("send").to_wire();
+    assert_eq!(service.receive(&wire), Ok(ReceiveOutcome::Stored));
+    let history = service.list_history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].body, "meet at the portal");
+    assert_eq!(history[0].recipient, "bob");
+
+    // Delivering the same bytes again is recognised and doesn't add a second entry
+    assert_eq!(service.receive(&wire), Ok(ReceiveOutcome::Duplicate));
+    assert_eq!(service.list_history().len(), 1);
+    assert_eq!(service.send("alice", "carol", "hi").err(), Some(ServiceError::UnknownRecipient("carol".to_string())));
+}
+
+#[test]
+fn manual_clock_expires_messages() {
+    let clock = Arc::new(ManualClock::new(1_700_000_000_000));
+    let mut service = MessagingService::with_clock(system_with_users(&["alice", "bob"]), clock.clone());
+    service.message_lifetime = Some(60_000);
+    let wire = service.send("alice", "bob", "gone in a minute").expect("send").to_wire();
+    assert_eq!(EncryptedMessage::from_wire(&wire).expect("parse").timestamp, 1_700_000_000_000);
+    assert_eq!(service.receive(&wire), Ok(ReceiveOutcome::Stored));
+    assert_eq!(service.list_history().len(), 1);
+
+    // Past the lifetime the copy in history is hidden, then purged
+    clock.advance(Duration::from_secs(61));
+    assert!(service.list_history().is_empty());
+    assert_eq!(service.history.purge_expired(), 1);
+
+    // Past the freshness policy a message still in transit is refused as expired
+    let late = service.send("alice", "bob", "too late").expect("send").to_wire();
+    clock.advance(service.system.policy.max_age + Duration::from_secs(1));
+    assert_eq!(service.receive(&late), Err(ServiceError::Decrypt(DecryptError::Expired)));
+}
+
+#[test]
+fn history_follows_a_clock_set_later() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    let clock = Arc::new(ManualClock::new(1_700_000_000_000));
+    service.system.set_clock(clock.clone());
+    service.message_lifetime = Some(60_000);
+    let wire = service.send("alice", "bob", "gone in a minute").expect("send").to_wire();
+    service.receive(&wire).expect("receive");
+
+    clock.advance(Duration::from_secs(61));
+    assert!(service.list_history().is_empty());
+}
+
+#[test]
+fn huge_message_lifetime_keeps_messages() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    service.message_lifetime = Some(u64::MAX);
+    let wire = service.send("alice", "bob", "forever").expect("send").to_wire();
+    assert_eq!(service.receive(&wire), Ok(ReceiveOutcome::Stored));
+    assert_eq!(service.list_history()[0].expires_at, Some(u64::MAX));
+    assert_eq!(service.history.purge_expired(), 0);
+}
+
+#[test]
+fn received_messages_start_unread() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    for text in ["first", "second"] {
+        let wire = service.send("alice", "bob", text).expect("send").to_wire();
+        service.receive(&wire).expect("receive");
+    }
+    assert_eq!(service.history.unread_count(), 2);
+
+    let id = service.list_history()[0].message_id;
+    assert!(service.history.mark_read(&id));
+    assert_eq!(service.history.unread_count(), 1);
+    assert!(service.list_history()[0].read);
+    assert!(!service.list_history()[1].read);
+}
+
+#[test]
+fn rewrapped_inbox_outlives_retired_key() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    service.inbox.push(service.send("alice", "bob", "sent before rotation").expect("send"));
+    service.inbox.push(service.send("bob", "alice", "not for bob").expect("send"));
+    let now = service.system.now_millis();
+    service.system.users.get_mut("bob").expect("bob").rotate_keys(now).expect("rotate");
+
+    assert_eq!(service.rewrap_history("bob"), Ok(1));
+    assert_eq!(service.rewrap_history("bob"), Ok(0));
+    service.system.users.get_mut("bob").expect("bob").retired.clear();
+
+    // The inbox badge counts what receive_all will read, even with the original key gone
+    let bob = &service.system.users["bob"];
+    assert!(!service.system.can_decrypt(bob, &service.inbox[0]));
+    assert!(service.can_read(&service.inbox[0], bob));
+    assert!(!service.can_read(&service.inbox[1], bob));
+
+    assert_eq!(outcomes(service.receive_all("bob")), [Ok(ReceiveOutcome::Stored)]);
+    assert_eq!(service.list_history()[0].body, "sent before rotation");
+    assert_eq!(service.inbox.len(), 1);
+    assert!(service.rewrapped.is_empty());
+}
+
+#[test]
+fn message_to_inactive_identity_still_decrypts() {
+    let mut service = MessagingService::new(system_with_users(&["personal", "guild", "rival"]));
+    service.current_user = Some("personal".to_string());
+
+    let wire = service.send("rival", "guild", "truce at dawn?").expect("send").to_wire();
+    service.receive(&wire).expect("receive");
+    assert_eq!(service.list_history()[0].recipient, "guild");
+    assert_eq!(service.current_user.as_deref(), Some("personal"));
+
+    let message = service.send("rival", "personal", "or not").expect("send");
+    let before = service.system.audit_log().entries().len();
+    let (reader, text) = service.system.decrypt_any(&message).expect("decrypt");
+    assert_eq!((reader.username.as_str(), text.display().as_str()), ("personal", "or not"));
+
+    // One audited attempt by the user holding the key, none by the others
+    let log = service.system.audit_log();
+    assert_eq!(log.entries().len(), before + 1);
+    assert_eq!(log.entries()[before].username, "personal");
+    drop(log);
+
+    // A message for none of our users is refused without writing to the log
+    let outsider = system_with_users(&["outsider"]);
+    let stray = service.system.encrypt_message(&service.system.users["rival"], &outsider.users["outsider"], "wrong door").expect("encrypt");
+    assert_eq!(service.system.decrypt_any(&stray).err(), Some(DecryptError::WrongRecipient));
+    assert_eq!(service.system.audit_log().entries().len(), before + 1);
+}
+
+#[test]
+fn decrypt_all_reports_each_message() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    let first = service.send("alice", "bob", "first").expect("send");
+    let mut corrupt = service.send("alice", "bob", "second").expect("send");
+    corrupt.encrypted_data[0] ^= 1;
+    let third = service.send("alice", "bob", "third").expect("send");
+    let for_alice = service.send("bob", "alice", "not yours").expect("send");
+    service.inbox = vec![first.clone(), corrupt.clone(), third.clone(), for_alice];
+
+    let results = service.decrypt_all(&service.system.users["bob"]);
+    assert_eq!(
+        results,
+        vec![
+            (first.message_id(), Ok("first".to_string())),
+            (corrupt.message_id(), Err(DecryptError::TamperedEnvelope)),
+            (third.message_id(), Ok("third".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn receive_all_files_read_messages() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    let mut corrupt = service.send("alice", "bob", "lost").expect("send");
+    corrupt.encrypted_data[0] ^= 1;
+    service.inbox = vec![
+        service.send("alice", "bob", "kept").expect("send"),
+        corrupt,
+        service.send("bob", "alice", "later").expect("send"),
+    ];
+
+    assert_eq!(outcomes(service.receive_all("bob")), [Ok(ReceiveOutcome::Stored), Err(DecryptError::TamperedEnvelope)]);
+    assert_eq!(service.list_history().iter().map(|stored| stored.body.as_str()).collect::<Vec<_>>(), ["kept"]);
+    assert_eq!(service.inbox.len(), 1);
+    assert_eq!(outcomes(service.receive_all("alice")), [Ok(ReceiveOutcome::Stored)]);
+    assert!(service.inbox.is_empty());
+}
+
+#[test]
+fn receive_all_skips_messages_already_received() {
+    let mut service = MessagingService::new(system_with_users(&["alice", "bob"]));
+    let sent = service.send("alice", "bob", "gg").expect("send");
+    assert_eq!(service.receive_message(&sent), Ok(ReceiveOutcome::Stored));
+
+    // Still in the inbox after being read on its own, and delivered again besides
+    service.inbox = vec![sent.clone(), sent];
+    assert_eq!(outcomes(service.receive_all("bob")), [Ok(ReceiveOutcome::Duplicate), Ok(ReceiveOutcome::Duplicate)]);
+    assert_eq!(service.list_history().len(), 1);
+    assert!(service.inbox.is_empty());
+}
+
+// The flow the GUI drives: create users, pick who to send as, send, then read what arrived.
+// Every failure comes back as a value for the status bar.
+#[test]
+fn gui_flow_smoke_test() {
+    let mut service = MessagingService::default();
+    for name in ["alice", "bob"] {
+        service.system.create_user(name.to_string()).expect("create user");
+    }
+    assert!(service.system.create_user("bob".to_string()).is_err());
+
+    service.current_user = Some("alice".to_string());
+    let sent = service.send("alice", "bob", "gg").expect("send");
+    assert_eq!(service.send("alice", "nobody", "gg").err(), Some(ServiceError::UnknownRecipient("nobody".to_string())));
+
+    service.current_user = Some("bob".to_string());
+    let mut tampered = sent.clone();
+    tampered.timestamp += 1;
+    service.inbox = vec![sent, tampered];
+    assert_eq!(outcomes(service.receive_all("bob")), [Ok(ReceiveOutcome::Stored), Err(DecryptError::TamperedEnvelope)]);
+    assert_eq!(service.list_history()[0].body, "gg");
+    assert_eq!(service.receive(b"not a message").err(), Some(ServiceError::Wire(WireError::BadMagic)));
+
+    service.current_user = None;
+    let file = service.system.encrypt_bytes(&service.system.users["alice"], &service.system.users["bob"], b"map").expect("encrypt");
+    assert_eq!(service.receive_bytes(&file), Err(ServiceError::NotSignedIn));
+}
@@ -0,0 +1,19 @@
+// Browser round trip through the wasm-bindgen wrappers, with randomness from crypto.getRandomValues.
+// Run with `wasm-pack test --headless --chrome -- --test wasm`; the unit tests need a filesystem and threads.
+#![cfg(target_arch = "wasm32")]
+
+use digital_signature_system::Messenger;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn round_trip_in_browser() {
+    let mut messenger = Messenger::new();
+    let _alice = messenger.create_user("alice".to_string()).expect("create alice");
+    let bob = messenger.create_user("bob".to_string()).expect("create bob");
+
+    let encrypted = messenger.encrypt_message("alice", &bob, "gg from the browser").expect("encrypt");
+    assert_eq!(messenger.decrypt_message("bob", &encrypted).expect("decrypt"), "gg from the browser");
+    assert!(messenger.decrypt_message("alice", &encrypted).is_err());
+}
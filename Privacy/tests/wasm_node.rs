@@ -0,0 +1,17 @@
+// The wasm round trip again, hosted in Node for machines without a browser.
+// Run with `wasm-pack test --node -- --test wasm_node`.
+#![cfg(target_arch = "wasm32")]
+
+use digital_signature_system::Messenger;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+fn round_trip_in_node() {
+    let mut messenger = Messenger::new();
+    let _alice = messenger.create_user("alice".to_string()).expect("create alice");
+    let bob = messenger.create_user("bob".to_string()).expect("create bob");
+
+    let encrypted = messenger.encrypt_message("alice", &bob, "gg from node").expect("encrypt");
+    assert_eq!(messenger.decrypt_message("bob", &encrypted).expect("decrypt"), "gg from node");
+    assert!(messenger.decrypt_message("alice", &encrypted).is_err());
+}